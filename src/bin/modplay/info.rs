@@ -0,0 +1,207 @@
+use std::{fmt::Write, io, path::PathBuf};
+
+use clap::Args;
+use pfr::sound::{MiscEffect, Mod, Note, ToneEffect, VolumeEffect};
+
+#[derive(Args)]
+pub struct InfoArgs {
+    file: PathBuf,
+    /// Dump every pattern too.
+    #[arg(long)]
+    patterns: bool,
+    /// Print JSON instead of text.
+    #[arg(long)]
+    json: bool,
+}
+
+/// How a pattern is used by the song.
+struct Usage {
+    positions: Vec<u8>,
+    notes: usize,
+    effects: usize,
+}
+
+pub fn run(args: InfoArgs) -> io::Result<()> {
+    let (module, tag) = crate::load_module(&args.file)?;
+    let out = if args.json {
+        json(&module, &tag, args.patterns)
+    } else {
+        text(&module, &tag, args.patterns)
+    };
+    print!("{out}");
+    Ok(())
+}
+
+fn usage(module: &Mod) -> Vec<Usage> {
+    module
+        .patterns
+        .iter()
+        .enumerate()
+        .map(|(i, pattern)| {
+            let cells = pattern.iter().flatten();
+            Usage {
+                positions: (0..module.positions.len() as u8)
+                    .filter(|&pos| module.positions[pos as usize] as usize == i)
+                    .collect(),
+                notes: cells
+                    .clone()
+                    .filter(|n| n.period.is_some() || n.sample.is_some())
+                    .count(),
+                effects: cells.filter(|n| has_effect(n)).count(),
+            }
+        })
+        .collect()
+}
+
+fn has_effect(note: &Note) -> bool {
+    !matches!(note.tone_effect, ToneEffect::None)
+        || !matches!(note.volume_effect, VolumeEffect::None)
+        || !matches!(note.misc_effect, MiscEffect::None)
+}
+
+/// Finetune as the signed value trackers show.
+fn finetune(x: u8) -> i8 {
+    ((x << 4) as i8) >> 4
+}
+
+/// The samples worth listing: everything but the empty slots.
+fn samples(module: &Mod) -> impl Iterator<Item = (usize, &pfr::sound::Sample)> {
+    module
+        .samples
+        .iter()
+        .enumerate()
+        .skip(1)
+        .filter(|(_, s)| !s.name.is_empty() || !s.data.is_empty())
+}
+
+fn text(module: &Mod, tag: &str, patterns: bool) -> String {
+    let mut out = String::new();
+    writeln!(out, "name     {}", module.name).unwrap();
+    writeln!(out, "format   {tag}").unwrap();
+    writeln!(out, "channels {}", module.channels()).unwrap();
+    writeln!(
+        out,
+        "orders   {} positions, restart at {:02x}",
+        module.positions.len(),
+        module.pos_restart
+    )
+    .unwrap();
+    for chunk in module.positions.chunks(0x10) {
+        out.push_str("        ");
+        for pos in chunk {
+            write!(out, " {pos:02x}").unwrap();
+        }
+        out.push('\n');
+    }
+    writeln!(out, "samples").unwrap();
+    writeln!(out, "   # name                   length  fine  vol  loop").unwrap();
+    for (i, sample) in samples(module) {
+        write!(
+            out,
+            "  {i:02x} {:22} {:6} {:+5}   {:02x}",
+            sample.name,
+            sample.data.len(),
+            finetune(sample.finetune),
+            sample.volume
+        )
+        .unwrap();
+        if let Some((start, len)) = sample.repeat {
+            write!(out, "  {start}+{len}").unwrap();
+        }
+        out.push('\n');
+    }
+    writeln!(out, "patterns").unwrap();
+    for (i, usage) in usage(module).iter().enumerate() {
+        write!(out, "  {i:02x}").unwrap();
+        if usage.positions.is_empty() {
+            write!(out, " unused").unwrap();
+        } else {
+            write!(out, " at").unwrap();
+            for pos in &usage.positions {
+                write!(out, " {pos:02x}").unwrap();
+            }
+        }
+        writeln!(out, ", {} notes, {} effects", usage.notes, usage.effects).unwrap();
+    }
+    if patterns {
+        for (i, pattern) in module.patterns.iter().enumerate() {
+            writeln!(out, "--- PAT {i:02x} ---").unwrap();
+            for (j, row) in pattern.iter().enumerate() {
+                write!(out, "{j:02x}:").unwrap();
+                for note in row {
+                    write!(out, "   {note}").unwrap();
+                }
+                out.push('\n');
+            }
+        }
+    }
+    out
+}
+
+fn json_str(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn json_list<T>(items: impl IntoIterator<Item = T>, f: impl Fn(T) -> String) -> String {
+    let items: Vec<_> = items.into_iter().map(f).collect();
+    format!("[{}]", items.join(", "))
+}
+
+fn json(module: &Mod, tag: &str, patterns: bool) -> String {
+    let mut out = String::from("{\n");
+    writeln!(out, "  \"name\": {},", json_str(&module.name)).unwrap();
+    writeln!(out, "  \"format\": {},", json_str(tag)).unwrap();
+    writeln!(out, "  \"channels\": {},", module.channels()).unwrap();
+    writeln!(out, "  \"restart\": {},", module.pos_restart).unwrap();
+    writeln!(
+        out,
+        "  \"orders\": {},",
+        json_list(&module.positions, |p| p.to_string())
+    )
+    .unwrap();
+    let samples = json_list(samples(module), |(i, s)| {
+        let repeat = match s.repeat {
+            Some((start, len)) => format!("{{\"start\": {start}, \"length\": {len}}}"),
+            None => "null".into(),
+        };
+        let c2spd = s.c2spd.map_or("null".into(), |c| c.to_string());
+        format!(
+            "\n    {{\"index\": {i}, \"name\": {}, \"length\": {}, \"finetune\": {}, \"c2spd\": {c2spd}, \"volume\": {}, \"loop\": {repeat}}}",
+            json_str(&s.name),
+            s.data.len(),
+            finetune(s.finetune),
+            s.volume,
+        )
+    });
+    writeln!(out, "  \"samples\": {samples},").unwrap();
+    let usage = usage(module);
+    let pattern_list = json_list(usage.iter().enumerate(), |(i, u)| {
+        let mut p = format!(
+            "\n    {{\"index\": {i}, \"positions\": {}, \"notes\": {}, \"effects\": {}",
+            json_list(&u.positions, |p| p.to_string()),
+            u.notes,
+            u.effects
+        );
+        if patterns {
+            let rows = json_list(&module.patterns[i], |row| {
+                format!("\n      {}", json_list(row, |n| json_str(&n.to_string())))
+            });
+            write!(p, ", \"rows\": {rows}").unwrap();
+        }
+        p.push('}');
+        p
+    });
+    writeln!(out, "  \"patterns\": {pattern_list}").unwrap();
+    out.push_str("}\n");
+    out
+}
@@ -0,0 +1,384 @@
+mod info;
+mod samples;
+mod script;
+mod tui;
+
+use clap::{error::ErrorKind, CommandFactory, Parser, Subcommand};
+use pfr::sound::{
+    self,
+    analysis::TICKS_PER_SECOND,
+    player::{
+        self, JingleMode, JingleRequest, JingleStart, Player, PlayerConfig, RowFlow, Transition,
+    },
+    sfx::SfxBank,
+    wav, Mod,
+};
+use script::Script;
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, IsTerminal, LineWriter, Write},
+    num::NonZeroU8,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+#[derive(Parser)]
+#[command(name = "modplay", args_conflicts_with_subcommands = true)]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// The module to play: MOD, S3M, XM, or a MOD packed for a game.
+    modfile: Option<PathBuf>,
+    /// Sound effect sample files (MOD, WAV or raw 8-bit) making up the sfx
+    /// bank; the music module samples are used if none are given.
+    #[arg(long)]
+    sfx: Vec<PathBuf>,
+    /// Play without a sound card, throwing the audio away.
+    #[arg(long)]
+    null: bool,
+    /// Run the control calls in a script file instead of taking commands.
+    #[arg(long)]
+    script: Option<PathBuf>,
+    /// Render the script offline into a WAV file instead of playing it.
+    #[arg(long, requires = "script")]
+    wav: Option<PathBuf>,
+    /// Seconds to keep playing after the last command of the script.
+    #[arg(long, default_value_t = 5)]
+    tail: u32,
+    /// Record every control call into a script file, which renders the
+    /// session again bit for bit with --wav.
+    #[arg(long)]
+    record: Option<PathBuf>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Print what is in a module instead of playing it.
+    Info(info::InfoArgs),
+    /// Export or replace the samples of a module.
+    Samples(samples::SamplesArgs),
+}
+
+/// The length of jingle transitions requested from the command line, in ticks.
+const FADE_TICKS: u16 = 50;
+
+fn transition(kind: u32) -> Transition {
+    match kind {
+        0 => Transition::Cut,
+        1 => Transition::FadeOut(FADE_TICKS),
+        2 => Transition::FadeIn(FADE_TICKS),
+        _ => Transition::Crossfade(FADE_TICKS),
+    }
+}
+
+/// Loads a module in any format [`pfr::sound::load_any`] knows, and reports
+/// what does not convert.  Returns the module and the name of its format.
+pub fn load_module(path: &Path) -> io::Result<(Mod, String)> {
+    let (module, format, warnings) = sound::load_any(&mut File::open(path)?)?;
+    for warning in warnings {
+        eprintln!("{}: {warning}", path.display());
+    }
+    Ok((module, format.to_string()))
+}
+
+/// The files of the modules loaded on the player, in the order they were
+/// loaded, so that recorded module loads can name them.
+#[derive(Clone, Default)]
+pub struct Loaded(Arc<Mutex<Vec<PathBuf>>>);
+
+impl Loaded {
+    /// Loads a module file on the player through a transition.
+    pub fn load(&self, player: &mut Player, path: &Path, transition: Transition) -> io::Result<()> {
+        let (module, _) = load_module(path)?;
+        // held across the load, so that the recorder finds the file as soon
+        // as the load is logged
+        let mut paths = self.0.lock().unwrap();
+        player
+            .load_module(module, transition)
+            .map_err(io::Error::other)?;
+        paths.push(path.into());
+        Ok(())
+    }
+}
+
+fn load_sfx(files: &[PathBuf]) -> std::io::Result<SfxBank> {
+    let mut bank = SfxBank::new();
+    for path in files {
+        let mut f = File::open(path)?;
+        let name = path.file_stem().unwrap_or_default().to_string_lossy();
+        let id = match path.extension().and_then(|e| e.to_str()) {
+            Some("wav" | "WAV") => bank.load_wav(&mut f, &name)?,
+            Some("raw" | "RAW") => bank.load_raw(&mut f, &name)?,
+            _ => bank.add_mod(&load_module(path)?.0)?,
+        };
+        println!("SFX {id:02x}: {}", path.display());
+    }
+    Ok(bank)
+}
+
+/// Writes the calls made on the player into a script file as they come in,
+/// until the player state goes away.
+fn record(player: &mut Player, path: &Path, loaded: &Loaded) -> io::Result<thread::JoinHandle<()>> {
+    let mut f = LineWriter::new(File::create(path)?);
+    let log = player.take_call_log().unwrap();
+    let loaded = loaded.clone();
+    Ok(thread::spawn(move || {
+        for rec in log {
+            if rec.dropped != 0 {
+                eprintln!(
+                    "record: {} calls lost before frame {}, the recording will not replay",
+                    rec.dropped, rec.frame
+                );
+            }
+            let line = script::call_line(&rec, &loaded.0.lock().unwrap());
+            if let Err(e) = writeln!(f, "{line}") {
+                eprintln!("record: {e}");
+                return;
+            }
+        }
+    }))
+}
+
+/// Complains about calls lost at the end of a recording.
+fn check_recording(player: &Player) {
+    let dropped = player.dropped_calls();
+    if dropped != 0 {
+        eprintln!("record: {dropped} calls lost, the recording will not replay");
+    }
+}
+
+fn main() -> std::io::Result<()> {
+    let args = Args::parse();
+    match args.command {
+        Some(Command::Info(args)) => return info::run(args),
+        Some(Command::Samples(args)) => return samples::run(args),
+        None => {}
+    }
+    let Some(modfile) = args.modfile else {
+        Args::command()
+            .error(
+                ErrorKind::MissingRequiredArgument,
+                "no module to play given",
+            )
+            .exit();
+    };
+    let (module, _) = load_module(&modfile)?;
+    let sfx = if args.sfx.is_empty() {
+        SfxBank::from_mod(&module)
+    } else {
+        load_sfx(&args.sfx)?
+    };
+    let config = PlayerConfig {
+        record_calls: args.record.is_some(),
+        ..Default::default()
+    };
+    let loaded = Loaded::default();
+    if let Some(path) = &args.script {
+        let script = Script::parse(&fs::read_to_string(path)?).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {e}", path.display()),
+            )
+        })?;
+        let tail = args.tail * TICKS_PER_SECOND;
+        if let Some(wav_path) = &args.wav {
+            let (mut player, mut state) = player::play_offline(module, sfx, config);
+            let recorder = args
+                .record
+                .as_ref()
+                .map(|path| record(&mut player, path, &loaded))
+                .transpose()?;
+            let data: Vec<i16> = script
+                .run_offline(&mut player, &mut state, &loaded, tail)
+                .iter()
+                .map(|&v| (v >> 16).clamp(i16::MIN as i32, i16::MAX as i32) as i16)
+                .collect();
+            let mut f = BufWriter::new(File::create(wav_path)?);
+            wav::write(&mut f, state.sample_rate(), 2, &data)?;
+            drop(state);
+            if let Some(recorder) = recorder {
+                recorder.join().unwrap();
+                check_recording(&player);
+            }
+            return f.flush();
+        }
+        let mut player = if args.null {
+            player::play_null(module, sfx, config)
+        } else {
+            player::play(module, sfx, config).map_err(io::Error::other)?
+        };
+        if let Some(path) = &args.record {
+            record(&mut player, path, &loaded)?;
+        }
+        script.run_live(&mut player, &loaded, tail);
+        for e in player.output_errors() {
+            eprintln!("{e}");
+        }
+        if args.record.is_some() {
+            check_recording(&player);
+        }
+        return Ok(());
+    }
+    // the terminal UI when run interactively, hex commands from stdin when
+    // piped
+    let interactive = io::stdin().is_terminal() && io::stdout().is_terminal();
+    let config = PlayerConfig {
+        trace_rows: !interactive,
+        ..config
+    };
+    let view = module.clone();
+    let sfx_names: Vec<_> = sfx.samples[1..].iter().map(|s| s.name.clone()).collect();
+    let mut player = if args.null {
+        player::play_null(module, sfx, config)
+    } else {
+        player::play(module, sfx, config).map_err(io::Error::other)?
+    };
+    if let Some(path) = &args.record {
+        record(&mut player, path, &loaded)?;
+    }
+    if interactive {
+        tui::run(&player, &view, &sfx_names)?;
+        if args.record.is_some() {
+            check_recording(&player);
+        }
+        return Ok(());
+    }
+    let trace = player.take_row_trace().unwrap();
+    thread::spawn(move || {
+        for t in trace {
+            print!("{:02x}/{:02x}.{:02x}", t.position, t.pattern, t.row);
+            for note in t.notes {
+                print!("   {note}");
+            }
+            println!();
+            match t.flow {
+                RowFlow::Next => {}
+                RowFlow::Jump => println!("---JUMP---"),
+                RowFlow::Break => println!("---BREAK---"),
+                RowFlow::PatternEnd => println!("---"),
+            }
+        }
+    });
+    let stdin = std::io::stdin();
+    loop {
+        let mut buf = String::new();
+        stdin.read_line(&mut buf)?;
+        for e in player.output_errors() {
+            eprintln!("{e}");
+        }
+
+        let c = buf.trim();
+        if let Some(path) = c.strip_prefix('l') {
+            if let Err(e) = loaded.load(&mut player, Path::new(path.trim()), transition(1)) {
+                eprintln!("load: {e}");
+            }
+        }
+        if let Some(r) = c.strip_prefix('j') {
+            let Ok(r) = u32::from_str_radix(r, 16) else {
+                continue;
+            };
+            let res = player.jingle(JingleRequest {
+                position: (r & 0xff) as u8,
+                repeats: (r >> 8 & 0xf) as u8,
+                priority: (r >> 12 & 0x3f) as u8,
+                mode: match r >> 24 & 3 {
+                    0 => JingleMode::Normal,
+                    1 => JingleMode::Force,
+                    _ => JingleMode::Queue,
+                },
+                start: match r >> 18 & 3 {
+                    0 => JingleStart::Now,
+                    1 => JingleStart::NextRow,
+                    2 => NonZeroU8::new((r >> 20 & 0xf) as u8)
+                        .map_or(JingleStart::Now, JingleStart::NextBeat),
+                    _ => JingleStart::NextPattern,
+                },
+                resume: r >> 26 & 1 != 0,
+                transition: transition(r >> 28 & 3),
+                return_transition: transition(r >> 30 & 3),
+            });
+            if let Err(e) = res {
+                eprintln!("jingle: {e}");
+            }
+        }
+        if let Some(r) = c.strip_prefix('o') {
+            let Ok(r) = u16::from_str_radix(r, 16) else {
+                continue;
+            };
+            if let Err(e) = player.fade_out(r) {
+                eprintln!("fade out: {e}");
+            }
+        }
+        if let Some(r) = c.strip_prefix('i') {
+            let Ok(r) = u16::from_str_radix(r, 16) else {
+                continue;
+            };
+            if let Err(e) = player.fade_in(r) {
+                eprintln!("fade in: {e}");
+            }
+        }
+        if let Some(r) = c.strip_prefix('r') {
+            if let Err(e) = player.set_music_resume(r != "0") {
+                eprintln!("music resume: {e}");
+            }
+        }
+        if let Some(r) = c.strip_prefix('m') {
+            let Ok(r) = u32::from_str_radix(r, 16) else {
+                continue;
+            };
+            if let Err(e) = player.set_music_pos((r & 0xff) as u8) {
+                eprintln!("music pos: {e}");
+            }
+        }
+        if let Some(r) = c.strip_prefix('g') {
+            let Ok(r) = u32::from_str_radix(r, 16) else {
+                continue;
+            };
+            if let Err(e) = player.seek((r >> 8 & 0xff) as u8, (r & 0xff) as u8) {
+                eprintln!("seek: {e}");
+            }
+        }
+        if let Some(r) = c.strip_prefix('t') {
+            let Some(r) = r
+                .parse()
+                .ok()
+                .and_then(|r| Duration::try_from_secs_f64(r).ok())
+            else {
+                continue;
+            };
+            if let Err(e) = player.seek_time(r) {
+                eprintln!("seek: {e}");
+            }
+        }
+        // mutes in the low 32 bits, solos in the high 32
+        if let Some(r) = c.strip_prefix('u') {
+            let Ok(r) = u64::from_str_radix(r, 16) else {
+                continue;
+            };
+            for channel in 0..player.channels() as u8 {
+                let res = player
+                    .set_channel_mute(channel, r >> channel & 1 != 0)
+                    .and_then(|_| player.set_channel_solo(channel, r >> (channel + 32) & 1 != 0));
+                if let Err(e) = res {
+                    eprintln!("mute: {e}");
+                }
+            }
+        }
+        if let Some(r) = c.strip_prefix('s') {
+            let Ok(r) = u32::from_str_radix(r, 16) else {
+                continue;
+            };
+            let res = player.play_sfx(
+                (r & 0xff) as u8,
+                (r >> 8 & 0xff) as u8,
+                (r >> 16 & 0xff) as u8,
+                (r >> 24 & 0xff) as u8,
+            );
+            if let Err(e) = res {
+                eprintln!("sfx: {e}");
+            }
+        }
+    }
+}
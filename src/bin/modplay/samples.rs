@@ -0,0 +1,147 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Cursor, Write},
+    path::PathBuf,
+};
+
+use clap::{Args, Subcommand};
+use pfr::sound::{loader, wav};
+
+#[derive(Args)]
+pub struct SamplesArgs {
+    #[command(subcommand)]
+    command: SamplesCommand,
+}
+
+#[derive(Subcommand)]
+enum SamplesCommand {
+    /// Write the samples of a module out as WAV files.
+    Export(ExportArgs),
+    /// Replace a sample of a module with a WAV file.
+    Import(ImportArgs),
+}
+
+#[derive(Args)]
+struct ExportArgs {
+    file: PathBuf,
+    /// Only export this sample, in hex as `info` lists them.
+    #[arg(long, value_parser = parse_index)]
+    sample: Option<u8>,
+    /// Directory to write the WAV files to.
+    #[arg(long, default_value = ".")]
+    out: PathBuf,
+    /// Rate the WAV files play at; the default is that of C-2, or of C-4
+    /// for samples tuned that way.
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    rate: Option<u32>,
+}
+
+#[derive(Args)]
+struct ImportArgs {
+    file: PathBuf,
+    /// The sample to replace, 1 to 1f in hex.
+    #[arg(value_parser = parse_mod_index)]
+    sample: u8,
+    wav: PathBuf,
+    /// Rate to resample to, which then plays as C-2.
+    #[arg(long, default_value_t = wav::AMIGA_C2_RATE, value_parser = clap::value_parser!(u32).range(1..))]
+    rate: u32,
+    /// New sample name; the old one is kept by default.
+    #[arg(long)]
+    name: Option<String>,
+    /// New volume, 0 to 64; the old one is kept by default.
+    #[arg(long, value_parser = clap::value_parser!(u8).range(0..=64))]
+    volume: Option<u8>,
+    /// New finetune, -8 to 7; the old one is kept by default.
+    #[arg(long, allow_negative_numbers = true, value_parser = clap::value_parser!(i8).range(-8..=7))]
+    finetune: Option<i8>,
+    /// Where to write the new module; the module is changed in place by
+    /// default.
+    #[arg(long)]
+    out: Option<PathBuf>,
+}
+
+fn parse_index(s: &str) -> Result<u8, String> {
+    match u8::from_str_radix(s.trim_start_matches("0x"), 16) {
+        Ok(i @ 1..) => Ok(i),
+        Ok(_) => Err("samples are numbered from 1".into()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+fn parse_mod_index(s: &str) -> Result<u8, String> {
+    match parse_index(s)? {
+        i @ 1..=0x1f => Ok(i),
+        _ => Err("MOD samples are numbered 1 to 1f".into()),
+    }
+}
+
+/// A sample name made safe to put in a file name.
+fn file_name(index: usize, name: &str) -> String {
+    let name: String = name
+        .trim()
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if name.is_empty() {
+        format!("{index:02x}.wav")
+    } else {
+        format!("{index:02x}-{name}.wav")
+    }
+}
+
+pub fn run(args: SamplesArgs) -> io::Result<()> {
+    match args.command {
+        SamplesCommand::Export(args) => export(args),
+        SamplesCommand::Import(args) => import(args),
+    }
+}
+
+fn export(args: ExportArgs) -> io::Result<()> {
+    let (module, _) = crate::load_module(&args.file)?;
+    fs::create_dir_all(&args.out)?;
+    for (i, sample) in module.samples.iter().enumerate().skip(1) {
+        if sample.data.is_empty() || args.sample.is_some_and(|s| s as usize != i) {
+            continue;
+        }
+        let path = args.out.join(file_name(i, &sample.name));
+        let mut f = BufWriter::new(File::create(&path)?);
+        // a c2spd of 0 plays at 8363 Hz, as in Scream Tracker
+        let c2spd = sample.c2spd.map(|c| if c == 0 { 8363 } else { c });
+        let rate = args.rate.or(c2spd).unwrap_or(wav::AMIGA_C2_RATE);
+        wav::export(&mut f, sample, rate)?;
+        f.flush()?;
+        println!("{}", path.display());
+    }
+    Ok(())
+}
+
+fn import(args: ImportArgs) -> io::Result<()> {
+    let file = fs::read(&args.file)?;
+    let module = loader::load(&mut Cursor::new(&file))?;
+    let old = &module.samples[args.sample as usize];
+    let mut sample = wav::import(&mut File::open(&args.wav)?, &old.name, args.rate)?;
+    if let Some(name) = args.name {
+        sample.name = name;
+    }
+    sample.volume = args.volume.unwrap_or(old.volume);
+    sample.finetune = args.finetune.map_or(old.finetune, |f| f as u8 & 0xf);
+    let file = loader::replace_sample(&file, args.sample, &sample)?;
+    fs::write(args.out.as_ref().unwrap_or(&args.file), file)?;
+    println!(
+        "sample {:02x}: {} bytes{}",
+        args.sample,
+        sample.data.len(),
+        match sample.repeat {
+            Some((start, len)) => format!(", loop {start}+{len}"),
+            None => String::new(),
+        }
+    );
+    Ok(())
+}
@@ -0,0 +1,511 @@
+//! Command scripts: player control calls with the time they are made at, one
+//! per line, like
+//!
+//! ```text
+//! # comments and blank lines are skipped
+//! at 2.5s jingle pos=0x20 repeat=2 prio=10
+//! at 1500ms sfx sample=3 channel=3
+//! at 300t music_pos pos=4
+//! at 64r seek pos=2 row=0x10
+//! at 88000f fade_out ticks=25
+//! at 20s mute channel=1
+//! at 25s load file=other.mod in=fadeout:25
+//! at 30s end
+//! ```
+//!
+//! Times are in seconds, milliseconds, ticks, rows played or frames, all
+//! counted by the audio clock from the start of playback.  Commands run in
+//! file order, each once its time has come.  Frame times are exact when
+//! rendering offline and rounded to ticks when playing live; recorded sessions
+//! use them, see [`call_line`].
+
+use std::{
+    fmt::{self, Display},
+    num::NonZeroU8,
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
+
+use pfr::sound::{
+    analysis::TICKS_PER_SECOND,
+    player::{
+        Call, CommandError, JingleMode, JingleRequest, JingleStart, Player, PlayerState,
+        RecordedCall, Transition,
+    },
+};
+
+use crate::Loaded;
+
+/// How often the live runner checks the clock.
+const POLL: Duration = Duration::from_millis(2);
+
+/// The length of fades in transitions, in ticks.
+const FADE_TICKS: u16 = 50;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum At {
+    Tick(u32),
+    Row(u32),
+    Frame(u64),
+}
+
+/// Where the audio clock is at.
+struct Clock {
+    ticks: u32,
+    rows: u32,
+    frames: u64,
+}
+
+impl At {
+    fn is_due(self, clock: &Clock) -> bool {
+        match self {
+            At::Tick(t) => clock.ticks >= t,
+            At::Row(r) => clock.rows >= r,
+            At::Frame(f) => clock.frames >= f,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Action {
+    Call(Call),
+    Seek(u8, u8),
+    Mute(u8, bool),
+    Solo(u8, bool),
+    Load(PathBuf, Transition),
+    End,
+}
+
+impl Action {
+    fn apply(&self, player: &mut Player, loaded: &Loaded) -> Result<(), String> {
+        let res = match *self {
+            Action::Call(call) => player.call(call),
+            Action::Seek(position, row) => player.seek(position, row),
+            Action::Mute(channel, on) => player.set_channel_mute(channel, on),
+            Action::Solo(channel, on) => player.set_channel_solo(channel, on),
+            Action::Load(ref path, transition) => {
+                return loaded
+                    .load(player, path, transition)
+                    .map_err(|e| format!("{}: {e}", path.display()));
+            }
+            Action::End => Ok(()),
+        };
+        res.map_err(|e: CommandError| e.to_string())
+    }
+}
+
+struct Event {
+    line: usize,
+    at: At,
+    action: Action,
+}
+
+pub struct Script {
+    events: Vec<Event>,
+}
+
+#[derive(Debug)]
+pub struct ParseError {
+    line: usize,
+    msg: String,
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.msg)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+fn parse_num<T: TryFrom<u64>>(s: &str) -> Result<T, String> {
+    let v = match s.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => s.parse(),
+    }
+    .map_err(|e| format!("bad number {s:?}: {e}"))?;
+    T::try_from(v).map_err(|_| format!("{s} out of range"))
+}
+
+fn parse_at(s: &str) -> Result<At, String> {
+    let bad = |e: &dyn Display| format!("bad time {s:?}: {e}");
+    if let Some(ms) = s.strip_suffix("ms") {
+        let ms: f64 = ms.parse().map_err(|e| bad(&e))?;
+        Ok(At::Tick((ms * TICKS_PER_SECOND as f64 / 1000.) as u32))
+    } else if let Some(secs) = s.strip_suffix('s') {
+        let secs: f64 = secs.parse().map_err(|e| bad(&e))?;
+        Ok(At::Tick((secs * TICKS_PER_SECOND as f64) as u32))
+    } else if let Some(ticks) = s.strip_suffix('t') {
+        Ok(At::Tick(parse_num(ticks)?))
+    } else if let Some(rows) = s.strip_suffix('r') {
+        Ok(At::Row(parse_num(rows)?))
+    } else if let Some(frames) = s.strip_suffix('f') {
+        Ok(At::Frame(parse_num(frames)?))
+    } else {
+        Err(bad(&"no unit (s, ms, t, r or f)"))
+    }
+}
+
+/// A transition, with its length in ticks after a colon if not the default,
+/// like `fadeout:25`.
+fn parse_transition(s: &str) -> Result<Transition, String> {
+    let (kind, ticks) = match s.split_once(':') {
+        Some((kind, ticks)) => (kind, parse_num(ticks)?),
+        None => (s, FADE_TICKS),
+    };
+    match kind {
+        "cut" => Ok(Transition::Cut),
+        "fadeout" => Ok(Transition::FadeOut(ticks)),
+        "fadein" => Ok(Transition::FadeIn(ticks)),
+        "crossfade" => Ok(Transition::Crossfade(ticks)),
+        _ => Err(format!("unknown transition {s:?}")),
+    }
+}
+
+fn transition_str(t: Transition) -> String {
+    match t {
+        Transition::Cut => "cut".into(),
+        Transition::FadeOut(ticks) => format!("fadeout:{ticks}"),
+        Transition::FadeIn(ticks) => format!("fadein:{ticks}"),
+        Transition::Crossfade(ticks) => format!("crossfade:{ticks}"),
+    }
+}
+
+/// A recorded call as a script line, which makes the same call at the same
+/// frame when run.  `loaded` are the files of the modules loaded, in order.
+pub fn call_line(rec: &RecordedCall, loaded: &[PathBuf]) -> String {
+    let cmd = match rec.call {
+        Call::Jingle(req) => format!(
+            "jingle pos={:#04x} repeat={} prio={} mode={} start={} resume={} in={} out={}",
+            req.position,
+            req.repeats,
+            req.priority,
+            match req.mode {
+                JingleMode::Normal => "normal",
+                JingleMode::Force => "force",
+                JingleMode::Queue => "queue",
+            },
+            match req.start {
+                JingleStart::Now => "now".into(),
+                JingleStart::NextRow => "row".into(),
+                JingleStart::NextPattern => "pattern".into(),
+                JingleStart::NextBeat(beat) => format!("beat:{beat}"),
+            },
+            req.resume as u8,
+            transition_str(req.transition),
+            transition_str(req.return_transition),
+        ),
+        Call::SetMusicPos(pos) => format!("music_pos pos={pos:#04x}"),
+        Call::SetMusicPrio(prio) => format!("music_prio prio={prio}"),
+        Call::SetMusicResume(resume) => format!("music_resume on={}", resume as u8),
+        Call::Sfx {
+            period,
+            sample,
+            volume,
+            channel,
+        } => format!("sfx sample={sample} period={period} volume={volume} channel={channel}"),
+        Call::FadeOut(ticks) => format!("fade_out ticks={ticks}"),
+        Call::FadeIn(ticks) => format!("fade_in ticks={ticks}"),
+        Call::Seek(tick) => format!("seek tick={tick}"),
+        Call::SetPaused(paused) => format!("pause on={}", paused as u8),
+        Call::SetMasterVolume(level) => format!("volume level={level}"),
+        Call::SetChannelMutes(mask) => format!("mutes mask={mask:#x}"),
+        Call::SetChannelSolos(mask) => format!("solos mask={mask:#x}"),
+        Call::LoadModule { number, transition } => match loaded.get(number as usize - 1) {
+            Some(path) => format!(
+                "load file={} in={}",
+                path.display(),
+                transition_str(transition)
+            ),
+            None => format!("# module {number} loaded from an unknown file"),
+        },
+    };
+    format!("at {}f {cmd}", rec.frame)
+}
+
+/// The `key=value` arguments of a command.
+struct Args<'a> {
+    args: Vec<(&'a str, &'a str)>,
+}
+
+impl<'a> Args<'a> {
+    fn parse(words: &[&'a str]) -> Result<Self, String> {
+        let args = words
+            .iter()
+            .map(|w| {
+                w.split_once('=')
+                    .ok_or(format!("expected key=value, got {w:?}"))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Args { args })
+    }
+
+    fn get(&self, key: &str) -> Option<&'a str> {
+        self.args.iter().find(|(k, _)| *k == key).map(|&(_, v)| v)
+    }
+
+    fn num<T: TryFrom<u64>>(&self, key: &str, default: Option<T>) -> Result<T, String> {
+        match self.get(key) {
+            Some(v) => parse_num(v),
+            None => default.ok_or(format!("missing {key}=")),
+        }
+    }
+
+    /// Fails on keys the command does not know.
+    fn check(&self, known: &[&str]) -> Result<(), String> {
+        match self.args.iter().find(|(k, _)| !known.contains(k)) {
+            Some((k, _)) => Err(format!("unknown argument {k:?}")),
+            None => Ok(()),
+        }
+    }
+}
+
+fn parse_jingle(args: &Args) -> Result<Action, String> {
+    args.check(&[
+        "pos", "repeat", "prio", "mode", "start", "resume", "in", "out",
+    ])?;
+    let start = match args.get("start").unwrap_or("now") {
+        "now" => JingleStart::Now,
+        "row" => JingleStart::NextRow,
+        "pattern" => JingleStart::NextPattern,
+        s => match s.strip_prefix("beat:").map(parse_num::<u8>) {
+            Some(beat) => JingleStart::NextBeat(
+                NonZeroU8::new(beat?).ok_or("beat must not be 0".to_string())?,
+            ),
+            None => return Err(format!("unknown start {s:?}")),
+        },
+    };
+    Ok(Action::Call(Call::Jingle(JingleRequest {
+        position: args.num("pos", None)?,
+        repeats: args.num("repeat", Some(1))?,
+        priority: args.num("prio", Some(0))?,
+        mode: match args.get("mode").unwrap_or("normal") {
+            "normal" => JingleMode::Normal,
+            "force" => JingleMode::Force,
+            "queue" => JingleMode::Queue,
+            s => return Err(format!("unknown mode {s:?}")),
+        },
+        start,
+        resume: args.num::<u8>("resume", Some(0))? != 0,
+        transition: parse_transition(args.get("in").unwrap_or("cut"))?,
+        return_transition: parse_transition(args.get("out").unwrap_or("cut"))?,
+    })))
+}
+
+fn parse_action(words: &[&str]) -> Result<Action, String> {
+    let (&cmd, rest) = words.split_first().ok_or("missing command")?;
+    let args = Args::parse(rest)?;
+    let action = match cmd {
+        "jingle" => return parse_jingle(&args),
+        "music_pos" => {
+            args.check(&["pos"])?;
+            Action::Call(Call::SetMusicPos(args.num("pos", None)?))
+        }
+        "music_prio" => {
+            args.check(&["prio"])?;
+            Action::Call(Call::SetMusicPrio(args.num("prio", None)?))
+        }
+        "music_resume" => {
+            args.check(&["on"])?;
+            Action::Call(Call::SetMusicResume(args.num::<u8>("on", Some(1))? != 0))
+        }
+        "sfx" => {
+            args.check(&["sample", "period", "volume", "channel"])?;
+            Action::Call(Call::Sfx {
+                sample: args.num("sample", None)?,
+                period: args.num("period", Some(12))?,
+                volume: args.num("volume", Some(0))?,
+                channel: args.num("channel", Some(3))?,
+            })
+        }
+        "seek" => {
+            args.check(&["pos", "row", "tick"])?;
+            match args.get("tick") {
+                // as recorded, already resolved to a tick of the song
+                Some(tick) => Action::Call(Call::Seek(parse_num(tick)?)),
+                None => Action::Seek(args.num("pos", None)?, args.num("row", Some(0))?),
+            }
+        }
+        "fade_out" => {
+            args.check(&["ticks"])?;
+            Action::Call(Call::FadeOut(args.num("ticks", Some(FADE_TICKS))?))
+        }
+        "fade_in" => {
+            args.check(&["ticks"])?;
+            Action::Call(Call::FadeIn(args.num("ticks", Some(FADE_TICKS))?))
+        }
+        "volume" => {
+            args.check(&["level"])?;
+            Action::Call(Call::SetMasterVolume(args.num("level", None)?))
+        }
+        "pause" => {
+            args.check(&["on"])?;
+            Action::Call(Call::SetPaused(args.num::<u8>("on", Some(1))? != 0))
+        }
+        "mutes" => {
+            args.check(&["mask"])?;
+            Action::Call(Call::SetChannelMutes(args.num("mask", None)?))
+        }
+        "solos" => {
+            args.check(&["mask"])?;
+            Action::Call(Call::SetChannelSolos(args.num("mask", None)?))
+        }
+        "load" => {
+            args.check(&["file", "in"])?;
+            Action::Load(
+                Path::new(args.get("file").ok_or("missing file=")?).into(),
+                parse_transition(args.get("in").unwrap_or("cut"))?,
+            )
+        }
+        "mute" => {
+            args.check(&["channel", "on"])?;
+            Action::Mute(
+                args.num("channel", None)?,
+                args.num::<u8>("on", Some(1))? != 0,
+            )
+        }
+        "solo" => {
+            args.check(&["channel", "on"])?;
+            Action::Solo(
+                args.num("channel", None)?,
+                args.num::<u8>("on", Some(1))? != 0,
+            )
+        }
+        "end" => {
+            args.check(&[])?;
+            Action::End
+        }
+        _ => return Err(format!("unknown command {cmd:?}")),
+    };
+    Ok(action)
+}
+
+impl Script {
+    pub fn parse(text: &str) -> Result<Script, ParseError> {
+        let mut events = vec![];
+        for (i, line) in text.lines().enumerate() {
+            let line_no = i + 1;
+            let err = |msg| ParseError { line: line_no, msg };
+            let line = line.split('#').next().unwrap();
+            let words: Vec<_> = line.split_whitespace().collect();
+            let [kw, at, rest @ ..] = &words[..] else {
+                if words.is_empty() {
+                    continue;
+                }
+                return Err(err("expected: at TIME COMMAND ...".into()));
+            };
+            if *kw != "at" {
+                return Err(err("expected: at TIME COMMAND ...".into()));
+            }
+            events.push(Event {
+                line: line_no,
+                at: parse_at(at).map_err(err)?,
+                action: parse_action(rest).map_err(err)?,
+            });
+        }
+        Ok(Script { events })
+    }
+
+    /// Makes the calls that are due by `clock`, in order.  Returns true once
+    /// the script is over.
+    fn run_due(
+        &mut self,
+        player: &mut Player,
+        loaded: &Loaded,
+        clock: &Clock,
+        next: &mut usize,
+    ) -> bool {
+        while let Some(event) = self.events.get(*next) {
+            if !event.at.is_due(clock) {
+                return false;
+            }
+            *next += 1;
+            if let Action::End = event.action {
+                return true;
+            }
+            if let Err(e) = event.action.apply(player, loaded) {
+                eprintln!("line {}: {e}", event.line);
+            }
+        }
+        true
+    }
+
+    /// Runs the script against a playing player, then lets it play for
+    /// `tail` more ticks unless the script ends itself.
+    pub fn run_live(mut self, player: &mut Player, loaded: &Loaded, tail: u32) {
+        let frames_per_tick = (player.sample_rate() / TICKS_PER_SECOND) as u64;
+        let clock = |player: &Player| {
+            let (ticks, rows) = player.clock();
+            Clock {
+                ticks,
+                rows,
+                frames: ticks as u64 * frames_per_tick,
+            }
+        };
+        let mut next = 0;
+        while !self.run_due(player, loaded, &clock(player), &mut next) {
+            thread::sleep(POLL);
+        }
+        if self.ended(next) {
+            return;
+        }
+        let (end, _) = player.clock();
+        while player.clock().0 < end + tail {
+            thread::sleep(POLL);
+        }
+    }
+
+    /// Runs the script against an offline player, rendering up to every tick
+    /// and every frame time, so that each call lands on the exact tick or
+    /// frame.  Returns the interleaved stereo output.
+    pub fn run_offline(
+        mut self,
+        player: &mut Player,
+        state: &mut PlayerState,
+        loaded: &Loaded,
+        tail: u32,
+    ) -> Vec<i32> {
+        let frames_per_tick = (state.sample_rate() / TICKS_PER_SECOND) as u64;
+        let mut buf = vec![0; frames_per_tick as usize * 2];
+        let mut out = vec![];
+        let mut next = 0;
+        let mut end = None;
+        loop {
+            let (ticks, rows) = player.clock();
+            let clock = Clock {
+                ticks,
+                rows,
+                frames: state.frames(),
+            };
+            if end.is_none() && self.run_due(player, loaded, &clock, &mut next) {
+                if self.ended(next) {
+                    break;
+                }
+                end = Some(clock.frames + tail as u64 * frames_per_tick);
+            }
+            let mut len = match state.frames_to_tick() {
+                0 => frames_per_tick,
+                left => left as u64,
+            };
+            if let Some(At::Frame(f)) = self.events.get(next).map(|e| e.at) {
+                len = len.min(f - clock.frames);
+            }
+            if let Some(end) = end {
+                if clock.frames >= end {
+                    break;
+                }
+                len = len.min(end - clock.frames);
+            }
+            let buf = &mut buf[..len as usize * 2];
+            state.render(buf);
+            out.extend_from_slice(buf);
+        }
+        out
+    }
+
+    /// Whether the script stopped at an `end` command.
+    fn ended(&self, next: usize) -> bool {
+        next > 0 && matches!(self.events[next - 1].action, Action::End)
+    }
+}
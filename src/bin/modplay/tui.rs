@@ -0,0 +1,296 @@
+use std::{
+    io::{self, Write},
+    time::Duration,
+};
+
+use crossterm::{
+    cursor::{Hide, MoveTo, Show},
+    event::{self, Event, KeyCode, KeyEventKind},
+    execute, queue,
+    style::{Attribute, Print, SetAttribute},
+    terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use pfr::sound::{
+    player::{JingleMode, JingleRequest, JingleStart, Player, Transition},
+    Mod,
+};
+
+/// How often the screen is redrawn.
+const FRAME: Duration = Duration::from_millis(40);
+/// Width of the VU bars.
+const BAR_WIDTH: usize = 32;
+/// Period sound effects are played at: C-2.
+const SFX_PERIOD: u8 = 12;
+
+const HELP: &str = "q quit  space pause  +/- volume  \u{2190}/\u{2192} position  \
+    g go to  j jingle  \u{2191}/\u{2193} sfx  enter play sfx  c sfx channel  \
+    1-9 mute  F1-F9 solo  0 unmute all";
+
+enum Prompt {
+    GoTo,
+    Jingle,
+}
+
+struct Tui<'a> {
+    player: &'a Player,
+    module: &'a Mod,
+    /// Names of the sfx bank samples, from ID 1 up.
+    sfx: &'a [String],
+    sfx_selected: usize,
+    sfx_channel: u8,
+    prompt: Option<(Prompt, String)>,
+    /// The last jingle fired, to start the next prompt from.
+    last_jingle: String,
+    message: String,
+}
+
+/// Runs the interactive player until the user quits.
+pub fn run(player: &Player, module: &Mod, sfx: &[String]) -> io::Result<()> {
+    let mut out = io::stdout();
+    terminal::enable_raw_mode()?;
+    execute!(out, EnterAlternateScreen, Hide)?;
+    let mut tui = Tui {
+        player,
+        module,
+        sfx,
+        sfx_selected: 0,
+        sfx_channel: 3,
+        prompt: None,
+        last_jingle: String::new(),
+        message: String::new(),
+    };
+    let res = tui.run(&mut out);
+    execute!(out, Show, LeaveAlternateScreen)?;
+    terminal::disable_raw_mode()?;
+    res
+}
+
+impl Tui<'_> {
+    fn run(&mut self, out: &mut impl Write) -> io::Result<()> {
+        loop {
+            for e in self.player.events() {
+                self.message = format!("{e:?}");
+            }
+            for e in self.player.output_errors() {
+                self.message = e.to_string();
+            }
+            self.draw(out)?;
+            if !event::poll(FRAME)? {
+                continue;
+            }
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            if key.kind == KeyEventKind::Release {
+                continue;
+            }
+            if self.prompt.is_some() {
+                self.prompt_key(key.code);
+                continue;
+            }
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                KeyCode::Char(' ') => self.player.set_paused(!self.player.paused()),
+                KeyCode::Char('+' | '=') => self.change_volume(5),
+                KeyCode::Char('-') => self.change_volume(-5),
+                KeyCode::Left => self.step_position(-1),
+                KeyCode::Right => self.step_position(1),
+                KeyCode::Char('g') => self.prompt = Some((Prompt::GoTo, String::new())),
+                KeyCode::Char('j') => {
+                    self.prompt = Some((Prompt::Jingle, self.last_jingle.clone()));
+                }
+                KeyCode::Up => self.sfx_selected = self.sfx_selected.saturating_sub(1),
+                KeyCode::Down => {
+                    self.sfx_selected = (self.sfx_selected + 1).min(self.sfx.len().max(1) - 1);
+                }
+                KeyCode::Char('c') => {
+                    self.sfx_channel = (self.sfx_channel + 1) % self.player.channels() as u8;
+                }
+                KeyCode::Enter => self.play_sfx(),
+                KeyCode::Char(c @ '1'..='9') => self.toggle_mute(c as u8 - b'1'),
+                KeyCode::F(n @ 1..=9) => self.toggle_solo(n - 1),
+                KeyCode::Char('0') => {
+                    for channel in 0..self.player.channels() as u8 {
+                        self.report(self.player.set_channel_mute(channel, false));
+                        self.report(self.player.set_channel_solo(channel, false));
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn prompt_key(&mut self, code: KeyCode) {
+        let Some((kind, text)) = &mut self.prompt else {
+            return;
+        };
+        match code {
+            KeyCode::Char(c) => text.push(c),
+            KeyCode::Backspace => {
+                text.pop();
+            }
+            KeyCode::Esc => self.prompt = None,
+            KeyCode::Enter => {
+                let text = text.clone();
+                match kind {
+                    Prompt::GoTo => self.go_to(&text),
+                    Prompt::Jingle => self.jingle(&text),
+                }
+                self.prompt = None;
+            }
+            _ => {}
+        }
+    }
+
+    fn change_volume(&mut self, delta: i16) {
+        let volume = (self.player.master_volume() as i16 + delta).clamp(0, 200);
+        self.player.set_master_volume(volume as u8);
+    }
+
+    fn step_position(&mut self, delta: i16) {
+        let (position, _) = self.player.status();
+        let position = position as i16 + delta;
+        if (0..self.module.positions.len() as i16).contains(&position) {
+            self.report(self.player.seek(position as u8, 0));
+        }
+    }
+
+    fn go_to(&mut self, text: &str) {
+        match u8::from_str_radix(text.trim(), 16) {
+            Ok(position) => self.report(self.player.seek(position, 0)),
+            Err(e) => self.message = format!("go to: {e}"),
+        }
+    }
+
+    /// Fires a jingle from "position [repeats [priority]]", in hex.
+    fn jingle(&mut self, text: &str) {
+        let fields: Result<Vec<u8>, _> = text
+            .split_whitespace()
+            .map(|f| u8::from_str_radix(f, 16))
+            .collect();
+        let fields = match fields {
+            Ok(fields) if (1..=3).contains(&fields.len()) => fields,
+            Ok(_) => {
+                self.message = "jingle: position [repeats [priority]]".into();
+                return;
+            }
+            Err(e) => {
+                self.message = format!("jingle: {e}");
+                return;
+            }
+        };
+        self.last_jingle = text.to_string();
+        let req = JingleRequest {
+            position: fields[0],
+            repeats: fields.get(1).copied().unwrap_or(1),
+            priority: fields.get(2).copied().unwrap_or(1),
+            mode: JingleMode::Normal,
+            start: JingleStart::Now,
+            resume: false,
+            transition: Transition::Cut,
+            return_transition: Transition::Cut,
+        };
+        self.report(self.player.jingle(req));
+    }
+
+    fn toggle_mute(&mut self, channel: u8) {
+        let (mutes, _) = self.player.channel_mutes();
+        let mute = !mutes.get(channel as usize).copied().unwrap_or_default();
+        self.report(self.player.set_channel_mute(channel, mute));
+    }
+
+    fn toggle_solo(&mut self, channel: u8) {
+        let (_, solos) = self.player.channel_mutes();
+        let solo = !solos.get(channel as usize).copied().unwrap_or_default();
+        self.report(self.player.set_channel_solo(channel, solo));
+    }
+
+    fn play_sfx(&mut self) {
+        if self.sfx.is_empty() {
+            return;
+        }
+        let sample = self.sfx_selected as u8 + 1;
+        self.report(
+            self.player
+                .play_sfx(SFX_PERIOD, sample, 0, self.sfx_channel),
+        );
+    }
+
+    fn report<E: ToString>(&mut self, res: Result<(), E>) {
+        if let Err(e) = res {
+            self.message = e.to_string();
+        }
+    }
+
+    fn draw(&self, out: &mut impl Write) -> io::Result<()> {
+        let (width, height) = terminal::size()?;
+        let (width, height) = (width as usize, height as usize);
+        let (position, row) = self.player.status();
+        let pattern = self.module.positions[position as usize];
+        let mut lines = vec![];
+        lines.push(format!("modplay  {}", self.module.name));
+        lines.push(format!(
+            "position {position:02x}/{:02x}  pattern {pattern:02x}  row {row:02x}  volume {}%{}",
+            self.module.positions.len(),
+            self.player.master_volume(),
+            if self.player.paused() { "  PAUSED" } else { "" },
+        ));
+        let (mutes, solos) = self.player.channel_mutes();
+        for (i, level) in self.player.channel_levels().iter().enumerate() {
+            let bar = level.peak as usize * BAR_WIDTH / 0x100;
+            lines.push(format!(
+                "ch{} vol {:02x} {}{} {}{}",
+                i + 1,
+                level.volume,
+                "\u{2588}".repeat(bar),
+                "\u{2591}".repeat(BAR_WIDTH - bar),
+                if mutes[i] { "M" } else { " " },
+                if solos[i] { "S" } else { " " },
+            ));
+        }
+        match self.sfx.get(self.sfx_selected) {
+            Some(name) => lines.push(format!(
+                "sfx {:02x}/{:02x} {name:22} on channel {}",
+                self.sfx_selected + 1,
+                self.sfx.len(),
+                self.sfx_channel + 1
+            )),
+            None => lines.push("no sfx".into()),
+        }
+        lines.push(String::new());
+        let footer = 2;
+        let view = height.saturating_sub(lines.len() + footer);
+        let current = lines.len() + view / 2;
+        let rows = &self.module.patterns[pattern as usize];
+        for i in 0..view {
+            let r = row as isize + i as isize - (view / 2) as isize;
+            match rows.get(r as usize).filter(|_| r >= 0) {
+                Some(notes) => {
+                    let mut line = format!("{r:02x}");
+                    for note in notes {
+                        line.push_str(&format!(" \u{2502} {note}"));
+                    }
+                    lines.push(line);
+                }
+                None => lines.push(String::new()),
+            }
+        }
+        lines.push(HELP.into());
+        lines.push(match &self.prompt {
+            Some((Prompt::GoTo, text)) => format!("go to position: {text}_"),
+            Some((Prompt::Jingle, text)) => {
+                format!("jingle position [repeats [priority]]: {text}_")
+            }
+            None => self.message.clone(),
+        });
+        for (y, line) in lines.iter().take(height).enumerate() {
+            queue!(out, MoveTo(0, y as u16), Clear(ClearType::CurrentLine))?;
+            if y == current {
+                queue!(out, SetAttribute(Attribute::Reverse))?;
+            }
+            let line: String = line.chars().take(width).collect();
+            queue!(out, Print(line), SetAttribute(Attribute::Reset))?;
+        }
+        out.flush()
+    }
+}
@@ -1,7 +1,19 @@
+pub mod analysis;
 pub mod loader;
+pub mod packed;
 pub mod player;
+pub mod s3m;
+pub mod sfx;
+pub mod wav;
+pub mod xm;
 
-use std::{fmt::Display, num::NonZeroU8};
+use std::{
+    fmt::Display,
+    io::{self, Cursor, Read},
+    num::NonZeroU8,
+};
+
+use packed::Packer;
 
 pub const PERIODS: [[u16; 36]; 16] = [
     [
@@ -70,42 +82,225 @@ pub const PERIODS: [[u16; 36]; 16] = [
     ],
 ];
 
-#[rustfmt::skip]
-pub const NAMES: [&str; 36] = [
-    "C-1", "C#1", "D-1", "D#1", "E-1", "F-1", "F#1", "G-1", "G#1", "A-1", "A#1", "B-1",
-    "C-2", "C#2", "D-2", "D#2", "E-2", "F-2", "F#2", "G-2", "G#2", "A-2", "A#2", "B-2",
-    "C-3", "C#3", "D-3", "D#3", "E-3", "F-3", "F#3", "G-3", "G#3", "A-3", "A#3", "B-3",
+pub const KEYS: [&str; 12] = [
+    "C-", "C#", "D-", "D#", "E-", "F-", "F#", "G-", "G#", "A-", "A#", "B-",
 ];
 
+/// Notes are numbered by semitone from C-0, in Scream Tracker's octaves.
+/// ProTracker's C-1, the first note of [`PERIODS`], is C-3 there, though
+/// notes are still shown with ProTracker's names.
+pub const MOD_FIRST_NOTE: u8 = 36;
+/// The highest note there is, B-9.
+pub const LAST_NOTE: u8 = 119;
+
+/// Most channels a module can have.
+pub const MAX_CHANNELS: usize = 32;
+
+/// Hard left, for [`Mod::panning`].
+pub const PAN_LEFT: u8 = 0;
+/// Hard right, for [`Mod::panning`].
+pub const PAN_RIGHT: u8 = 0x80;
+
+#[derive(Clone)]
 pub struct Mod {
     pub name: String,
     pub samples: Vec<Sample>,
     pub patterns: Vec<Pattern>,
     pub positions: Vec<u8>,
     pub pos_restart: u8,
+    /// Where each channel starts out, from [`PAN_LEFT`] to [`PAN_RIGHT`].
+    /// There are as many channels as entries, up to [`MAX_CHANNELS`].
+    pub panning: Vec<u8>,
+    /// Ticks per row the song starts at.
+    pub speed: u8,
+    /// Volume of the whole song, 0 to 0x40.
+    pub global_volume: u8,
+    /// Periods go down linearly with the pitch, as in most XM files, rather
+    /// than with the frequency as on the Amiga.
+    pub linear_periods: bool,
+}
+
+impl Mod {
+    pub fn channels(&self) -> usize {
+        self.panning.len()
+    }
+}
+
+/// The formats [`load_any`] tells apart.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// A MOD, with the tag it has at 1080, like "M.K.".
+    Mod(String),
+    S3m,
+    Xm,
+    /// A MOD packed for a game.
+    Packed(Packer),
+    /// A file crunched with PowerPacker, and what is inside.
+    PowerPacker(Box<Format>),
+}
+
+impl Display for Format {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Format::Mod(tag) => write!(f, "{tag}"),
+            Format::S3m => write!(f, "S3M"),
+            Format::Xm => write!(f, "XM"),
+            Format::Packed(packer) => write!(f, "{packer}"),
+            Format::PowerPacker(inner) => write!(f, "{inner} in PowerPacker"),
+        }
+    }
+}
+
+/// Where the tag sits in a MOD.
+const MOD_TAG: usize = 1080;
+
+/// Where the tag sits in an S3M.
+const S3M_TAG: usize = 0x2c;
+
+/// Loads a module in any format there is a loader or a depacker for, telling
+/// them apart by their tags, or for packers without one by the layout.  What
+/// does not convert is in the warnings.
+pub fn load_any(f: &mut impl Read) -> io::Result<(Mod, Format, Vec<String>)> {
+    let mut file = vec![];
+    f.read_to_end(&mut file)?;
+    load_bytes(&file)
+}
+
+fn strings(warnings: Vec<impl Display>) -> Vec<String> {
+    warnings.iter().map(ToString::to_string).collect()
+}
+
+fn load_bytes(file: &[u8]) -> io::Result<(Mod, Format, Vec<String>)> {
+    if file.starts_with(xm::TAG) {
+        let (module, warnings) = xm::load(&mut Cursor::new(file))?;
+        return Ok((module, Format::Xm, strings(warnings)));
+    }
+    if file.get(S3M_TAG..S3M_TAG + 4) == Some(b"SCRM") {
+        let (module, warnings) = s3m::load(&mut Cursor::new(file))?;
+        return Ok((module, Format::S3m, strings(warnings)));
+    }
+    if file.starts_with(packed::POWERPACKER_TAG) {
+        let (module, format, warnings) = load_bytes(&packed::decrunch(file)?)?;
+        return Ok((module, Format::PowerPacker(Box::new(format)), warnings));
+    }
+    let tag = file.get(MOD_TAG..MOD_TAG + 4).unwrap_or_default();
+    if !matches!(tag, b"M.K." | b"M!K!" | b"FLT4" | b"4CHN") {
+        if let Some(packer) = packed::detect(file) {
+            let file = packed::depack(file, packer)?;
+            let module = loader::load(&mut Cursor::new(file))?;
+            return Ok((module, Format::Packed(packer), vec![]));
+        }
+    }
+    let module = loader::load(&mut Cursor::new(file))?;
+    let tag = String::from_utf8_lossy(tag).into_owned();
+    Ok((module, Format::Mod(tag), vec![]))
 }
 
 #[derive(Clone, Debug)]
 pub struct Sample {
     pub name: String,
-    pub data: Vec<u8>,
+    pub data: SampleData,
     pub finetune: u8,
     pub volume: u8,
+    /// Start and length of the loop, in frames.
     pub repeat: Option<(usize, usize)>,
+    /// The rate the sample plays at as C-4, for samples tuned that way
+    /// instead of by finetune, as in S3M files.
+    pub c2spd: Option<u32>,
+    /// How far the sample is tuned up in modules with linear periods, in
+    /// 1/128 semitones: the relative note of an XM sample times 128, plus its
+    /// finetune.
+    pub tuning: i16,
+    /// Where a note with the sample pans the channel to, if anywhere.
+    pub panning: Option<u8>,
 }
 
-pub type Pattern = [Row; 0x40];
-pub type Row = [Note; 4];
+/// The frames of a sample, signed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SampleData {
+    /// 8-bit, as bytes the way MOD files store them.
+    Bits8(Vec<u8>),
+    Bits16(Vec<i16>),
+    /// 8-bit left and right, as bytes.
+    Stereo8(Vec<[u8; 2]>),
+    Stereo16(Vec<[i16; 2]>),
+}
+
+impl SampleData {
+    /// The length in frames.
+    pub fn len(&self) -> usize {
+        match self {
+            SampleData::Bits8(data) => data.len(),
+            SampleData::Bits16(data) => data.len(),
+            SampleData::Stereo8(data) => data.len(),
+            SampleData::Stereo16(data) => data.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// True for 16-bit data.
+    pub fn is_16_bit(&self) -> bool {
+        matches!(self, SampleData::Bits16(_) | SampleData::Stereo16(_))
+    }
+
+    pub fn is_stereo(&self) -> bool {
+        matches!(self, SampleData::Stereo8(_) | SampleData::Stereo16(_))
+    }
+
+    /// The frame at `pos`, scaled to 16 bits, with stereo mixed down to mono.
+    pub fn frame(&self, pos: usize) -> i16 {
+        let (left, right) = self.stereo_frame(pos);
+        ((left as i32 + right as i32) >> 1) as i16
+    }
+
+    /// The left and the right of the frame at `pos`, scaled to 16 bits.  Both
+    /// are the same for mono data.
+    pub fn stereo_frame(&self, pos: usize) -> (i16, i16) {
+        let bits8 = |b: u8| (b as i8 as i16) << 8;
+        match self {
+            SampleData::Bits8(data) => (bits8(data[pos]), bits8(data[pos])),
+            SampleData::Bits16(data) => (data[pos], data[pos]),
+            SampleData::Stereo8(data) => (bits8(data[pos][0]), bits8(data[pos][1])),
+            SampleData::Stereo16(data) => (data[pos][0], data[pos][1]),
+        }
+    }
+}
+
+impl Default for SampleData {
+    fn default() -> Self {
+        SampleData::Bits8(vec![])
+    }
+}
+
+/// Rows of notes, one per channel.
+pub type Pattern = Vec<Row>;
+pub type Row = Vec<Note>;
 
 #[derive(Copy, Clone, Debug)]
 pub struct Note {
     pub period: Option<u8>,
     pub sample: Option<u8>,
+    /// What the volume column sets the volume to.
+    pub volume: Option<u8>,
     pub tone_effect: ToneEffect,
     pub volume_effect: VolumeEffect,
     pub misc_effect: MiscEffect,
 }
 
+impl Note {
+    pub const EMPTY: Note = Note {
+        period: None,
+        sample: None,
+        volume: None,
+        tone_effect: ToneEffect::None,
+        volume_effect: VolumeEffect::None,
+        misc_effect: MiscEffect::None,
+    };
+}
+
 #[derive(Copy, Clone, Debug)]
 pub enum ToneEffect {
     None,
@@ -118,6 +313,8 @@ pub enum ToneEffect {
         rate: Option<NonZeroU8>,
         depth: Option<NonZeroU8>,
     },
+    /// Moves the period once, on the row: down to raise the pitch.
+    FinePortamento(i8),
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -125,6 +322,8 @@ pub enum VolumeEffect {
     None,
     SetVolume(u8),
     VolumeSlide(i8),
+    /// Slides the volume once, on the row.
+    FineVolumeSlide(i8),
     Reset,
 }
 
@@ -136,12 +335,23 @@ pub enum MiscEffect {
     PatternBreak(u8),
     RetrigNote(u8),
     SetSpeed(u8),
+    /// Pans the channel, from [`PAN_LEFT`] to [`PAN_RIGHT`].
+    SetPanning(u8),
+    SetGlobalVolume(u8),
+}
+
+/// Writes the name of a note, like `C#3`, in ProTracker's octaves as MODs
+/// name them: the first note of [`PERIODS`] is C-1.  Notes more than an
+/// octave below it get negative octaves.
+fn write_note(f: &mut std::fmt::Formatter<'_>, note: u8) -> std::fmt::Result {
+    let octave = (note / 12) as i8 - (MOD_FIRST_NOTE / 12 - 1) as i8;
+    write!(f, "{}{octave}", KEYS[note as usize % 12])
 }
 
 impl Display for Note {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self.period {
-            Some(period) => write!(f, "{}", NAMES[period as usize])?,
+            Some(period) => write_note(f, period)?,
             None => write!(f, "---")?,
         }
         match self.sample {
@@ -156,6 +366,8 @@ impl Display for Note {
                 MiscEffect::PatternBreak(x) => write!(f, " PB{x:02x} ---")?,
                 MiscEffect::RetrigNote(x) => write!(f, " RN{x:02x} ---")?,
                 MiscEffect::SetSpeed(x) => write!(f, " SS{x:02x} ---")?,
+                MiscEffect::SetPanning(x) => write!(f, " SP{x:02x} ---")?,
+                MiscEffect::SetGlobalVolume(x) => write!(f, " GV{x:02x} ---")?,
             },
             ToneEffect::Arpeggio(a, b) => write!(f, " Ar{a:x}{b:x} ---")?,
             ToneEffect::Portamento { target, speed } => {
@@ -165,7 +377,10 @@ impl Display for Note {
                     None => write!(f, "--")?,
                 }
                 match target {
-                    Some(t) => write!(f, " {}", NAMES[t as usize])?,
+                    Some(t) => {
+                        write!(f, " ")?;
+                        write_note(f, t)?;
+                    }
                     None => write!(f, " ---")?,
                 }
             }
@@ -181,9 +396,19 @@ impl Display for Note {
                 }
                 write!(f, " ---")?;
             }
+            ToneEffect::FinePortamento(x) => {
+                if x < 0 {
+                    write!(f, " FU{:02x} ---", x.unsigned_abs())?
+                } else {
+                    write!(f, " FD{x:02x} ---")?
+                }
+            }
         }
         match self.volume_effect {
-            VolumeEffect::None => write!(f, " ----")?,
+            VolumeEffect::None => match self.volume {
+                Some(v) => write!(f, " Vc{v:02x}")?,
+                None => write!(f, " ----")?,
+            },
             VolumeEffect::SetVolume(v) => write!(f, " Vo{v:02x}")?,
             VolumeEffect::VolumeSlide(v) => {
                 if v < 0 {
@@ -192,6 +417,13 @@ impl Display for Note {
                     write!(f, " VS+{v:x}")?
                 }
             }
+            VolumeEffect::FineVolumeSlide(v) => {
+                if v < 0 {
+                    write!(f, " FV-{:x}", v.unsigned_abs())?
+                } else {
+                    write!(f, " FV+{v:x}")?
+                }
+            }
             VolumeEffect::Reset => write!(f, " VR--")?,
         }
         Ok(())
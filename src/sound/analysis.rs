@@ -0,0 +1,172 @@
+//! Song timing, worked out by following the order list and the flow effects
+//! the way the player does, without mixing any audio.
+
+use std::{
+    collections::{HashMap, HashSet},
+    time::Duration,
+};
+
+use super::{MiscEffect, Mod};
+
+/// The player runs at a fixed 50 ticks per second.
+pub const TICKS_PER_SECOND: u32 = 50;
+
+/// How long `ticks` ticks of playback take.
+pub fn tick_duration(ticks: u32) -> Duration {
+    Duration::from_millis(ticks as u64 * 1000 / TICKS_PER_SECOND as u64)
+}
+
+/// When things happen in a song played from some position on, in ticks.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SongTiming {
+    /// Tick at which each order position is first reached, or None if the
+    /// song never gets there.
+    pub position_starts: Vec<Option<u32>>,
+    /// The same for every row of each order position.
+    pub row_starts: Vec<Vec<Option<u32>>>,
+    /// Ticks played until the song starts repeating itself.
+    pub length: u32,
+    /// Position and row the song continues at once it has played through.
+    pub loop_position: u8,
+    pub loop_row: u8,
+    /// Tick at which the loop point was first played.  The song repeats
+    /// every `length - loop_tick` ticks from there on.
+    pub loop_tick: u32,
+}
+
+/// What a row does to the flow of the song.
+struct RowFlow {
+    speed: Option<u8>,
+    jumps: Vec<u8>,
+    /// Where the song goes if none of the jumps is taken.
+    next: (u8, u8),
+}
+
+impl Mod {
+    /// Timing of the song played from the start.
+    pub fn timing(&self) -> SongTiming {
+        self.timing_from(0)
+    }
+
+    /// Timing of the song played from `position`, as the music is after
+    /// [`super::player::Player::set_music_pos`].
+    pub fn timing_from(&self, position: u8) -> SongTiming {
+        let mut position_starts = vec![None; self.positions.len()];
+        let mut row_starts: Vec<_> = self
+            .positions
+            .iter()
+            .map(|&pattern| vec![None; self.patterns[pattern as usize].len()])
+            .collect();
+        let mut seen = HashMap::new();
+        let (mut position, mut row, mut speed) = (position, 0, self.speed);
+        let mut tick = 0;
+        loop {
+            let flow = self.row_flow(position, row);
+            // a row that sets the speed plays the same whatever came before
+            let at = (position, row, flow.speed.map_or(speed, |_| 0));
+            if let Some(&loop_tick) = seen.get(&at) {
+                return SongTiming {
+                    position_starts,
+                    row_starts,
+                    length: tick,
+                    loop_position: position,
+                    loop_row: row,
+                    loop_tick,
+                };
+            }
+            seen.insert(at, tick);
+            position_starts[position as usize].get_or_insert(tick);
+            row_starts[position as usize][row as usize].get_or_insert(tick);
+            speed = flow.speed.unwrap_or(speed);
+            tick += speed as u32;
+            (position, row) = flow.next();
+        }
+    }
+
+    /// Ticks a jingle at `position` plays for with the given repeat count,
+    /// until it hands back to the music.  None if it never does, which is
+    /// always the case with 0 repeats.  The jingle is assumed to start at
+    /// the speed of the song unless it sets its own.
+    pub fn jingle_length(&self, position: u8, repeats: u8) -> Option<u32> {
+        let mut repeats = repeats;
+        if repeats == 0 {
+            return None;
+        }
+        let mut seen = HashSet::new();
+        let (mut position, mut row, mut speed) = (position, 0, self.speed);
+        let mut tick = 0;
+        loop {
+            let flow = self.row_flow(position, row);
+            // with repeats left to count the same row can come around again,
+            // but not with the same count
+            let at = (position, row, flow.speed.map_or(speed, |_| 0));
+            if !seen.insert((at, repeats)) {
+                return None;
+            }
+            speed = flow.speed.unwrap_or(speed);
+            tick += speed as u32;
+            for _ in &flow.jumps {
+                if repeats == 1 {
+                    return Some(tick);
+                }
+                repeats -= 1;
+            }
+            (position, row) = flow.next();
+        }
+    }
+
+    fn row_flow(&self, position: u8, row: u8) -> RowFlow {
+        let pattern = &self.patterns[self.positions[position as usize] as usize];
+        let mut speed = None;
+        let mut jumps = vec![];
+        let mut pattern_break = None;
+        for note in &pattern[row as usize] {
+            match note.misc_effect {
+                // the player ignores speed 0 too
+                MiscEffect::SetSpeed(s) if s != 0 => speed = Some(s),
+                MiscEffect::PositionJump(target) => {
+                    jumps.push(if (target as usize) < self.positions.len() {
+                        target
+                    } else {
+                        0
+                    })
+                }
+                MiscEffect::PatternBreak(row) => pattern_break = Some(row),
+                _ => {}
+            }
+        }
+        let next = match pattern_break {
+            Some(row) => {
+                let next = self.next_position(position);
+                (next, row.min((self.rows(next) - 1) as u8))
+            }
+            None if row as usize == self.rows(position) - 1 => (self.next_position(position), 0),
+            None => (position, row + 1),
+        };
+        RowFlow { speed, jumps, next }
+    }
+
+    /// How many rows the pattern at `position` has.
+    fn rows(&self, position: u8) -> usize {
+        self.patterns[self.positions[position as usize] as usize].len()
+    }
+
+    fn next_position(&self, position: u8) -> u8 {
+        if position as usize + 1 == self.positions.len() {
+            0
+        } else {
+            position + 1
+        }
+    }
+}
+
+impl RowFlow {
+    /// Where the song goes after the row.  The last position jump in the row
+    /// wins over everything else.
+    fn next(&self) -> (u8, u8) {
+        match self.jumps.last() {
+            Some(&target) => (target, 0),
+            None => self.next,
+        }
+    }
+}
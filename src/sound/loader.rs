@@ -1,5 +1,4 @@
 use std::{
-    array,
     io::{self, Read, Seek},
     num::NonZeroU8,
     str,
@@ -7,17 +6,39 @@ use std::{
 
 use arrayref::array_ref;
 
-use super::{MiscEffect, Mod, Note, Sample, ToneEffect, VolumeEffect, PERIODS};
+use super::{
+    wav, MiscEffect, Mod, Note, Pattern, Sample, SampleData, ToneEffect, VolumeEffect,
+    MOD_FIRST_NOTE, PAN_LEFT, PAN_RIGHT, PERIODS,
+};
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// A MOD file name, cut at the first NUL.
+fn name(buf: &[u8]) -> io::Result<String> {
+    let name = str::from_utf8(buf).map_err(|_| invalid("name is not UTF-8"))?;
+    Ok(name.trim_end_matches('\0').to_string())
+}
+
+impl TryFrom<u32> for Note {
+    type Error = io::Error;
 
-impl From<u32> for Note {
-    fn from(value: u32) -> Self {
+    fn try_from(value: u32) -> io::Result<Self> {
         let period = (value >> 16 & 0xfff) as u16;
         let mut period = if period == 0 {
             None
         } else {
-            Some(PERIODS[0].iter().position(|&x| x == period).unwrap() as u8)
+            let note = PERIODS[0]
+                .iter()
+                .position(|&x| x == period)
+                .ok_or_else(|| invalid(&format!("unknown period {period:03x}")))?;
+            Some(note as u8 + MOD_FIRST_NOTE)
         };
         let sample = (value >> 24 & 0xf0 | value >> 12 & 0xf) as u8;
+        if sample > 31 {
+            return Err(invalid(&format!("sample {sample} out of range")));
+        }
         let mut sample = if sample == 0 { None } else { Some(sample) };
         let effect = value & 0xfff;
         let effect_arg = (effect & 0xff) as u8;
@@ -36,14 +57,14 @@ impl From<u32> for Note {
             1 => {
                 // Portamento Up
                 tone_effect = ToneEffect::Portamento {
-                    target: Some(35),
+                    target: Some(MOD_FIRST_NOTE + 35),
                     speed: NonZeroU8::new(effect_arg),
                 }
             }
             2 => {
                 // Portamento Down
                 tone_effect = ToneEffect::Portamento {
-                    target: Some(0),
+                    target: Some(MOD_FIRST_NOTE),
                     speed: NonZeroU8::new(effect_arg),
                 }
             }
@@ -107,40 +128,47 @@ impl From<u32> for Note {
             0xb => misc_effect = MiscEffect::PositionJump(effect_arg),
             0xc => volume_effect = VolumeEffect::SetVolume(effect_arg),
             0xd => misc_effect = MiscEffect::PatternBreak(effect_arg),
+            // retriggering every 0 ticks does nothing
+            0xe if effect_arg == 0x90 => {}
             0xe if effect_arg_hi == 9 => misc_effect = MiscEffect::RetrigNote(effect_arg_lo),
+            // F00 would stop the song, which the player does not do
+            0xf if effect_arg == 0 => {}
             0xf => misc_effect = MiscEffect::SetSpeed(effect_arg),
-            _ => panic!("unknown effect {effect:03x}"),
+            _ => return Err(invalid(&format!("unknown effect {effect:03x}"))),
         }
-        Note {
+        Ok(Note {
             period,
             sample,
+            volume: None,
             tone_effect,
             volume_effect,
             misc_effect,
-        }
+        })
     }
 }
 
 pub fn load(f: &mut (impl Read + Seek)) -> io::Result<Mod> {
-    let mut name = [0; 20];
-    f.read_exact(&mut name)?;
-    let name = str::from_utf8(&name)
-        .unwrap()
-        .trim_end_matches('\0')
-        .to_string();
+    let mut buf = [0; 20];
+    f.read_exact(&mut buf)?;
+    let song_name = name(&buf)?;
     let mut sample_lens = vec![0];
     let mut samples = vec![Sample {
         name: "".into(),
-        data: vec![],
+        data: SampleData::Bits8(vec![]),
         finetune: 0,
         volume: 0,
         repeat: None,
+        c2spd: None,
+        tuning: 0,
+        panning: None,
     }];
     for _ in 0..31 {
         let mut buf = [0; 30];
         f.read_exact(&mut buf)?;
         sample_lens.push(u16::from_be_bytes(*array_ref![buf, 22, 2]) as usize * 2);
-        assert_eq!(buf[24] & 0xf0, 0);
+        if buf[24] & 0xf0 != 0 {
+            return Err(invalid("finetune out of range"));
+        }
         let rep_pos = u16::from_be_bytes(*array_ref![buf, 26, 2]) as usize * 2;
         let rep_len = u16::from_be_bytes(*array_ref![buf, 28, 2]) as usize * 2;
         let repeat = if rep_pos == 0 && rep_len == 2 {
@@ -149,50 +177,139 @@ pub fn load(f: &mut (impl Read + Seek)) -> io::Result<Mod> {
             Some((rep_pos, rep_len))
         };
         samples.push(Sample {
-            name: str::from_utf8(&buf[..22])
-                .unwrap()
-                .trim_end_matches('\0')
-                .to_string(),
-            data: vec![],
+            name: name(&buf[..22])?,
+            data: SampleData::Bits8(vec![]),
             finetune: buf[24],
             volume: buf[25],
             repeat,
+            c2spd: None,
+            tuning: 0,
+            panning: None,
         });
     }
     let mut buf = [0; 134];
     f.read_exact(&mut buf)?;
     let song_len = buf[0];
     let pos_restart = if buf[1] == 127 { 0 } else { buf[1] };
-    assert!(song_len <= 128);
-    assert!(song_len != 0);
-    assert!(pos_restart < song_len);
+    if song_len == 0 || song_len > 128 {
+        return Err(invalid(&format!("song length {song_len} out of range")));
+    }
+    if pos_restart >= song_len {
+        return Err(invalid("restart position past the end of the song"));
+    }
     let positions = &buf[2..130];
     let num_patterns = positions.iter().copied().max().unwrap() as usize + 1;
     let positions = positions[..song_len as usize].to_vec();
-    let mut patterns = vec![];
+    let mut patterns: Vec<Pattern> = vec![];
     for _ in 0..num_patterns {
         let mut buf = [0; 0x400];
         f.read_exact(&mut buf)?;
-        patterns.push(array::from_fn(|pat| {
-            array::from_fn(|ch| {
-                let pos = pat << 4 | ch << 2;
-                Note::from(u32::from_be_bytes(*array_ref![buf, pos, 4]))
-            })
-        }));
+        patterns.push(
+            (0..0x40)
+                .map(|row| {
+                    (0..4)
+                        .map(|ch| {
+                            let pos = row << 4 | ch << 2;
+                            Note::try_from(u32::from_be_bytes(*array_ref![buf, pos, 4]))
+                        })
+                        .collect()
+                })
+                .collect::<io::Result<_>>()?,
+        );
+    }
+    // jumps past the end of the song start it over, as in XM and S3M
+    for note in patterns.iter_mut().flatten().flatten() {
+        if let MiscEffect::PositionJump(position) = note.misc_effect {
+            if position >= song_len {
+                note.misc_effect = MiscEffect::PositionJump(0);
+            }
+        }
     }
     for (sample, len) in samples.iter_mut().zip(sample_lens) {
-        if len <= 2 {
-            continue;
+        if len > 2 {
+            let mut data = vec![0; len];
+            f.read_exact(&mut data)?;
+            sample.data = SampleData::Bits8(data);
         }
-        let mut data = vec![0; len];
-        f.read_exact(&mut data)?;
-        sample.data = data;
+        // a loop running past the end of the data is cut short there
+        let len = sample.data.len();
+        sample.repeat = sample.repeat.and_then(|(start, repeat_len)| {
+            let end = (start + repeat_len).min(len);
+            (start < end).then_some((start, end - start))
+        });
     }
     Ok(Mod {
-        name,
+        name: song_name,
         samples,
         patterns,
         positions,
         pos_restart,
+        panning: vec![PAN_LEFT, PAN_LEFT, PAN_RIGHT, PAN_RIGHT],
+        speed: 6,
+        global_volume: 0x40,
+        linear_periods: false,
     })
 }
+
+/// Where the sample headers start in a MOD file.
+const SAMPLE_HEADERS: usize = 20;
+const SAMPLE_HEADER_LEN: usize = 30;
+/// Where the patterns start in a MOD file.
+const PATTERNS: usize = 1084;
+
+/// Replaces sample `index` (1 to 31) of the MOD file in `file`, header and
+/// data, leaving everything else as it is.  Returns the new file.
+pub fn replace_sample(file: &[u8], index: u8, sample: &Sample) -> io::Result<Vec<u8>> {
+    if !(1..=31).contains(&index) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("sample {index} out of range"),
+        ));
+    }
+    let SampleData::Bits8(data) = &sample.data else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "only 8-bit mono samples fit in a MOD",
+        ));
+    };
+    if data.len() > wav::MAX_SAMPLE_LEN || data.len() & 1 != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "sample length must be even and at most {} bytes",
+                wav::MAX_SAMPLE_LEN
+            ),
+        ));
+    }
+    if file.len() < PATTERNS {
+        return Err(invalid("truncated MOD header"));
+    }
+    let header = |i: usize| SAMPLE_HEADERS + (i - 1) * SAMPLE_HEADER_LEN;
+    let sample_len =
+        |i: usize| u16::from_be_bytes(*array_ref![file, header(i) + 22, 2]) as usize * 2;
+    let num_patterns = file[952..1080].iter().copied().max().unwrap() as usize + 1;
+    let start =
+        PATTERNS + num_patterns * 0x400 + (1..index as usize).map(sample_len).sum::<usize>();
+    let end = start + sample_len(index as usize);
+    if file.len() < end {
+        return Err(invalid("truncated MOD sample data"));
+    }
+    let mut out = file[..start].to_vec();
+    let hdr = &mut out[header(index as usize)..][..SAMPLE_HEADER_LEN];
+    hdr.fill(0);
+    let mut name_len = sample.name.len().min(22);
+    while !sample.name.is_char_boundary(name_len) {
+        name_len -= 1;
+    }
+    hdr[..name_len].copy_from_slice(&sample.name.as_bytes()[..name_len]);
+    let (rep_pos, rep_len) = sample.repeat.unwrap_or((0, 2));
+    let words = |x: usize| ((x / 2) as u16).to_be_bytes();
+    hdr[22..24].copy_from_slice(&words(data.len()));
+    hdr[24] = sample.finetune & 0xf;
+    hdr[25] = sample.volume.min(0x40);
+    hdr[26..28].copy_from_slice(&words(rep_pos));
+    hdr[28..30].copy_from_slice(&words(rep_len));
+    out.extend_from_slice(data);
+    out.extend_from_slice(&file[end..]);
+    Ok(out)
+}
@@ -0,0 +1,210 @@
+//! Depackers for the packed MOD formats game rips come in.  Each turns the
+//! packed file back into a plain 4-channel MOD file, for [`super::loader`].
+
+mod noisepacker;
+mod player61;
+mod powerpacker;
+mod propacker;
+mod prorunner;
+
+use std::{fmt::Display, io};
+
+use arrayref::array_ref;
+
+use super::PERIODS;
+
+pub use self::powerpacker::{decrunch, TAG as POWERPACKER_TAG};
+
+/// The packers [`detect`] tells apart.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Packer {
+    ProPacker10,
+    ProPacker21,
+    NoisePacker2,
+    NoisePacker3,
+    ProRunner1,
+    ProRunner2,
+    ThePlayer61,
+}
+
+impl Display for Packer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Packer::ProPacker10 => "ProPacker 1.0",
+            Packer::ProPacker21 => "ProPacker 2.1",
+            Packer::NoisePacker2 => "NoisePacker 2",
+            Packer::NoisePacker3 => "NoisePacker 3",
+            Packer::ProRunner1 => "ProRunner 1",
+            Packer::ProRunner2 => "ProRunner 2",
+            Packer::ThePlayer61 => "The Player 6.1",
+        })
+    }
+}
+
+/// Tells which packer made a file, if any.  Only ProRunner and The Player
+/// leave a tag; the rest is told by the layout making sense.  Plain MODs
+/// should be ruled out first, by their tag.
+pub fn detect(file: &[u8]) -> Option<Packer> {
+    if prorunner::test1(file) {
+        Some(Packer::ProRunner1)
+    } else if prorunner::test2(file) {
+        Some(Packer::ProRunner2)
+    } else if player61::test(file, true) {
+        Some(Packer::ThePlayer61)
+    } else if propacker::test21(file) {
+        Some(Packer::ProPacker21)
+    } else if propacker::test10(file) {
+        Some(Packer::ProPacker10)
+    } else if noisepacker::test(file, false) {
+        Some(Packer::NoisePacker2)
+    } else if noisepacker::test(file, true) {
+        Some(Packer::NoisePacker3)
+    } else if player61::test(file, false) {
+        Some(Packer::ThePlayer61)
+    } else {
+        None
+    }
+}
+
+/// Turns a file packed by `packer` back into a plain MOD file.
+pub fn depack(file: &[u8], packer: Packer) -> io::Result<Vec<u8>> {
+    let parts = match packer {
+        Packer::ProPacker10 => propacker::depack10(file),
+        Packer::ProPacker21 => propacker::depack21(file),
+        Packer::NoisePacker2 => noisepacker::depack(file, false),
+        Packer::NoisePacker3 => noisepacker::depack(file, true),
+        Packer::ProRunner1 => prorunner::depack1(file),
+        Packer::ProRunner2 => prorunner::depack2(file),
+        Packer::ThePlayer61 => player61::depack(file),
+    }?;
+    Ok(parts.encode())
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn u16_at(buf: &[u8], pos: usize) -> u16 {
+    u16::from_be_bytes(*array_ref![buf, pos, 2])
+}
+
+fn u32_at(buf: &[u8], pos: usize) -> u32 {
+    u32::from_be_bytes(*array_ref![buf, pos, 4])
+}
+
+/// `len` bytes of the file from `pos`, or an error if the file ends first.
+fn chunk<'a>(file: &'a [u8], pos: usize, len: usize, what: &str) -> io::Result<&'a [u8]> {
+    file.get(pos..pos + len)
+        .ok_or_else(|| invalid(&format!("truncated {what}")))
+}
+
+/// A MOD sample header, lengths in words, and the sample data.
+#[derive(Clone, Default)]
+struct SampleHeader {
+    len: u16,
+    finetune: u8,
+    volume: u8,
+    repeat_start: u16,
+    repeat_len: u16,
+    data: Vec<u8>,
+}
+
+impl SampleHeader {
+    /// A header that makes sense in a MOD, for telling packed files apart.
+    fn is_sane(&self) -> bool {
+        self.finetune <= 0xf
+            && self.volume <= 0x40
+            && self.len <= 0x8000
+            && self.repeat_start as u32 + self.repeat_len as u32 <= self.len as u32 + 1
+    }
+
+    /// Fills in the data from `pos`, padded with silence if the file ends
+    /// first.  Returns where the data ends.
+    fn read_data(&mut self, file: &[u8], pos: usize) -> usize {
+        let end = pos + self.len as usize * 2;
+        self.data = file
+            .get(pos.min(file.len())..end.min(file.len()))
+            .unwrap_or(&[])
+            .to_vec();
+        self.data.resize(self.len as usize * 2, 0);
+        end
+    }
+}
+
+/// A 4-channel MOD pattern, as cells of 4 bytes, row by row.
+type Pattern = [[u8; 4]; 0x100];
+
+const EMPTY_PATTERN: Pattern = [[0; 4]; 0x100];
+
+/// The parts of a plain MOD, as a depacker puts them together.
+#[derive(Default)]
+struct ModParts {
+    /// Up to 31.
+    samples: Vec<SampleHeader>,
+    positions: Vec<u8>,
+    patterns: Vec<Pattern>,
+}
+
+impl ModParts {
+    /// Writes out the MOD file, with as many patterns as the positions use.
+    fn encode(&self) -> Vec<u8> {
+        let mut out = vec![0; 20];
+        for i in 0..31 {
+            let sample = self.samples.get(i).cloned().unwrap_or_default();
+            out.extend_from_slice(&[0; 22]);
+            out.extend_from_slice(&sample.len.to_be_bytes());
+            out.extend_from_slice(&[sample.finetune, sample.volume]);
+            out.extend_from_slice(&sample.repeat_start.to_be_bytes());
+            // a loop of one word is none, and a loop ends with the sample
+            let repeat_len = sample
+                .repeat_len
+                .min(sample.len.saturating_sub(sample.repeat_start));
+            out.extend_from_slice(&repeat_len.max(1).to_be_bytes());
+        }
+        out.push(self.positions.len() as u8);
+        out.push(0x7f);
+        let mut positions = self.positions.clone();
+        positions.resize(128, 0);
+        out.extend_from_slice(&positions);
+        out.extend_from_slice(b"M.K.");
+        let patterns = self.positions.iter().max().map_or(1, |&p| p as usize + 1);
+        for i in 0..patterns {
+            for cell in self.patterns.get(i).unwrap_or(&EMPTY_PATTERN) {
+                out.extend_from_slice(cell);
+            }
+        }
+        for sample in &self.samples {
+            // the loader only reads samples longer than a word
+            if sample.len > 1 {
+                out.extend_from_slice(&sample.data);
+            }
+        }
+        out
+    }
+}
+
+/// A MOD cell from a note number, 1 for C-1 to 36 for B-3 and 0 for none,
+/// and the rest as in a MOD.
+fn cell(note: u8, sample: u8, effect: u8, arg: u8) -> io::Result<[u8; 4]> {
+    let period = match note {
+        0 => 0,
+        1..=36 => PERIODS[0][note as usize - 1],
+        _ => return Err(invalid(&format!("note {note} out of range"))),
+    };
+    if sample > 31 {
+        return Err(invalid(&format!("sample {sample} out of range")));
+    }
+    Ok([
+        sample & 0xf0 | (period >> 8) as u8,
+        period as u8,
+        sample << 4 | effect & 0xf,
+        arg,
+    ])
+}
+
+/// The usual 3-byte packed note: the note number and the high bit of the
+/// sample, the rest of the sample and the effect, then its argument.
+fn packed_cell(bytes: [u8; 3]) -> io::Result<[u8; 4]> {
+    let [a, b, arg] = bytes;
+    cell(a >> 1, (a & 1) << 4 | b >> 4, b & 0xf, arg)
+}
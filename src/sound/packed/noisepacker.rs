@@ -0,0 +1,146 @@
+//! NoisePacker 2 and 3, which store patterns as tracks shared between
+//! patterns.  Version 3 also leaves out the empty rows of a track.
+//!
+//! The header has the number of samples times 16 plus 0xc, the size of the
+//! position list, the track list and the track data, then the samples, 16
+//! bytes each.  Positions are offsets into the track list, which holds four
+//! track offsets a pattern, the last channel first.  Volume slides keep their
+//! speed as a signed byte.
+
+use std::io;
+
+use super::{chunk, invalid, packed_cell, u16_at, ModParts, SampleHeader, EMPTY_PATTERN};
+
+/// The header, samples, positions and track list of a NoisePacker file.
+struct Layout {
+    samples: Vec<SampleHeader>,
+    positions: Vec<usize>,
+    /// The track offsets of each pattern, first channel first.
+    tracks: Vec<[usize; 4]>,
+    /// Where the track data starts, and how long it is.
+    data: (usize, usize),
+}
+
+fn layout(file: &[u8], np3: bool) -> Option<Layout> {
+    let hdr = file.get(..8)?;
+    let (samples, positions) = (u16_at(hdr, 0), u16_at(hdr, 2) as usize);
+    let (track_list, track_data) = (u16_at(hdr, 4) as usize, u16_at(hdr, 6) as usize);
+    if samples & 0xf != 0xc || !(1..=31).contains(&(samples >> 4)) {
+        return None;
+    }
+    if positions == 0 || positions % 2 != 0 || positions > 0x100 {
+        return None;
+    }
+    if track_list == 0 || track_list % 8 != 0 || track_data == 0 {
+        return None;
+    }
+    let headers = file.get(8..8 + (samples >> 4) as usize * 16)?;
+    let samples: Vec<SampleHeader> = headers
+        .chunks(16)
+        .map(|hdr| {
+            let (finetune, volume, rest) = if np3 {
+                (hdr[0], hdr[1], &hdr[2..])
+            } else {
+                (hdr[6], hdr[7], hdr)
+            };
+            let len = u16_at(rest, 4);
+            SampleHeader {
+                len,
+                finetune,
+                volume,
+                repeat_start: u16_at(hdr, 14) / 2,
+                repeat_len: u16_at(hdr, 12),
+                data: vec![],
+            }
+        })
+        .collect();
+    if !samples.iter().all(SampleHeader::is_sane) {
+        return None;
+    }
+    let at = 8 + headers.len();
+    let list = file.get(at..at + positions)?;
+    let positions: Vec<usize> = list.chunks(2).map(|p| u16_at(p, 0) as usize).collect();
+    if positions.iter().any(|&p| p % 8 != 0 || p >= track_list) {
+        return None;
+    }
+    let at = at + list.len();
+    let tracks: Vec<[usize; 4]> = file
+        .get(at..at + track_list)?
+        .chunks(8)
+        .map(|t| [6, 4, 2, 0].map(|i| u16_at(t, i) as usize))
+        .collect();
+    if tracks.iter().flatten().any(|&t| t >= track_data) {
+        return None;
+    }
+    if !np3 && (track_data % 0xc0 != 0 || tracks.iter().flatten().any(|&t| t % 0xc0 != 0)) {
+        return None;
+    }
+    let data = (at + track_list, track_data);
+    let sample_size: usize = samples.iter().map(|s| s.len as usize * 2).sum();
+    if file.len() < data.0 + data.1 + sample_size {
+        return None;
+    }
+    Some(Layout {
+        samples,
+        positions: positions.into_iter().map(|p| p / 8).collect(),
+        tracks,
+        data,
+    })
+}
+
+/// Decodes the 64 rows of the track at `pos`.
+fn track(data: &[u8], mut pos: usize, np3: bool) -> io::Result<Vec<[u8; 4]>> {
+    let mut rows = vec![];
+    while rows.len() < 0x40 {
+        let first = *data.get(pos).ok_or_else(|| invalid("truncated track"))?;
+        if np3 && first >= 0x80 {
+            // a run of empty rows
+            rows.resize(rows.len() + 0x100 - first as usize, [0; 4]);
+            pos += 1;
+            continue;
+        }
+        let [a, b, arg] = *chunk(data, pos, 3, "track")? else {
+            unreachable!()
+        };
+        pos += 3;
+        let arg = match b & 0xf {
+            5 | 6 | 0xa if arg >= 0x80 => (0x100 - arg as u16) as u8 & 0xf,
+            5 | 6 | 0xa => arg << 4,
+            _ => arg,
+        };
+        rows.push(packed_cell([a, b, arg])?);
+    }
+    if rows.len() > 0x40 {
+        return Err(invalid("track runs past the last row"));
+    }
+    Ok(rows)
+}
+
+pub(super) fn test(file: &[u8], np3: bool) -> bool {
+    layout(file, np3).is_some() && depack(file, np3).is_ok()
+}
+
+pub(super) fn depack(file: &[u8], np3: bool) -> io::Result<ModParts> {
+    let layout = layout(file, np3).ok_or_else(|| invalid("not a NoisePacker file"))?;
+    let data = &file[layout.data.0..layout.data.0 + layout.data.1];
+    let mut patterns = vec![];
+    for offsets in &layout.tracks {
+        let mut pattern = EMPTY_PATTERN;
+        for (channel, &offset) in offsets.iter().enumerate() {
+            for (row, cell) in track(data, offset, np3)?.into_iter().enumerate() {
+                pattern[row * 4 + channel] = cell;
+            }
+        }
+        patterns.push(pattern);
+    }
+    let mut samples = layout.samples;
+    let mut pos = layout.data.0 + layout.data.1;
+    for sample in &mut samples {
+        pos = sample.read_data(file, pos);
+    }
+    Ok(ModParts {
+        samples,
+        positions: layout.positions.iter().map(|&p| p as u8).collect(),
+        patterns,
+    })
+}
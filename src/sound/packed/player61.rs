@@ -0,0 +1,231 @@
+//! The Player 6.1, which packs the notes of its tracks and lets them repeat
+//! earlier stretches of the track.
+//!
+//! After an optional "P61A" tag, the header has where the sample data starts,
+//! the number of patterns and samples, and the samples, 6 bytes each: the
+//! length, finetune, volume and where the loop starts, in words.  A negative
+//! length shares the data of an earlier sample.  Four track offsets a
+//! pattern follow, then the positions, ending in 0xff, then the tracks.
+//!
+//! A note is 3 bytes as usual, 2 for an effect alone, and 1 for an empty row.
+//! If its top bit is set, a byte follows saying what comes after it: empty
+//! rows, the same note again, or rows taken from earlier in the track.
+
+use std::io;
+
+use super::{cell, chunk, invalid, packed_cell, u16_at, ModParts, SampleHeader, EMPTY_PATTERN};
+
+/// The tag the file may start with.
+const TAG: &[u8] = b"P61A";
+
+/// The header of a P61 file, offsets from the start of the file.
+struct Layout {
+    /// The samples, and the number of the sample each shares its data with,
+    /// if any.
+    samples: Vec<(SampleHeader, Option<usize>)>,
+    tracks: Vec<[usize; 4]>,
+    positions: Vec<u8>,
+    /// Where the track data and the sample data start.
+    track_data: usize,
+    sample_data: usize,
+}
+
+fn layout(file: &[u8]) -> io::Result<Layout> {
+    let base = if file.starts_with(TAG) { TAG.len() } else { 0 };
+    let hdr = chunk(file, base, 4, "P61 header")?;
+    let sample_data = base + u16_at(hdr, 0) as usize;
+    let (num_patterns, num_samples) = (hdr[2] as usize, hdr[3]);
+    if num_samples & 0xc0 != 0 {
+        return Err(invalid("packed P61 samples are not supported"));
+    }
+    let num_samples = num_samples as usize;
+    if !(1..=31).contains(&num_samples) || num_patterns == 0 {
+        return Err(invalid("bad P61 sample or pattern count"));
+    }
+    let mut pos = base + 4;
+    let mut samples: Vec<(SampleHeader, Option<usize>)> = vec![];
+    for i in 0..num_samples {
+        let hdr = chunk(file, pos, 6, "P61 samples")?;
+        pos += 6;
+        if hdr[2] & 0x80 != 0 {
+            return Err(invalid("packed P61 samples are not supported"));
+        }
+        let len = u16_at(hdr, 0) as i16;
+        let shared = if len < 0 {
+            let other = len.unsigned_abs() as usize;
+            if other > i {
+                return Err(invalid(&format!("sample {} shares a later one", i + 1)));
+            }
+            Some(other - 1)
+        } else {
+            None
+        };
+        let len = match shared {
+            Some(other) => samples[other].0.len,
+            None => len as u16,
+        };
+        let (repeat_start, repeat_len) = match u16_at(hdr, 4) {
+            0xffff => (0, 1),
+            start if start < len => (start, len - start),
+            _ => return Err(invalid(&format!("sample {} loops past its end", i + 1))),
+        };
+        let header = SampleHeader {
+            len,
+            finetune: hdr[2],
+            volume: hdr[3],
+            repeat_start,
+            repeat_len,
+            data: vec![],
+        };
+        if !header.is_sane() {
+            return Err(invalid(&format!("bad sample {}", i + 1)));
+        }
+        samples.push((header, shared));
+    }
+    let list = chunk(file, pos, num_patterns * 8, "P61 patterns")?;
+    pos += list.len();
+    let tracks: Vec<[usize; 4]> = list
+        .chunks(8)
+        .map(|t| [0, 2, 4, 6].map(|i| u16_at(t, i) as usize))
+        .collect();
+    let mut positions = vec![];
+    loop {
+        match file.get(pos).copied() {
+            Some(0xff) => break,
+            Some(p) if (p as usize) < num_patterns && positions.len() < 128 => positions.push(p),
+            _ => return Err(invalid("bad P61 positions")),
+        }
+        pos += 1;
+    }
+    let track_data = pos + 1;
+    if positions.is_empty() || sample_data < track_data || sample_data > file.len() {
+        return Err(invalid("bad P61 header"));
+    }
+    if tracks
+        .iter()
+        .flatten()
+        .any(|&t| track_data + t >= sample_data)
+    {
+        return Err(invalid("P61 track out of the track data"));
+    }
+    Ok(Layout {
+        samples,
+        tracks,
+        positions,
+        track_data,
+        sample_data,
+    })
+}
+
+/// Reads the note at `pos`, returning the cell, where it ends and the byte
+/// saying what follows it, if any.
+fn note(data: &[u8], pos: usize) -> io::Result<([u8; 4], usize, Option<u8>)> {
+    let byte = |at: usize| {
+        data.get(at)
+            .copied()
+            .ok_or_else(|| invalid("truncated P61 track"))
+    };
+    let first = byte(pos)?;
+    let (cell, len) = match first & 0x70 {
+        0x70 => ([0; 4], 1),
+        0x60 => (cell(0, 0, first & 0xf, byte(pos + 1)?)?, 2),
+        _ => (
+            packed_cell([first & 0x7f, byte(pos + 1)?, byte(pos + 2)?])?,
+            3,
+        ),
+    };
+    if first & 0x80 != 0 {
+        Ok((cell, pos + len + 1, Some(byte(pos + len)?)))
+    } else {
+        Ok((cell, pos + len, None))
+    }
+}
+
+/// Decodes the 64 rows of the track starting at `pos`.
+fn track(data: &[u8], mut pos: usize) -> io::Result<Vec<[u8; 4]>> {
+    let mut rows = vec![];
+    // from where a stretch is repeated, and how many rows of it are left
+    let mut repeat: Option<(usize, usize)> = None;
+    while rows.len() < 0x40 {
+        let (cell, next, info) = match &mut repeat {
+            Some((at, left)) => {
+                let (cell, next, info) = note(data, *at)?;
+                *at = next;
+                *left -= 1;
+                (cell, pos, info)
+            }
+            None => note(data, pos)?,
+        };
+        pos = next;
+        rows.push(cell);
+        if let Some(info) = info {
+            let count = (info & 0x3f) as usize;
+            match info >> 6 {
+                0 => rows.resize(rows.len() + count, [0; 4]),
+                1 => rows.resize(rows.len() + count, cell),
+                _ if repeat.is_some() => return Err(invalid("nested P61 repeat")),
+                long => {
+                    let offset = if long == 3 {
+                        let offset = u16_at(chunk(data, pos, 2, "P61 track")?, 0);
+                        pos += 2;
+                        offset as usize
+                    } else {
+                        pos += 1;
+                        *data
+                            .get(pos - 1)
+                            .ok_or_else(|| invalid("truncated P61 track"))?
+                            as usize
+                    };
+                    let at = pos
+                        .checked_sub(offset)
+                        .ok_or_else(|| invalid("P61 repeat before the track data"))?;
+                    repeat = Some((at, count));
+                }
+            }
+        }
+        if matches!(repeat, Some((_, 0))) {
+            repeat = None;
+        }
+    }
+    if rows.len() > 0x40 {
+        return Err(invalid("P61 track runs past the last row"));
+    }
+    Ok(rows)
+}
+
+pub(super) fn test(file: &[u8], tagged: bool) -> bool {
+    if tagged {
+        file.starts_with(TAG)
+    } else {
+        depack(file).is_ok()
+    }
+}
+
+pub(super) fn depack(file: &[u8]) -> io::Result<ModParts> {
+    let layout = layout(file)?;
+    let data = &file[layout.track_data..layout.sample_data];
+    let mut patterns = vec![];
+    for offsets in &layout.tracks {
+        let mut pattern = EMPTY_PATTERN;
+        for (channel, &offset) in offsets.iter().enumerate() {
+            for (row, cell) in track(data, offset)?.into_iter().enumerate() {
+                pattern[row * 4 + channel] = cell;
+            }
+        }
+        patterns.push(pattern);
+    }
+    let mut samples: Vec<SampleHeader> = vec![];
+    let mut pos = layout.sample_data;
+    for (mut sample, shared) in layout.samples {
+        match shared {
+            Some(other) => sample.data = samples[other].data.clone(),
+            None => pos = sample.read_data(file, pos),
+        }
+        samples.push(sample);
+    }
+    Ok(ModParts {
+        samples,
+        positions: layout.positions,
+        patterns,
+    })
+}
@@ -0,0 +1,116 @@
+//! PowerPacker, which crunches whole files.  Any module may be inside.
+//!
+//! After the tag come four offset widths, then the crunched bits, read from
+//! the end backwards, and last the length of the decrunched file, 24 bits,
+//! and how many bits to skip at the start.
+
+use std::io;
+
+use super::invalid;
+
+/// The tag a PowerPacker file starts with.
+pub const TAG: &[u8] = b"PP20";
+
+/// Reads bits from the end of the data backwards, lowest first.
+struct Bits<'a> {
+    data: &'a [u8],
+    buffer: u32,
+    left: u32,
+}
+
+impl Bits<'_> {
+    fn read(&mut self, count: u32) -> io::Result<u32> {
+        while self.left < count {
+            let (&last, rest) = self
+                .data
+                .split_last()
+                .ok_or_else(|| invalid("PowerPacker data ends early"))?;
+            self.data = rest;
+            self.buffer |= (last as u32) << self.left;
+            self.left += 8;
+        }
+        let mut val = 0;
+        for _ in 0..count {
+            val = val << 1 | self.buffer & 1;
+            self.buffer >>= 1;
+        }
+        self.left -= count;
+        Ok(val)
+    }
+}
+
+/// Decrunches a PowerPacker file.
+pub fn decrunch(file: &[u8]) -> io::Result<Vec<u8>> {
+    if !file.starts_with(TAG) || file.len() < 12 {
+        return Err(invalid("not a PowerPacker file"));
+    }
+    let widths = &file[4..8];
+    if widths.iter().any(|&w| w > 16) {
+        return Err(invalid("bad PowerPacker offset widths"));
+    }
+    let trailer = &file[file.len() - 4..];
+    let len = (trailer[0] as usize) << 16 | (trailer[1] as usize) << 8 | trailer[2] as usize;
+    let mut bits = Bits {
+        data: &file[8..file.len() - 4],
+        buffer: 0,
+        left: 0,
+    };
+    for _ in 0..trailer[3] {
+        bits.read(1)?;
+    }
+    // written from the end
+    let mut out = vec![0; len];
+    let mut pos = len;
+    while pos > 0 {
+        if bits.read(1)? == 0 {
+            let mut count = 1;
+            loop {
+                let more = bits.read(2)?;
+                count += more as usize;
+                if more != 3 {
+                    break;
+                }
+            }
+            if count > pos {
+                return Err(invalid("PowerPacker literal runs past the start"));
+            }
+            for _ in 0..count {
+                pos -= 1;
+                out[pos] = bits.read(8)? as u8;
+            }
+            if pos == 0 {
+                break;
+            }
+        }
+        let kind = bits.read(2)?;
+        let mut width = widths[kind as usize] as u32;
+        if kind == 3 && bits.read(1)? == 0 {
+            width = 7;
+        }
+        let offset = bits.read(width)? as usize;
+        let mut count = kind as usize + 2;
+        if kind == 3 {
+            loop {
+                let more = bits.read(3)?;
+                count += more as usize;
+                if more != 7 {
+                    break;
+                }
+            }
+        }
+        copy(&mut out, &mut pos, offset, count)?;
+    }
+    Ok(out)
+}
+
+/// Copies `count` bytes from `offset + 1` bytes after `pos`, going backwards.
+fn copy(out: &mut [u8], pos: &mut usize, offset: usize, count: usize) -> io::Result<()> {
+    if *pos + offset >= out.len() || count > *pos {
+        return Err(invalid("PowerPacker match out of range"));
+    }
+    for _ in 0..count {
+        *pos -= 1;
+        out[*pos] = out[*pos + 1 + offset];
+    }
+    Ok(())
+}
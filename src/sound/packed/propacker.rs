@@ -0,0 +1,144 @@
+//! ProPacker, which stores patterns as tracks, one per channel and position.
+//! Version 1.0 keeps the notes in the tracks; 2.1 has each track refer to
+//! notes in a table, so that each note is stored once.
+//!
+//! Both start with 31 samples of 8 bytes (length, finetune, volume, loop
+//! start and loop length, lengths in words), then the song length, the
+//! restart byte and the track of each position for each channel, 128 a
+//! channel.
+
+use std::io;
+
+use super::{chunk, invalid, u16_at, u32_at, ModParts, Pattern, SampleHeader, EMPTY_PATTERN};
+use crate::sound::PERIODS;
+
+/// Where the tracks start.
+const TRACKS: usize = 762;
+
+fn samples(file: &[u8]) -> Vec<SampleHeader> {
+    (0..31)
+        .map(|i| {
+            let hdr = &file[i * 8..i * 8 + 8];
+            SampleHeader {
+                len: u16_at(hdr, 0),
+                finetune: hdr[2],
+                volume: hdr[3],
+                repeat_start: u16_at(hdr, 4),
+                repeat_len: u16_at(hdr, 6),
+                data: vec![],
+            }
+        })
+        .collect()
+}
+
+/// The highest track the song uses, if the header makes sense.
+fn header(file: &[u8]) -> Option<usize> {
+    if file.len() < TRACKS {
+        return None;
+    }
+    let samples = samples(file);
+    if !samples.iter().all(SampleHeader::is_sane) || samples.iter().all(|s| s.len == 0) {
+        return None;
+    }
+    if !(1..=128).contains(&file[248]) {
+        return None;
+    }
+    file[250..TRACKS].iter().max().map(|&t| t as usize)
+}
+
+fn sample_size(file: &[u8]) -> usize {
+    samples(file).iter().map(|s| s.len as usize * 2).sum()
+}
+
+/// A MOD cell as stored, checked to have a period the loader knows.
+fn mod_cell(bytes: &[u8]) -> io::Result<[u8; 4]> {
+    let period = u16::from_be_bytes([bytes[0] & 0xf, bytes[1]]);
+    if period != 0 && !PERIODS[0].contains(&period) {
+        return Err(invalid(&format!("period {period} is not a note")));
+    }
+    Ok([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+pub(super) fn test21(file: &[u8]) -> bool {
+    let Some(max) = header(file) else {
+        return false;
+    };
+    let table = TRACKS + (max + 1) * 0x80;
+    let Some(len) = file.get(table..table + 4).map(|b| u32_at(b, 0) as usize) else {
+        return false;
+    };
+    len != 0
+        && len % 4 == 0
+        && file.len() >= table + 4 + len + sample_size(file)
+        && (0..(max + 1) * 0x40).all(|i| (u16_at(file, TRACKS + i * 2) as usize) * 4 < len)
+}
+
+pub(super) fn test10(file: &[u8]) -> bool {
+    let Some(max) = header(file) else {
+        return false;
+    };
+    let end = TRACKS + (max + 1) * 0x100;
+    file.len() >= end + sample_size(file)
+        && file[TRACKS..end].chunks(4).all(|c| mod_cell(c).is_ok())
+}
+
+/// Puts the patterns together from the tracks, with `note` giving the
+/// cells of a track.
+fn parts(
+    file: &[u8],
+    mut note: impl FnMut(usize, usize) -> io::Result<[u8; 4]>,
+    samples_at: usize,
+) -> io::Result<ModParts> {
+    let len = file[248] as usize;
+    let mut patterns = vec![];
+    for position in 0..len {
+        let mut pattern: Pattern = EMPTY_PATTERN;
+        for channel in 0..4 {
+            let track = file[250 + channel * 0x80 + position] as usize;
+            for row in 0..0x40 {
+                pattern[row * 4 + channel] = note(track, row)?;
+            }
+        }
+        patterns.push(pattern);
+    }
+    let mut samples = samples(file);
+    let mut pos = samples_at;
+    for sample in &mut samples {
+        pos = sample.read_data(file, pos);
+    }
+    Ok(ModParts {
+        samples,
+        // every position has a pattern of its own
+        positions: (0..len as u8).collect(),
+        patterns,
+    })
+}
+
+pub(super) fn depack10(file: &[u8]) -> io::Result<ModParts> {
+    let max = header(file).ok_or_else(|| invalid("not a ProPacker 1.0 file"))?;
+    let tracks = chunk(file, TRACKS, (max + 1) * 0x100, "tracks")?;
+    parts(
+        file,
+        |track, row| mod_cell(&tracks[(track * 0x40 + row) * 4..]),
+        TRACKS + tracks.len(),
+    )
+}
+
+pub(super) fn depack21(file: &[u8]) -> io::Result<ModParts> {
+    let max = header(file).ok_or_else(|| invalid("not a ProPacker 2.1 file"))?;
+    let refs = chunk(file, TRACKS, (max + 1) * 0x80, "tracks")?;
+    let at = TRACKS + refs.len();
+    let len = u32_at(chunk(file, at, 4, "note table")?, 0) as usize;
+    let notes = chunk(file, at + 4, len, "note table")?;
+    parts(
+        file,
+        |track, row| {
+            let i = u16_at(refs, (track * 0x40 + row) * 2) as usize * 4;
+            notes
+                .get(i..i + 4)
+                .ok_or_else(|| invalid("note out of the table"))
+                .and_then(mod_cell)
+        },
+        at + 4 + len,
+    )
+}
@@ -0,0 +1,129 @@
+//! ProRunner 1 and 2.  Version 1 is a MOD with notes stored by number rather
+//! than period, and "SNT." for a tag.  Version 2 starts with "SNT!" and packs
+//! the patterns.
+
+use std::io;
+
+use super::{
+    cell, chunk, invalid, packed_cell, u16_at, u32_at, ModParts, SampleHeader, EMPTY_PATTERN,
+};
+
+/// Where the tag sits in ProRunner 1, as in a MOD.
+const TAG1: usize = 1080;
+
+/// Where the patterns start in ProRunner 2.
+const PATTERNS2: usize = 770;
+
+pub(super) fn test1(file: &[u8]) -> bool {
+    file.get(TAG1..TAG1 + 4) == Some(b"SNT.")
+}
+
+pub(super) fn test2(file: &[u8]) -> bool {
+    file.starts_with(b"SNT!") && file.len() >= PATTERNS2
+}
+
+fn check(samples: &[SampleHeader]) -> io::Result<()> {
+    if samples.iter().all(SampleHeader::is_sane) {
+        Ok(())
+    } else {
+        Err(invalid("bad ProRunner sample"))
+    }
+}
+
+/// The positions, the song length long, and how many patterns they use.
+fn positions(len: u8, list: &[u8]) -> io::Result<(Vec<u8>, usize)> {
+    if !(1..=128).contains(&len) {
+        return Err(invalid("bad song length"));
+    }
+    let positions = list[..len as usize].to_vec();
+    let patterns = *positions.iter().max().unwrap() as usize + 1;
+    Ok((positions, patterns))
+}
+
+pub(super) fn depack1(file: &[u8]) -> io::Result<ModParts> {
+    let hdr = chunk(file, 0, TAG1 + 4, "ProRunner header")?;
+    let mut samples: Vec<SampleHeader> = (0..31)
+        .map(|i| {
+            let hdr = &hdr[20 + i * 30 + 22..];
+            SampleHeader {
+                len: u16_at(hdr, 0),
+                finetune: hdr[2],
+                volume: hdr[3],
+                repeat_start: u16_at(hdr, 4),
+                repeat_len: u16_at(hdr, 6),
+                data: vec![],
+            }
+        })
+        .collect();
+    check(&samples)?;
+    let (positions, count) = positions(hdr[950], &hdr[952..1080])?;
+    let data = chunk(file, TAG1 + 4, count * 0x400, "patterns")?;
+    let mut patterns = vec![];
+    for stored in data.chunks(0x400) {
+        let mut pattern = EMPTY_PATTERN;
+        for (cell_out, c) in pattern.iter_mut().zip(stored.chunks(4)) {
+            *cell_out = cell(c[1], c[0], c[2], c[3])?;
+        }
+        patterns.push(pattern);
+    }
+    let mut pos = TAG1 + 4 + data.len();
+    for sample in &mut samples {
+        pos = sample.read_data(file, pos);
+    }
+    Ok(ModParts {
+        samples,
+        positions,
+        patterns,
+    })
+}
+
+pub(super) fn depack2(file: &[u8]) -> io::Result<ModParts> {
+    let hdr = chunk(file, 0, PATTERNS2, "ProRunner header")?;
+    let samples_at = u32_at(hdr, 4) as usize;
+    let mut samples: Vec<SampleHeader> = (0..31)
+        .map(|i| {
+            let hdr = &hdr[8 + i * 8..];
+            SampleHeader {
+                len: u16_at(hdr, 0),
+                finetune: hdr[2],
+                volume: hdr[3],
+                repeat_start: u16_at(hdr, 4),
+                repeat_len: u16_at(hdr, 6),
+                data: vec![],
+            }
+        })
+        .collect();
+    check(&samples)?;
+    let (positions, count) = positions(hdr[256], &hdr[258..386])?;
+    let mut pos = PATTERNS2;
+    let mut next = || {
+        let byte = file.get(pos).copied();
+        pos += 1;
+        byte.ok_or_else(|| invalid("truncated pattern"))
+    };
+    let mut patterns = vec![];
+    let mut last = [[0; 4]; 4];
+    for _ in 0..count {
+        let mut pattern = EMPTY_PATTERN;
+        for (i, cell) in pattern.iter_mut().enumerate() {
+            let channel = i % 4;
+            *cell = match next()? {
+                0x80 => [0; 4],
+                // the note before on the channel again
+                0xc0 => last[channel],
+                a => packed_cell([a, next()?, next()?])?,
+            };
+            last[channel] = *cell;
+        }
+        patterns.push(pattern);
+    }
+    let mut pos = samples_at;
+    for sample in &mut samples {
+        pos = sample.read_data(file, pos);
+    }
+    Ok(ModParts {
+        samples,
+        positions,
+        patterns,
+    })
+}
@@ -1,36 +1,150 @@
-use std::sync::{
-    atomic::{AtomicU32, Ordering},
-    Arc,
+mod command;
+mod event;
+mod fade;
+mod null;
+mod output;
+mod record;
+mod snapshot;
+
+use std::{
+    array, mem,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        mpsc::{self, Receiver, SyncSender, TryIter, TrySendError},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+use self::{
+    command::{Command, SfxRequest},
+    fade::{Fader, FADE_ONE},
+    null::NullOutput,
+    output::Output,
+};
+use super::{
+    analysis::{SongTiming, TICKS_PER_SECOND},
+    sfx::SfxBank,
+    MiscEffect, Mod, Note, Sample, SampleData, ToneEffect, VolumeEffect, LAST_NOTE, MAX_CHANNELS,
+    MOD_FIRST_NOTE, PAN_LEFT, PAN_RIGHT, PERIODS,
 };
 
-use cpal::{
-    traits::{DeviceTrait, HostTrait},
-    BufferSize, SampleRate, Stream, StreamConfig,
+pub use self::command::{
+    CommandError, JingleMode, JingleRequest, JingleStart, JingleState, JumpTarget, RequestOutcome,
+    Transition, MAX_PRIORITY, MAX_REPEATS,
 };
+pub use self::event::{PlayerEvent, RowFlow, RowTrace};
+pub use self::output::OutputError;
+pub use self::record::{replay, Call, RecordedCall};
+pub use self::snapshot::{ActiveEffect, ChannelLevel, ChannelSnapshot};
 
-use super::{MiscEffect, Mod, Note, ToneEffect, VolumeEffect, PERIODS};
+/// How many control calls can be in flight to the audio thread at once.
+const COMMAND_QUEUE_LEN: usize = 64;
+/// How many replaced modules may wait for the reaper thread, and so how many
+/// module loads may be in flight.
+const GARBAGE_QUEUE_LEN: usize = 16;
+/// How many events the audio thread buffers before dropping new ones.
+const EVENT_QUEUE_LEN: usize = 256;
+/// How many recorded calls the audio thread buffers before dropping new ones.
+const CALL_LOG_LEN: usize = 1024;
+/// How many ticks of a long seek are fast-forwarded in the time of one tick
+/// of output, which stays silent meanwhile.  Shorter seeks happen at once.
+const SEEK_TICKS_PER_TICK: u32 = 0x200;
 
 const VIBRATO_LUT: [u8; 32] = [
     0x00, 0x18, 0x31, 0x4a, 0x61, 0x78, 0x8d, 0xa1, 0xb4, 0xc5, 0xd4, 0xe0, 0xeb, 0xf4, 0xfa, 0xfd,
     0xff, 0xfd, 0xfa, 0xf4, 0xeb, 0xe0, 0xd4, 0xc5, 0xb4, 0xa1, 0x8d, 0x78, 0x61, 0x4a, 0x31, 0x18,
 ];
 
-struct PlayerState {
-    module: Mod,
+/// Scream Tracker's periods for the notes of octave 0.
+const ST3_PERIODS: [u64; 12] = [
+    1712, 1616, 1524, 1440, 1356, 1280, 1208, 1140, 1076, 1016, 960, 907,
+];
+/// The Amiga clock the periods count in, and Scream Tracker's.
+const AMIGA_CLOCK: u64 = 3546895;
+const ST3_CLOCK: u64 = 14317056;
+/// The linear period of C-0.
+const LINEAR_C0: i32 = 7680;
+/// Linear periods are four times finer than the slides are given in.
+const LINEAR_SLIDE: u16 = 4;
+
+/// The replayer itself: the song, the mixer and the jingle state machine.
+/// Normally it lives on the audio thread, but with [`play_offline`] it is up
+/// to the caller to drive it.
+pub struct PlayerState {
+    module: Box<Mod>,
+    sfx: SfxBank,
     control: Arc<PlayerControl>,
+    commands: Receiver<Command>,
+    events: SyncSender<PlayerEvent>,
+    trace: Option<SyncSender<RowTrace>>,
+    calls: Option<SyncSender<RecordedCall>>,
+    /// Frames rendered so far.
+    frames: u64,
+    /// Where replaced modules go to be dropped, off the audio thread.
+    garbage: SyncSender<Box<Mod>>,
+    jingles: JingleState,
     sample_rate: u32,
     speed: u8,
+    global_volume: u8,
     ticks_left: u8,
     samples_left: u32,
     samples_in_tick: u32,
     position: usize,
     row: usize,
+    pattern_start: bool,
     started: bool,
-    channels: [ChannelState; 4],
+    /// Only as many as the module has are played.
+    channels: [ChannelState; MAX_CHANNELS],
     pattern_break: Option<u8>,
-    jump: Option<u8>,
+    jump: Option<Jump>,
+    fader: Fader,
+    fading: Option<Fading>,
+    /// A cut waiting for the fade-out before it.
+    held_cut: Option<HeldCut>,
+    /// Loudest frame of each channel since the levels were last published.
+    peaks: [u32; MAX_CHANNELS],
+    /// Fast-forwarding to a seek target; nothing is reported meanwhile.
+    seeking: bool,
+    /// Ticks still to fast-forward through to reach the seek target.
+    seek_left: u32,
+    /// The settings as last recorded in the call log.
+    recorded_settings: [u32; 3],
+    /// Calls dropped from the call log since the last one that made it.
+    calls_dropped: u32,
+}
+
+/// A position jump effect hit in the current row.
+#[derive(Copy, Clone)]
+struct Jump {
+    effect: u8,
+    target: JumpTarget,
+    transition: Transition,
+}
+
+enum HeldCut {
+    Jingle(JingleRequest),
+    Jump(JumpTarget),
+    Module(Box<Mod>),
+}
+
+/// The part being faded out by a crossfade.
+struct Fading {
+    channels: [ChannelState; MAX_CHANNELS],
+    fader: Fader,
+    /// The module the channels play from, if it is not the current one any
+    /// more.
+    module: Option<Box<Mod>>,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum SampleBank {
+    Music,
+    Sfx,
 }
 
+#[derive(Copy, Clone)]
 enum ChannelToneEffect {
     None,
     Portamento,
@@ -39,13 +153,16 @@ enum ChannelToneEffect {
     Retrig,
 }
 
+#[derive(Copy, Clone)]
 enum ChannelVolumeEffect {
     None,
     Slide,
 }
 
+#[derive(Clone)]
 struct ChannelState {
     volume: u8,
+    bank: SampleBank,
     sample: usize,
     sample_pos: u64,
     sample_bytes_per_frame: u64,
@@ -63,351 +180,1167 @@ struct ChannelState {
     volume_slide_speed: i8,
     retrig_period: u8,
     retrig_left: u8,
+    pan: u8,
 }
 
 struct PlayerControl {
-    cmd: AtomicU32,
     status: AtomicU32,
-    sfx: AtomicU32,
     state: AtomicU32,
+    /// Per channel, the volume and the peak of the last buffer rendered.
+    levels: [AtomicU32; MAX_CHANNELS],
+    /// Ticks and rows played so far.
+    ticks: AtomicU32,
+    rows: AtomicU32,
+    /// One bit per channel.
+    mutes: AtomicU32,
+    solos: AtomicU32,
+    /// Modules sent by [`Player::load_module`] and not yet dropped by the
+    /// reaper.  There are never more modules waiting to be dropped than
+    /// this, so keeping it within the reaper queue means the audio thread
+    /// never has to drop one itself.
+    modules_in_flight: AtomicU32,
+    /// Recorded calls lost because the call log was full.
+    calls_dropped: AtomicU32,
 }
 
 impl PlayerControl {
-    const CMD_JUMP_POSITION: u32 = 0x7f;
-    const CMD_JUMP_VALID: u32 = 0x80;
     const STATE_PAUSED: u32 = 0x100;
     const STATE_MASTER_VOLUME: u32 = 0xff;
+
+    /// The pause and master volume state, the mutes and the solos, read
+    /// once for a whole buffer.
+    fn settings(&self) -> [u32; 3] {
+        [&self.state, &self.mutes, &self.solos].map(|bits| bits.load(Ordering::Relaxed))
+    }
+}
+
+/// A mask per channel to AND its output with: all ones if it can be heard, 0
+/// if it is muted or another channel is soloed.
+fn audible(mutes: u32, solos: u32) -> [i32; MAX_CHANNELS] {
+    let on = if solos != 0 { solos } else { !mutes };
+    array::from_fn(|i| -((on >> i & 1) as i32))
+}
+
+/// Sets the bits of all channels at once, if there are no bits for channels
+/// the module does not have.
+fn set_bits(bits: &AtomicU32, value: u32, channels: usize) -> Result<(), CommandError> {
+    if value.checked_shr(channels as u32).unwrap_or(0) != 0 {
+        return Err(CommandError::ChannelOutOfRange(
+            31 - value.leading_zeros() as u8,
+        ));
+    }
+    bits.store(value, Ordering::Relaxed);
+    Ok(())
 }
 
+fn set_bit(bits: &AtomicU32, bit: u8, on: bool) {
+    if on {
+        bits.fetch_or(1 << bit, Ordering::Relaxed);
+    } else {
+        bits.fetch_and(!(1 << bit), Ordering::Relaxed);
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct PlayerConfig {
+    /// Start playing from position 0 right away, instead of waiting for the
+    /// first jingle.
+    pub start: bool,
+    /// How many jingles can wait in the jingle queue.
+    pub jingle_queue_depth: usize,
+    /// Report every row played through [`Player::take_row_trace`].
+    pub trace_rows: bool,
+    /// Log every control call through [`Player::take_call_log`], with the
+    /// frame it took effect at, for [`replay`].
+    pub record_calls: bool,
+}
+
+impl Default for PlayerConfig {
+    fn default() -> Self {
+        PlayerConfig {
+            start: true,
+            jingle_queue_depth: 4,
+            trace_rows: false,
+            record_calls: false,
+        }
+    }
+}
+
+/// What clocks the player state.  The outputs are only held to be dropped
+/// along with the player.
+enum Backend {
+    Device {
+        _output: Output,
+    },
+    Null {
+        _output: NullOutput,
+    },
+    /// The caller renders the player state on demand.
+    Offline,
+}
+
+/// The control handle of a playing module.  Control calls are validated here
+/// and passed to the audio thread through a bounded queue, so that neither
+/// side ever blocks on the other.
 pub struct Player {
-    _stream: Stream,
+    _backend: Backend,
+    output_errors: Receiver<OutputError>,
     control: Arc<PlayerControl>,
+    commands: SyncSender<Command>,
+    events: Receiver<PlayerEvent>,
+    trace: Option<Receiver<RowTrace>>,
+    calls: Option<Receiver<RecordedCall>>,
+    song_len: usize,
+    /// When each row of the song is first played and where it loops, for
+    /// seeking.
+    timing: SongTiming,
+    sfx_len: usize,
+    sample_rate: u32,
+    channels: usize,
+    /// Modules loaded so far, to number them in the call log.
+    modules_loaded: u32,
 }
 
 impl Player {
-    pub fn jingle(&self, pos: u8, repeat: u8, prio: u8, hard: bool) {
-        assert!(repeat < 0x10);
-        assert!(prio < 0x40);
-        let mut val = self.control.cmd.load(Ordering::Acquire);
-        loop {
-            let cur_prio = val >> 12 & 0x3f;
-            if (prio as u32) < cur_prio && !hard {
-                return;
-            }
-            let new_val =
-                val & !0x3ffff | 0x80 | (pos as u32) | (repeat as u32) << 8 | (prio as u32) << 12;
-            match self.control.cmd.compare_exchange(
-                val,
-                new_val,
-                Ordering::Release,
-                Ordering::Acquire,
-            ) {
-                Ok(_) => break,
-                Err(x) => val = x,
-            }
-        }
-    }
-
-    pub fn set_music_pos(&self, pos: u8) {
-        let mut val = self.control.cmd.load(Ordering::Acquire);
-        loop {
-            let new_val = val & !0x7f000000 | (pos as u32) << 24;
-            match self.control.cmd.compare_exchange(
-                val,
-                new_val,
-                Ordering::Release,
-                Ordering::Acquire,
-            ) {
-                Ok(_) => break,
-                Err(x) => val = x,
-            }
-        }
-    }
-
-    pub fn set_music_prio(&self, prio: u8) {
-        let mut val = self.control.cmd.load(Ordering::Acquire);
-        loop {
-            let new_val = val & !0x00fc0000 | (prio as u32) << 18;
-            match self.control.cmd.compare_exchange(
-                val,
-                new_val,
-                Ordering::Release,
-                Ordering::Acquire,
-            ) {
-                Ok(_) => break,
-                Err(x) => val = x,
-            }
-        }
-    }
-
-    pub fn play_sfx(&self, period: u8, sample: u8, volume: u8, channel: u8) {
-        let val =
-            (period as u32) | (sample as u32) << 8 | (volume as u32) << 16 | (channel as u32) << 24;
-        self.control.sfx.store(val, Ordering::Relaxed);
+    fn send(&self, cmd: Command) -> Result<(), CommandError> {
+        self.commands.try_send(cmd).map_err(|e| match e {
+            TrySendError::Full(_) => CommandError::QueueFull,
+            TrySendError::Disconnected(_) => CommandError::Disconnected,
+        })
+    }
+
+    /// Returns the order position and row last played.
+    pub fn status(&self) -> (u8, u8) {
+        let status = self.control.status.load(Ordering::Relaxed);
+        ((status >> 8) as u8, status as u8)
+    }
+
+    /// Ticks and rows played so far, as counted by the audio clock.  Seeking
+    /// does not count.
+    pub fn clock(&self) -> (u32, u32) {
+        (
+            self.control.ticks.load(Ordering::Relaxed),
+            self.control.rows.load(Ordering::Relaxed),
+        )
+    }
+
+    /// Frames per second of the output.
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Per-channel volume and peak level, as of the last buffer played.
+    pub fn channel_levels(&self) -> Vec<ChannelLevel> {
+        self.control.levels[..self.channels]
+            .iter()
+            .map(|level| ChannelLevel::from_bits(level.load(Ordering::Relaxed)))
+            .collect()
+    }
+
+    /// How many channels the module has.
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    /// Pauses or unpauses the output.  Takes effect at the next buffer.
+    pub fn set_paused(&self, paused: bool) {
+        if paused {
+            self.control
+                .state
+                .fetch_or(PlayerControl::STATE_PAUSED, Ordering::Relaxed);
+        } else {
+            self.control
+                .state
+                .fetch_and(!PlayerControl::STATE_PAUSED, Ordering::Relaxed);
+        }
+    }
+
+    pub fn paused(&self) -> bool {
+        self.control.state.load(Ordering::Relaxed) & PlayerControl::STATE_PAUSED != 0
+    }
+
+    /// Sets the master volume in percent; 100 is the default, and anything
+    /// above it can clip.
+    pub fn set_master_volume(&self, volume: u8) {
+        let _ = self
+            .control
+            .state
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |state| {
+                Some(state & !PlayerControl::STATE_MASTER_VOLUME | volume as u32)
+            });
+    }
+
+    pub fn master_volume(&self) -> u8 {
+        (self.control.state.load(Ordering::Relaxed) & PlayerControl::STATE_MASTER_VOLUME) as u8
+    }
+
+    /// Silences a channel, or makes it heard again.  The channel keeps playing
+    /// its notes and effects while muted, so it comes back in step.
+    pub fn set_channel_mute(&self, channel: u8, mute: bool) -> Result<(), CommandError> {
+        if channel as usize >= self.channels {
+            return Err(CommandError::ChannelOutOfRange(channel));
+        }
+        set_bit(&self.control.mutes, channel, mute);
+        Ok(())
+    }
+
+    /// Solos a channel, or takes it out of the solo.  While any channel is
+    /// soloed, only the soloed channels are heard, muted or not.
+    pub fn set_channel_solo(&self, channel: u8, solo: bool) -> Result<(), CommandError> {
+        if channel as usize >= self.channels {
+            return Err(CommandError::ChannelOutOfRange(channel));
+        }
+        set_bit(&self.control.solos, channel, solo);
+        Ok(())
+    }
+
+    /// Which channels are muted, and which soloed.
+    pub fn channel_mutes(&self) -> (Vec<bool>, Vec<bool>) {
+        let bits = |bits: &AtomicU32| {
+            let bits = bits.load(Ordering::Relaxed);
+            (0..self.channels).map(|i| bits >> i & 1 != 0).collect()
+        };
+        (bits(&self.control.mutes), bits(&self.control.solos))
+    }
+
+    /// Returns the events reported by the audio thread since the last call.
+    /// Events are dropped if the game does not keep up with them.
+    pub fn events(&self) -> TryIter<'_, PlayerEvent> {
+        self.events.try_iter()
+    }
+
+    /// Fades the music out over the given number of ticks.  It stays silent
+    /// until faded back in or until the next jingle.
+    pub fn fade_out(&self, ticks: u16) -> Result<(), CommandError> {
+        self.send(Command::Fade { level: 0, ticks })
+    }
+
+    /// Fades the music back in over the given number of ticks.
+    pub fn fade_in(&self, ticks: u16) -> Result<(), CommandError> {
+        self.send(Command::Fade {
+            level: FADE_ONE,
+            ticks,
+        })
+    }
+
+    /// Returns the errors reported by the audio output since the last call.
+    pub fn output_errors(&self) -> TryIter<'_, OutputError> {
+        self.output_errors.try_iter()
+    }
+
+    /// Takes the receiving end of the row trace stream, so that it can be
+    /// drained on another thread.  Returns `None` unless the player was made
+    /// with [`PlayerConfig::trace_rows`], or if the stream was already taken.
+    pub fn take_row_trace(&mut self) -> Option<Receiver<RowTrace>> {
+        self.trace.take()
+    }
+
+    /// Takes the receiving end of the call log, like
+    /// [`Player::take_row_trace`].  Calls are dropped if the consumer does not
+    /// keep up, so it should be drained from a thread of its own.
+    pub fn take_call_log(&mut self) -> Option<Receiver<RecordedCall>> {
+        self.calls.take()
+    }
+
+    /// Replaces the module being played, through the given transition.  The
+    /// new module starts at position 0, and the jingle state starts over;
+    /// control calls made after this one apply to the new module.  The sfx
+    /// bank stays as it is.  Too many loads in a row, faster than the audio
+    /// thread swaps them in and the old modules are dropped, give
+    /// [`CommandError::QueueFull`].
+    pub fn load_module(&mut self, module: Mod, transition: Transition) -> Result<(), CommandError> {
+        let song_len = module.positions.len();
+        let timing = module.timing();
+        let channels = module.channels();
+        let in_flight = &self.control.modules_in_flight;
+        if in_flight.fetch_add(1, Ordering::Relaxed) as usize >= GARBAGE_QUEUE_LEN {
+            in_flight.fetch_sub(1, Ordering::Relaxed);
+            return Err(CommandError::QueueFull);
+        }
+        let number = self.modules_loaded + 1;
+        if let Err(e) = self.send(Command::LoadModule(Box::new(module), transition, number)) {
+            in_flight.fetch_sub(1, Ordering::Relaxed);
+            return Err(e);
+        }
+        self.modules_loaded = number;
+        self.song_len = song_len;
+        self.timing = timing;
+        self.channels = channels;
+        Ok(())
+    }
+
+    /// Moves the music to a row, in the state straight playback from the
+    /// start would have reached it in: speed, channel samples, volumes,
+    /// periods and effects.  The first time the song plays the row counts.
+    /// Jingles playing or waiting are dropped, and sound effects cut.
+    pub fn seek(&self, position: u8, row: u8) -> Result<(), CommandError> {
+        let starts = self
+            .timing
+            .row_starts
+            .get(position as usize)
+            .ok_or(CommandError::PositionOutOfRange(position))?;
+        let tick = starts
+            .get(row as usize)
+            .ok_or(CommandError::RowOutOfRange(row))?
+            .ok_or(CommandError::RowUnreachable { position, row })?;
+        self.send(Command::Seek(tick))
+    }
+
+    /// Like [`Player::seek`], but to a time from the start of the song.  The
+    /// time is rounded down to a tick.  Past the end of the song, the seek
+    /// lands where the song has looped around to by then.
+    pub fn seek_time(&self, time: Duration) -> Result<(), CommandError> {
+        self.seek_tick(time.as_millis() * TICKS_PER_SECOND as u128 / 1000)
+    }
+
+    /// Seeks to a tick of the song played straight from the start, brought
+    /// back within the first time through the song.
+    fn seek_tick(&self, tick: u128) -> Result<(), CommandError> {
+        let (length, loop_tick) = (self.timing.length as u128, self.timing.loop_tick as u128);
+        let tick = if tick < length {
+            tick
+        } else {
+            loop_tick + (tick - loop_tick) % (length - loop_tick).max(1)
+        };
+        self.send(Command::Seek(tick as u32))
+    }
+
+    /// Requests a jingle.  Whether it actually plays is decided on the audio
+    /// thread, against the priority of whatever is playing at that point.
+    pub fn jingle(&self, req: JingleRequest) -> Result<(), CommandError> {
+        req.validate(self.song_len)?;
+        self.send(Command::Jingle(req))
+    }
+
+    /// Sets the position the song returns to when a jingle runs out of
+    /// repeats.
+    pub fn set_music_pos(&self, pos: u8) -> Result<(), CommandError> {
+        if pos as usize >= self.song_len {
+            return Err(CommandError::PositionOutOfRange(pos));
+        }
+        self.send(Command::SetMusicPos(pos))
+    }
+
+    /// Sets the priority the music has once a jingle runs out of repeats.
+    pub fn set_music_prio(&self, prio: u8) -> Result<(), CommandError> {
+        if prio > MAX_PRIORITY {
+            return Err(CommandError::PriorityOutOfRange(prio));
+        }
+        self.send(Command::SetMusicPrio(prio))
     }
+
+    /// Sets whether the music continues at the position and row where a jingle
+    /// interrupted it, instead of starting over at the music position.
+    pub fn set_music_resume(&self, resume: bool) -> Result<(), CommandError> {
+        self.send(Command::SetMusicResume(resume))
+    }
+
+    /// Plays a sample from the sfx bank on the given channel.  A volume of 0
+    /// keeps the sample's default volume.
+    pub fn play_sfx(
+        &self,
+        period: u8,
+        sample: u8,
+        volume: u8,
+        channel: u8,
+    ) -> Result<(), CommandError> {
+        if period as usize >= PERIODS[0].len() {
+            return Err(CommandError::PeriodOutOfRange(period));
+        }
+        if sample as usize >= self.sfx_len {
+            return Err(CommandError::SampleOutOfRange(sample));
+        }
+        if channel as usize >= self.channels {
+            return Err(CommandError::ChannelOutOfRange(channel));
+        }
+        self.send(Command::Sfx(SfxRequest {
+            period,
+            sample,
+            volume,
+            channel,
+        }))
+    }
+}
+
+/// Plays a module on the default output device.
+pub fn play(module: Mod, sfx: SfxBank, config: PlayerConfig) -> Result<Player, OutputError> {
+    let (mut player, state, errors) = create(module, sfx, config);
+    player._backend = Backend::Device {
+        _output: Output::start(state, errors)?,
+    };
+    Ok(player)
+}
+
+/// Plays a module in real time without any output device, throwing the audio
+/// away.  Everything else works as with [`play`].
+pub fn play_null(module: Mod, sfx: SfxBank, config: PlayerConfig) -> Player {
+    let (mut player, state, _) = create(module, sfx, config);
+    player._backend = Backend::Null {
+        _output: NullOutput::start(state),
+    };
+    player
+}
+
+/// Makes a player without any clock.  The module plays as far as the returned
+/// player state is rendered with [`PlayerState::render`].
+pub fn play_offline(module: Mod, sfx: SfxBank, config: PlayerConfig) -> (Player, PlayerState) {
+    let (player, state, _) = create(module, sfx, config);
+    (player, state)
 }
 
-pub fn play(module: Mod, start: bool) -> Player {
-    let host = cpal::default_host();
-    let device = host
-        .default_output_device()
-        .expect("no output device available");
+fn create(
+    module: Mod,
+    sfx: SfxBank,
+    config: PlayerConfig,
+) -> (Player, PlayerState, SyncSender<OutputError>) {
     let sample_rate = 44000;
     let control = Arc::new(PlayerControl {
-        cmd: AtomicU32::new(0),
         status: AtomicU32::new(0),
-        sfx: AtomicU32::new(0),
         state: AtomicU32::new(100),
+        levels: Default::default(),
+        ticks: AtomicU32::new(0),
+        rows: AtomicU32::new(0),
+        mutes: AtomicU32::new(0),
+        solos: AtomicU32::new(0),
+        modules_in_flight: AtomicU32::new(0),
+        calls_dropped: AtomicU32::new(0),
     });
+    let (commands, commands_rx) = mpsc::sync_channel(COMMAND_QUEUE_LEN);
+    let (events_tx, events) = mpsc::sync_channel(EVENT_QUEUE_LEN);
+    let (trace_tx, trace) = if config.trace_rows {
+        let (tx, rx) = mpsc::sync_channel(EVENT_QUEUE_LEN);
+        (Some(tx), Some(rx))
+    } else {
+        (None, None)
+    };
+    let (calls_tx, calls) = if config.record_calls {
+        let (tx, rx) = mpsc::sync_channel(CALL_LOG_LEN);
+        (Some(tx), Some(rx))
+    } else {
+        (None, None)
+    };
+    let (garbage, garbage_rx) = mpsc::sync_channel(GARBAGE_QUEUE_LEN);
+    let reaper_control = control.clone();
+    thread::Builder::new()
+        .name("module reaper".into())
+        .spawn(move || {
+            for module in garbage_rx {
+                drop::<Box<Mod>>(module);
+                reaper_control
+                    .modules_in_flight
+                    .fetch_sub(1, Ordering::Relaxed);
+            }
+        })
+        .expect("failed to spawn module reaper thread");
+    let song_len = module.positions.len();
+    let timing = module.timing();
+    let sfx_len = sfx.samples.len();
+    let channels = module.channels();
+    let (speed, global_volume) = (module.speed, module.global_volume);
     let mut state = PlayerState {
-        module,
-        speed: 6,
+        module: Box::new(module),
+        sfx,
+        speed,
+        global_volume,
         ticks_left: 0,
         samples_left: 0,
         control: control.clone(),
+        commands: commands_rx,
+        events: events_tx,
+        trace: trace_tx,
+        calls: calls_tx,
+        frames: 0,
+        garbage,
+        jingles: JingleState::new(config.jingle_queue_depth),
         samples_in_tick: sample_rate / 50,
         position: 0,
         row: 0,
-        started: start,
-        channels: std::array::from_fn(|_| ChannelState {
-            volume: 0x40,
-            sample: 0,
-            sample_pos: 0,
-            sample_bytes_per_frame: 0,
-            sample_pos_reload: 0,
-            period: 0,
-            vibrato_phase: 0,
-            tone_effect: ChannelToneEffect::None,
-            arpeggio_periods: [0, 0],
-            portamento_target: 0,
-            portamento_speed: 0,
-            vibrato_rate: 0,
-            vibrato_depth: 0,
-            volume_effect: ChannelVolumeEffect::None,
-            volume_slide_speed: 0,
-            retrig_period: 0,
-            retrig_left: 0,
-            xperiod: 0,
-        }),
+        pattern_start: true,
+        started: config.start,
+        channels: array::from_fn(|_| ChannelState::new()),
         sample_rate,
         pattern_break: None,
         jump: None,
+        fader: Fader::new(FADE_ONE),
+        fading: None,
+        held_cut: None,
+        peaks: [0; MAX_CHANNELS],
+        seeking: false,
+        seek_left: 0,
+        recorded_settings: control.settings(),
+        calls_dropped: 0,
     };
-    let config = StreamConfig {
-        channels: 2,
-        sample_rate: SampleRate(sample_rate),
-        buffer_size: BufferSize::Fixed(sample_rate / 50),
-    };
-    let stream = device
-        .build_output_stream(
-            &config,
-            move |data: &mut [i32], _: &cpal::OutputCallbackInfo| state.make_samples(data),
-            move |err| eprintln!("audio error: {err:?}"),
-            None, // None=blocking, Some(Duration)=timeout
-        )
-        .expect("failed to make stream");
-    Player {
-        _stream: stream,
+    let (errors, output_errors) = mpsc::sync_channel(EVENT_QUEUE_LEN);
+    let player = Player {
+        _backend: Backend::Offline,
+        output_errors,
         control,
-    }
+        commands,
+        events,
+        trace,
+        calls,
+        song_len,
+        timing,
+        sfx_len,
+        sample_rate,
+        channels,
+        modules_loaded: 0,
+    };
+    state.reset_pans();
+    (player, state, errors)
 }
 
 impl PlayerState {
-    fn make_samples(&mut self, data: &mut [i32]) {
-        let state = self.control.state.load(Ordering::Relaxed);
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Frames rendered so far.
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// Frames left until the next tick is played.
+    pub fn frames_to_tick(&self) -> u32 {
+        self.samples_left
+    }
+
+    /// Plays the module into a buffer of interleaved stereo frames.  Control
+    /// calls made on the player are picked up at the start of each call, or
+    /// of the first call after a long seek has caught up.  A
+    /// value left over after the last whole frame is set to 0.
+    pub fn render(&mut self, data: &mut [i32]) {
+        let settings = self.control.settings();
+        if self.calls.is_some() {
+            self.record_settings(settings);
+        }
+        let [state, mutes, solos] = settings;
         if (state & PlayerControl::STATE_PAUSED) != 0 {
-            for v in data {
-                *v = 0;
-            }
+            data.fill(0);
+            self.frames += (data.len() / 2) as u64;
             return;
         }
         let master_volume = (state & PlayerControl::STATE_MASTER_VOLUME) as i32;
-        self.process_cmd();
+        let audible = audible(mutes, solos);
+        // calls wait for a seek spread over several ticks to finish
+        if !self.seeking {
+            self.process_cmd();
+        }
+        self.frames += (data.len() / 2) as u64;
         if !self.started {
             for v in data {
                 *v = 0;
             }
             return;
         }
-        let sfx = self.control.sfx.swap(0, Ordering::Relaxed);
-        if sfx != 0 {
-            let volume = (sfx >> 16 & 0xff) as u8;
-            let channel = (sfx >> 24 & 0xff) as usize;
-            let note = Note {
-                period: Some((sfx & 0xff) as u8),
-                sample: Some((sfx >> 8 & 0xff) as u8),
-                tone_effect: ToneEffect::None,
-                volume_effect: if volume == 0 {
-                    VolumeEffect::None
-                } else {
-                    VolumeEffect::SetVolume(volume)
-                },
-                misc_effect: MiscEffect::None,
-            };
-            self.play_note(channel, note);
-        }
-        // XXX process sfx
-        let mut pos = 0;
-        while pos < data.len() {
+        let mut frames = data.chunks_exact_mut(2);
+        for frame in &mut frames {
             if self.samples_left == 0 {
-                if self.ticks_left == 0 {
-                    self.play_row();
-                    self.ticks_left = self.speed - 1;
+                if self.seek_left != 0 {
+                    self.fast_forward();
                 } else {
-                    self.ticks_left -= 1;
-                    self.play_effects();
+                    if self.seeking {
+                        self.finish_seek();
+                    }
+                    self.tick();
                 }
                 self.samples_left = self.samples_in_tick;
             }
-            data[pos] = (self.play_channel(0) + self.play_channel(1)) / 100 * master_volume;
-            data[pos + 1] = (self.play_channel(2) + self.play_channel(3)) / 100 * master_volume;
-            pos += 2;
+            if self.seeking {
+                frame.fill(0);
+                self.samples_left -= 1;
+                continue;
+            }
+            let (left, right) = self.mix_frame(&audible);
+            frame[0] = left / 100 * master_volume;
+            frame[1] = right / 100 * master_volume;
             self.samples_left -= 1;
         }
+        frames.into_remainder().fill(0);
+        self.publish_levels();
     }
 
-    fn process_cmd(&mut self) {
-        let mut cmd = self.control.cmd.load(Ordering::Acquire);
-        loop {
-            if cmd & PlayerControl::CMD_JUMP_VALID == 0 {
-                return;
-            }
-            let new_cmd = cmd & !PlayerControl::CMD_JUMP_VALID;
-            match self.control.cmd.compare_exchange(
-                cmd,
-                new_cmd,
-                Ordering::Release,
-                Ordering::Acquire,
-            ) {
-                Ok(_) => break,
-                Err(val) => cmd = val,
-            }
-        }
-        self.position = (cmd & PlayerControl::CMD_JUMP_POSITION) as usize;
+    fn publish_levels(&mut self) {
+        for ((slot, channel), peak) in self
+            .control
+            .levels
+            .iter()
+            .zip(&self.channels[..self.module.channels()])
+            .zip(mem::take(&mut self.peaks))
+        {
+            let level = ChannelLevel {
+                volume: channel.volume,
+                // a full-scale sample at full volume is 1 << 29
+                peak: (peak >> 21).min(0xff) as u8,
+            };
+            slot.store(level.to_bits(), Ordering::Relaxed);
+        }
+    }
+
+    /// Runs the player up to the start of the next tick and plays that tick,
+    /// without rendering audio.  Returns true if the tick started a new row.
+    /// Does nothing before the song is started.
+    pub fn step_tick(&mut self) -> bool {
+        // stepping is not in real time, so long seeks finish at once
+        self.catch_up_seek();
+        self.process_cmd();
+        self.catch_up_seek();
+        if !self.started {
+            return false;
+        }
+        let [_, mutes, solos] = self.control.settings();
+        let audible = audible(mutes, solos);
+        while self.samples_left != 0 {
+            self.mix_frame(&audible);
+            self.samples_left -= 1;
+        }
+        let row = self.tick();
+        self.samples_left = self.samples_in_tick;
+        row
+    }
+
+    /// Steps ticks until the next row has been played.
+    pub fn step_row(&mut self) {
+        while !self.step_tick() && self.started {}
+    }
+
+    /// Position and row of the last row played.
+    pub fn status(&self) -> (u8, u8) {
+        let status = self.control.status.load(Ordering::Relaxed);
+        ((status >> 8) as u8, status as u8)
+    }
+
+    pub fn speed(&self) -> u8 {
+        self.speed
+    }
+
+    pub fn channels(&self) -> Vec<ChannelSnapshot> {
+        self.channels[..self.module.channels()]
+            .iter()
+            .map(ChannelState::snapshot)
+            .collect()
+    }
+
+    /// Mixes one frame of all the channels, before the master volume.  Every
+    /// channel plays, but only the `audible` ones are mixed in.
+    fn mix_frame(&mut self, audible: &[i32; MAX_CHANNELS]) -> (i32, i32) {
+        let channels = self.module.channels();
+        let (mut left, mut right) = (0, 0);
+        for (i, &on) in audible.iter().enumerate().take(channels) {
+            let (l, r) = self.play_channel(i);
+            let (l, r) = (l & on, r & on);
+            self.peaks[i] = self.peaks[i].max(l.unsigned_abs()).max(r.unsigned_abs());
+            let (l, r) = self.channels[i].pan(l, r);
+            left += l;
+            right += r;
+        }
+        let mut left = self.fader.apply(left);
+        let mut right = self.fader.apply(right);
+        if let Some(fading) = &mut self.fading {
+            let module = fading.module.as_ref().unwrap_or(&self.module);
+            let (mut fading_left, mut fading_right) = (0, 0);
+            for (channel, &on) in fading
+                .channels
+                .iter_mut()
+                .zip(audible)
+                .take(module.channels())
+            {
+                let (l, r) = channel.next_frame(module, &self.sfx);
+                let (l, r) = channel.pan(l & on, r & on);
+                fading_left += l;
+                fading_right += r;
+            }
+            left += fading.fader.apply(fading_left);
+            right += fading.fader.apply(fading_right);
+        }
+        // the global volume, and headroom for modules with more than four
+        // channels; none of it for plain MODs
+        let gain = |val: i64| {
+            let val = val * self.global_volume as i64 * 4 / (0x40 * channels.max(4) as i64);
+            val.clamp(i32::MIN as i64, i32::MAX as i64) as i32
+        };
+        (gain(left), gain(right))
+    }
+
+    /// Sets the channels to the panning the module starts with.
+    fn reset_pans(&mut self) {
+        for (channel, &pan) in self.channels.iter_mut().zip(&self.module.panning) {
+            channel.pan = pan;
+        }
+    }
+
+    /// Plays the song from the start up to `ticks` ticks in, without output.
+    /// Long seeks go on over the following ticks of silence.
+    fn seek(&mut self, ticks: u32) {
+        if let Some(cut) = self.held_cut.take() {
+            self.fader.set(FADE_ONE);
+            if let HeldCut::Module(module) = cut {
+                self.swap_module(module);
+            }
+        }
+        if let Some(module) = self.fading.take().and_then(|fading| fading.module) {
+            self.discard(module);
+        }
+        self.jingles.cancel();
+        self.channels = array::from_fn(|_| ChannelState::new());
+        self.reset_pans();
+        self.speed = self.module.speed;
+        self.global_volume = self.module.global_volume;
+        self.position = 0;
         self.row = 0;
+        self.pattern_start = true;
         self.ticks_left = 0;
         self.samples_left = 0;
+        self.pattern_break = None;
+        self.jump = None;
         self.started = true;
+        self.seeking = true;
+        self.seek_left = ticks;
+        self.fast_forward();
+        if self.seek_left == 0 {
+            self.finish_seek();
+        }
     }
 
-    fn jump(&mut self, mut pos: u8) {
-        let mut val = self.control.cmd.load(Ordering::Acquire);
-        loop {
-            if val & 0x80 != 0 {
-                // doesn't matter, the command will override everything anyway.
-                break;
+    /// Plays up to [`SEEK_TICKS_PER_TICK`] ticks of the seek without mixing
+    /// them.
+    fn fast_forward(&mut self) {
+        let ticks = self.seek_left.min(SEEK_TICKS_PER_TICK);
+        for _ in 0..ticks {
+            self.advance();
+            for channel in &mut self.channels[..self.module.channels()] {
+                channel.skip(self.samples_in_tick, &self.module, &self.sfx);
             }
-            let repeat = val >> 8 & 0xf;
-            match repeat {
-                0 => {
-                    // nothing to worry about, just jump
-                    break;
+        }
+        self.seek_left -= ticks;
+    }
+
+    /// Finishes a seek still going on.
+    fn catch_up_seek(&mut self) {
+        while self.seek_left != 0 {
+            self.fast_forward();
+        }
+        if self.seeking {
+            self.finish_seek();
+        }
+    }
+
+    fn finish_seek(&mut self) {
+        self.seeking = false;
+        self.emit_pattern_changed();
+    }
+
+    /// Returns true if the tick played a row.
+    fn tick(&mut self) -> bool {
+        self.fader.tick();
+        if self.fader.is_done() {
+            match self.held_cut.take() {
+                None => {}
+                Some(HeldCut::Jingle(req)) => {
+                    self.fader.set(FADE_ONE);
+                    self.start_jingle(&req);
+                    self.ticks_left = 0;
+                }
+                Some(HeldCut::Jump(target)) => {
+                    self.fader.set(FADE_ONE);
+                    self.take_jump(target);
+                    self.ticks_left = 0;
+                }
+                Some(HeldCut::Module(module)) => {
+                    self.fader.set(FADE_ONE);
+                    self.swap_module(module);
+                }
+            }
+        }
+        if let Some(fading) = &mut self.fading {
+            fading.fader.tick();
+            if fading.fader.level() == 0 {
+                if let Some(module) = self.fading.take().unwrap().module {
+                    self.discard(module);
+                }
+            }
+        }
+        let row = self.advance();
+        self.control.ticks.fetch_add(1, Ordering::Relaxed);
+        if row {
+            self.control.rows.fetch_add(1, Ordering::Relaxed);
+        }
+        row
+    }
+
+    /// Plays a row or the effects between rows.  Returns true for a row.
+    fn advance(&mut self) -> bool {
+        if self.ticks_left == 0 {
+            self.play_row();
+            self.ticks_left = self.speed - 1;
+            true
+        } else {
+            self.ticks_left -= 1;
+            self.play_effects();
+            false
+        }
+    }
+
+    /// Starts a transition to another part of the song, unless it starts with
+    /// a fade-out.  Returns false if the cut has to wait for the fade-out.
+    fn begin_transition(&mut self, transition: Transition) -> bool {
+        match transition {
+            Transition::FadeOut(ticks) if ticks != 0 && self.started => {
+                self.fader.fade_to(0, ticks);
+                return false;
+            }
+            Transition::Cut | Transition::FadeOut(_) => self.fader.set(FADE_ONE),
+            Transition::FadeIn(ticks) => {
+                self.fader.set(0);
+                self.fader.fade_to(FADE_ONE, ticks);
+            }
+            Transition::Crossfade(ticks) => {
+                let mut fader = self.fader;
+                fader.fade_to(0, ticks);
+                let old = self.fading.replace(Fading {
+                    channels: self.channels.clone(),
+                    fader,
+                    module: None,
+                });
+                if let Some(module) = old.and_then(|f| f.module) {
+                    self.discard(module);
                 }
-                1 => {
-                    // repeat ran out, jump to music instead
-                    let music_prio = val >> 18 & 0x3f;
-                    let new_val = val & !0x3ff00 | music_prio << 12;
-                    pos = (val >> 24) as u8;
-                    match self.control.cmd.compare_exchange(
-                        val,
-                        new_val,
-                        Ordering::Release,
-                        Ordering::Acquire,
-                    ) {
-                        Ok(_) => break,
-                        Err(x) => val = x,
+                for channel in &mut self.channels {
+                    // silent until the new part plays a note
+                    channel.bank = SampleBank::Music;
+                    channel.sample = 0;
+                }
+                self.fader.set(0);
+                self.fader.fade_to(FADE_ONE, ticks);
+            }
+        }
+        true
+    }
+
+    /// Cuts into a jingle just let in by the jingle state machine.  Returns
+    /// false if the cut has to wait for the fade-out before it.
+    fn enter_jingle(&mut self, req: JingleRequest) -> bool {
+        if self.begin_transition(req.transition) {
+            self.start_jingle(&req);
+            true
+        } else {
+            self.hold_cut(HeldCut::Jingle(req));
+            false
+        }
+    }
+
+    fn hold_cut(&mut self, cut: HeldCut) {
+        if let Some(HeldCut::Module(module)) = self.held_cut.replace(cut) {
+            self.discard(module);
+        }
+    }
+
+    /// Whether a new module is waiting for the fade-out of the old one.
+    /// Until it is swapped in, jingles wait, as they refer to the new module.
+    fn swap_pending(&self) -> bool {
+        matches!(self.held_cut, Some(HeldCut::Module(_)))
+    }
+
+    /// Hands a module over to the reaper thread to be dropped.
+    fn discard(&self, module: Box<Mod>) {
+        // `Player::load_module` keeps the modules in flight within the queue,
+        // so this only fails if the reaper is gone; leak the module then
+        // rather than free it here.
+        if let Err(e) = self.garbage.try_send(module) {
+            mem::forget(e);
+        }
+    }
+
+    fn swap_module(&mut self, module: Box<Mod>) {
+        let old = mem::replace(&mut self.module, module);
+        match &mut self.fading {
+            // the crossfade still plays from the old module
+            Some(fading) if fading.module.is_none() => fading.module = Some(old),
+            _ => self.discard(old),
+        }
+        for channel in &mut self.channels {
+            if channel.bank == SampleBank::Music {
+                channel.sample = 0;
+            }
+        }
+        self.reset_pans();
+        self.speed = self.module.speed;
+        self.global_volume = self.module.global_volume;
+        self.position = 0;
+        self.row = 0;
+        self.pattern_start = true;
+        self.ticks_left = 0;
+        self.samples_left = 0;
+        self.pattern_break = None;
+        self.jump = None;
+        self.emit(PlayerEvent::ModuleChanged);
+        self.emit_pattern_changed();
+    }
+
+    /// Logs a call as taking effect at the current frame.
+    fn record(&mut self, call: Call) {
+        let Some(calls) = &self.calls else {
+            return;
+        };
+        let rec = RecordedCall {
+            frame: self.frames,
+            tick: self.control.ticks.load(Ordering::Relaxed),
+            dropped: self.calls_dropped,
+            call,
+        };
+        // never block the audio thread, but keep count of what is lost
+        if calls.try_send(rec).is_ok() {
+            self.calls_dropped = 0;
+        } else {
+            self.calls_dropped = self.calls_dropped.saturating_add(1);
+            self.control.calls_dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Logs the changes to the settings the player makes directly, without
+    /// going through the command queue.
+    fn record_settings(&mut self, settings: [u32; 3]) {
+        let [state, mutes, solos] = settings;
+        let [old_state, old_mutes, old_solos] = mem::replace(&mut self.recorded_settings, settings);
+        if (state ^ old_state) & PlayerControl::STATE_PAUSED != 0 {
+            self.record(Call::SetPaused(state & PlayerControl::STATE_PAUSED != 0));
+        }
+        if (state ^ old_state) & PlayerControl::STATE_MASTER_VOLUME != 0 {
+            self.record(Call::SetMasterVolume(
+                (state & PlayerControl::STATE_MASTER_VOLUME) as u8,
+            ));
+        }
+        if mutes != old_mutes {
+            self.record(Call::SetChannelMutes(mutes));
+        }
+        if solos != old_solos {
+            self.record(Call::SetChannelSolos(solos));
+        }
+    }
+
+    fn process_cmd(&mut self) {
+        while let Ok(cmd) = self.commands.try_recv() {
+            if self.calls.is_some() {
+                self.record(Call::from_command(&cmd));
+            }
+            match cmd {
+                Command::Jingle(req) => {
+                    if self.jingles.request(&req) != RequestOutcome::Accepted {
+                        continue;
+                    }
+                    // otherwise held until play_row reaches the requested
+                    // boundary
+                    if (req.start == JingleStart::Now || !self.started) && !self.swap_pending() {
+                        let req = self
+                            .jingles
+                            .start(self.position as u8, self.row as u8)
+                            .unwrap();
+                        if self.enter_jingle(req) {
+                            self.ticks_left = 0;
+                            self.samples_left = 0;
+                            self.started = true;
+                        }
                     }
                 }
-                _ => {
-                    // decrease repeat count
-                    let new_val = val & !0xf00 | (repeat - 1) << 8;
-                    match self.control.cmd.compare_exchange(
-                        val,
-                        new_val,
-                        Ordering::Release,
-                        Ordering::Acquire,
-                    ) {
-                        Ok(_) => break,
-                        Err(x) => val = x,
+                Command::SetMusicPos(pos) => self.jingles.set_music_pos(pos),
+                Command::SetMusicPrio(prio) => self.jingles.set_music_prio(prio),
+                Command::SetMusicResume(resume) => self.jingles.set_music_resume(resume),
+                Command::Sfx(sfx) => {
+                    let note = Note {
+                        period: Some(MOD_FIRST_NOTE + sfx.period),
+                        sample: Some(sfx.sample),
+                        volume: None,
+                        tone_effect: ToneEffect::None,
+                        volume_effect: if sfx.volume == 0 {
+                            VolumeEffect::None
+                        } else {
+                            VolumeEffect::SetVolume(sfx.volume)
+                        },
+                        misc_effect: MiscEffect::None,
+                    };
+                    self.play_note(sfx.channel as usize, note, SampleBank::Sfx);
+                }
+                Command::Fade { level, ticks } => self.fader.fade_to(level, ticks),
+                Command::LoadModule(module, transition, _) => {
+                    self.jingles.reset();
+                    if self.begin_transition(transition) {
+                        self.swap_module(module);
+                    } else {
+                        self.hold_cut(HeldCut::Module(module));
                     }
                 }
+                Command::Seek(ticks) => self.seek(ticks),
+            }
+        }
+    }
+
+    fn emit(&self, event: PlayerEvent) {
+        if self.seeking {
+            return;
+        }
+        // Never block the audio thread; if the game does not drain the
+        // events, it loses them.
+        let _ = self.events.try_send(event);
+    }
+
+    fn emit_pattern_changed(&self) {
+        self.emit(PlayerEvent::PatternChanged {
+            position: self.position as u8,
+            pattern: self.module.positions[self.position],
+        });
+    }
+
+    fn start_jingle(&mut self, req: &JingleRequest) {
+        self.position = req.position as usize;
+        self.row = 0;
+        self.pattern_start = true;
+        self.emit(PlayerEvent::JingleStarted {
+            position: req.position,
+            priority: req.priority,
+        });
+        self.emit_pattern_changed();
+    }
+
+    fn take_jump(&mut self, target: JumpTarget) {
+        // jumps past the end of the song start it over, as in the timing
+        // analysis
+        let position = match target.position() {
+            p if (p as usize) < self.module.positions.len() => p,
+            _ => 0,
+        };
+        self.emit(PlayerEvent::PositionJump {
+            from: self.position as u8,
+            to: position,
+        });
+        match target {
+            JumpTarget::Song(_) => {}
+            JumpTarget::Jingle {
+                position, priority, ..
+            } => {
+                self.emit(PlayerEvent::JingleFinished { position });
+                self.emit(PlayerEvent::JingleStarted { position, priority });
+            }
+            JumpTarget::Music { position, .. } => {
+                self.emit(PlayerEvent::JingleFinished { position });
             }
         }
-        self.jump = Some(pos)
+        self.position = position as usize;
+        self.row = target.row() as usize;
+        self.pattern_start = true;
+        self.emit_pattern_changed();
+    }
+
+    fn next_position(&mut self) {
+        self.pattern_start = true;
+        self.position += 1;
+        if self.position == self.module.positions.len() {
+            self.position = 0;
+            self.emit(PlayerEvent::SongLooped);
+        }
+        self.emit_pattern_changed();
     }
 
     fn play_row(&mut self) {
-        let pattern = self.module.positions[self.position] as usize;
-        let row = self.module.patterns[pattern][self.row];
-        print!(
-            "{pos:02x}/{pattern:02x}.{r:02x}",
-            pos = self.position,
-            r = self.row
-        );
-        for (i, &note) in row.iter().enumerate() {
-            self.play_note(i, note);
-            print!("   {note}");
+        if !self.swap_pending() {
+            let due = self
+                .jingles
+                .start_due(self.position as u8, self.row, self.pattern_start);
+            if let Some(req) = due {
+                self.enter_jingle(req);
+            }
+        }
+        self.pattern_start = false;
+        let position = self.position;
+        let pattern = self.module.positions[position];
+        let row_idx = self.row;
+        let rows = self.module.patterns[pattern as usize].len();
+        for i in 0..self.module.channels() {
+            let note = self.module.patterns[pattern as usize][row_idx][i];
+            self.play_note(i, note, SampleBank::Music);
         }
-        let status = self.row | self.position << 6;
+        let status = self.row | self.position << 8;
         self.control.status.store(status as u32, Ordering::Relaxed);
-        println!();
-        if let Some(pos) = self.jump {
-            println!("---JUMP---");
-            self.position = pos as usize;
-            self.row = 0;
-            self.jump = None;
-        } else if let Some(row) = self.pattern_break {
-            println!("---BREAK---");
-            self.row = row as usize;
-            self.position += 1;
-            if self.position == self.module.positions.len() {
-                self.position = 0;
+        let flow = if let Some(jump) = self.jump.take() {
+            if matches!(jump.target, JumpTarget::Song(_)) || self.begin_transition(jump.transition)
+            {
+                self.take_jump(jump.target);
+            } else {
+                // keep the jingle going while it fades out
+                self.take_jump(JumpTarget::Song(jump.effect));
+                self.held_cut = Some(HeldCut::Jump(jump.target));
             }
+            RowFlow::Jump
+        } else if let Some(row) = self.pattern_break {
+            self.next_position();
+            let rows = self.module.patterns[self.module.positions[self.position] as usize].len();
+            self.row = (row as usize).min(rows - 1);
             self.pattern_break = None;
+            RowFlow::Break
         } else {
             self.row += 1;
-            if self.row == 0x40 {
-                println!("---");
+            if self.row == rows {
                 self.row = 0;
-                self.position += 1;
-                if self.position == self.module.positions.len() {
-                    self.position = 0;
-                }
+                self.next_position();
+                RowFlow::PatternEnd
+            } else {
+                RowFlow::Next
             }
+        };
+        if let Some(trace) = self.trace.as_ref().filter(|_| !self.seeking) {
+            // dropped if the consumer does not keep up, like events
+            let _ = trace.try_send(RowTrace {
+                position: position as u8,
+                pattern,
+                row: row_idx as u8,
+                notes: self.module.patterns[pattern as usize][row_idx].clone(),
+                flow,
+            });
         }
     }
 
-    fn play_note(&mut self, cidx: usize, note: Note) {
+    fn play_note(&mut self, cidx: usize, note: Note, bank: SampleBank) {
         let channel = &mut self.channels[cidx];
         if let Some(sidx) = note.sample {
+            channel.bank = bank;
             channel.sample = sidx as usize;
             channel.sample_pos_reload = 0;
-            let sample = &self.module.samples[channel.sample];
+        }
+        let sample = match channel.bank {
+            SampleBank::Music => &self.module.samples[channel.sample],
+            SampleBank::Sfx => &self.sfx.samples[channel.sample],
+        };
+        // sound effects always play with Amiga periods
+        let linear = self.module.linear_periods && channel.bank == SampleBank::Music;
+        if note.sample.is_some() {
             channel.volume = sample.volume;
+            if let Some(pan) = sample.panning {
+                channel.pan = pan.min(PAN_RIGHT);
+            }
+        }
+        if let Some(volume) = note.volume {
+            channel.volume = volume.min(0x40);
         }
-        let sample = &self.module.samples[channel.sample];
         if let Some(xperiod) = note.period {
-            let period = PERIODS[sample.finetune as usize][xperiod as usize];
+            let period = note_period(sample, xperiod, linear);
             channel.xperiod = xperiod;
             channel.period = period;
             channel.sample_pos = channel.sample_pos_reload;
             channel.vibrato_phase = 0;
-            let byte_len = 0x361f0f / (period as u32);
-            channel.sample_bytes_per_frame = ((byte_len as u64) << 32) / (self.sample_rate as u64);
+            channel.sample_bytes_per_frame = frame_step(period, linear, self.sample_rate);
         }
         match note.tone_effect {
             super::ToneEffect::None => channel.tone_effect = ChannelToneEffect::None,
             super::ToneEffect::Arpeggio(a, b) => {
                 channel.tone_effect = ChannelToneEffect::Arpeggio;
                 channel.arpeggio_periods = [
-                    PERIODS[sample.finetune as usize][(channel.xperiod + a).min(35) as usize],
-                    PERIODS[sample.finetune as usize][(channel.xperiod + b).min(35) as usize],
+                    note_period(sample, (channel.xperiod + a).min(LAST_NOTE), linear),
+                    note_period(sample, (channel.xperiod + b).min(LAST_NOTE), linear),
                 ];
             }
             super::ToneEffect::Portamento { target, speed } => {
                 channel.tone_effect = ChannelToneEffect::Portamento;
                 if let Some(v) = target {
-                    channel.portamento_target = PERIODS[sample.finetune as usize][v as usize];
+                    channel.portamento_target = note_period(sample, v, linear);
                 }
                 if let Some(v) = speed {
                     channel.portamento_speed = v.into();
@@ -422,6 +1355,13 @@ impl PlayerState {
                     channel.vibrato_depth = v.get();
                 }
             }
+            super::ToneEffect::FinePortamento(delta) => {
+                channel.tone_effect = ChannelToneEffect::None;
+                let delta = delta as i16 * if linear { LINEAR_SLIDE as i16 } else { 1 };
+                channel.period = channel.period.saturating_add_signed(delta).max(1);
+                channel.sample_bytes_per_frame =
+                    frame_step(channel.period, linear, self.sample_rate);
+            }
         }
         match note.volume_effect {
             super::VolumeEffect::None => channel.volume_effect = ChannelVolumeEffect::None,
@@ -433,6 +1373,10 @@ impl PlayerState {
                 channel.volume_effect = ChannelVolumeEffect::Slide;
                 channel.volume_slide_speed = s;
             }
+            super::VolumeEffect::FineVolumeSlide(s) => {
+                channel.volume_effect = ChannelVolumeEffect::None;
+                channel.volume = channel.volume.saturating_add_signed(s).min(0x40);
+            }
             super::VolumeEffect::Reset => {
                 channel.volume_effect = ChannelVolumeEffect::None;
                 channel.volume = sample.volume;
@@ -446,7 +1390,24 @@ impl PlayerState {
                     channel.sample_pos = channel.sample_pos_reload;
                 }
             }
-            MiscEffect::PositionJump(pos) => self.jump(pos),
+            MiscEffect::PositionJump(pos) => {
+                let transition = self
+                    .jingles
+                    .current()
+                    .map_or(Transition::Cut, |j| j.return_transition);
+                let target = if self.held_cut.is_some() {
+                    // the part being faded out does not count towards the
+                    // repeats of what comes after it
+                    JumpTarget::Song(pos)
+                } else {
+                    self.jingles.position_jump(pos)
+                };
+                self.jump = Some(Jump {
+                    effect: pos,
+                    target,
+                    transition,
+                });
+            }
             MiscEffect::PatternBreak(x) => {
                 self.pattern_break = Some(x);
             }
@@ -455,15 +1416,20 @@ impl PlayerState {
                 channel.retrig_period = x;
                 channel.retrig_left = x - 1;
             }
+            // ignored, as in the timing analysis
+            MiscEffect::SetSpeed(0) => {}
             MiscEffect::SetSpeed(s) => {
                 self.speed = s;
                 self.ticks_left = s - 1;
             }
+            MiscEffect::SetPanning(pan) => channel.pan = pan.min(PAN_RIGHT),
+            MiscEffect::SetGlobalVolume(volume) => self.global_volume = volume.min(0x40),
         }
     }
 
     fn play_effects(&mut self) {
-        for channel in &mut self.channels {
+        for channel in &mut self.channels[..self.module.channels()] {
+            let linear = self.module.linear_periods && channel.bank == SampleBank::Music;
             match channel.tone_effect {
                 ChannelToneEffect::None => {}
                 ChannelToneEffect::Arpeggio => {
@@ -471,27 +1437,27 @@ impl PlayerState {
                     channel.period = channel.arpeggio_periods[1];
                     channel.arpeggio_periods[1] = channel.arpeggio_periods[0];
                     channel.arpeggio_periods[0] = tmp;
-                    let byte_len = 0x361f0f / (channel.period as u32);
                     channel.sample_bytes_per_frame =
-                        ((byte_len as u64) << 32) / (self.sample_rate as u64);
+                        frame_step(channel.period, linear, self.sample_rate);
                 }
                 ChannelToneEffect::Portamento => {
                     // println!("PORTAMENTO!");
                     if channel.portamento_target != 0 {
+                        let speed =
+                            channel.portamento_speed as u16 * if linear { LINEAR_SLIDE } else { 1 };
                         if channel.portamento_target < channel.period {
-                            channel.period -= channel.portamento_speed as u16;
+                            channel.period = channel.period.saturating_sub(speed);
                             if channel.period < channel.portamento_target {
                                 channel.period = channel.portamento_target;
                             }
                         } else {
-                            channel.period += channel.portamento_speed as u16;
+                            channel.period = channel.period.saturating_add(speed);
                             if channel.period > channel.portamento_target {
                                 channel.period = channel.portamento_target;
                             }
                         }
-                        let byte_len = 0x361f0f / (channel.period as u32);
                         channel.sample_bytes_per_frame =
-                            ((byte_len as u64) << 32) / (self.sample_rate as u64);
+                            frame_step(channel.period, linear, self.sample_rate);
                     }
                 }
                 ChannelToneEffect::Vibrato => {
@@ -499,15 +1465,13 @@ impl PlayerState {
                     channel.vibrato_phase = phase.wrapping_add(channel.vibrato_rate);
                     let mut delta = VIBRATO_LUT[(phase >> 2 & 0x1f) as usize] as i16;
                     delta *= channel.vibrato_depth as i16;
-                    delta >>= 7;
+                    delta >>= if linear { 5 } else { 7 };
                     if phase & 0x80 != 0 {
                         delta *= -1;
                     }
                     // println!("VIBRATO {delta}");
                     let period = channel.period.wrapping_add_signed(delta);
-                    let byte_len = 0x361f0f / (period as u32);
-                    channel.sample_bytes_per_frame =
-                        ((byte_len as u64) << 32) / (self.sample_rate as u64);
+                    channel.sample_bytes_per_frame = frame_step(period, linear, self.sample_rate);
                 }
                 ChannelToneEffect::Retrig => {
                     if channel.retrig_left == 0 {
@@ -532,9 +1496,124 @@ impl PlayerState {
         }
     }
 
-    fn play_channel(&mut self, idx: usize) -> i32 {
-        let channel = &mut self.channels[idx];
-        let sample = &self.module.samples[channel.sample];
+    fn play_channel(&mut self, idx: usize) -> (i32, i32) {
+        self.channels[idx].next_frame(&self.module, &self.sfx)
+    }
+}
+
+/// The period `sample` plays `note` at, linear or Amiga.
+fn note_period(sample: &Sample, note: u8, linear: bool) -> u16 {
+    if linear {
+        // C-4 is 4608, and every semitone 64 below the one before
+        let period = LINEAR_C0 - note as i32 * 64 - (sample.tuning as i32 >> 1);
+        return period.clamp(1, u16::MAX as i32) as u16;
+    }
+    match sample.c2spd {
+        None => {
+            let idx = note.clamp(MOD_FIRST_NOTE, MOD_FIRST_NOTE + 35) - MOD_FIRST_NOTE;
+            PERIODS[sample.finetune as usize & 0xf][idx as usize]
+        }
+        Some(c2spd) => {
+            // Scream Tracker plays C-4 at c2spd, through its own periods
+            let c2spd = if c2spd == 0 { 8363 } else { c2spd as u64 };
+            let st3_period = 8363 * 16 * ST3_PERIODS[note as usize % 12] * AMIGA_CLOCK;
+            let period = st3_period / ((c2spd * ST3_CLOCK) << (note / 12));
+            period.clamp(1, u16::MAX as u64) as u16
+        }
+    }
+}
+
+/// How far a channel moves through its sample per frame at `period`, in
+/// 1/2^32 frames.
+fn frame_step(period: u16, linear: bool, sample_rate: u32) -> u64 {
+    if linear {
+        // C-4 plays at 8363Hz, and the rate doubles every 768 periods down
+        let rate = 8363. * ((LINEAR_C0 as f64 - 48. * 64. - period as f64) / 768.).exp2();
+        (rate * (1u64 << 32) as f64 / sample_rate as f64) as u64
+    } else {
+        let byte_len = 0x361f0f / (period as u32);
+        ((byte_len as u64) << 32) / (sample_rate as u64)
+    }
+}
+
+impl ChannelState {
+    fn new() -> Self {
+        ChannelState {
+            volume: 0x40,
+            bank: SampleBank::Music,
+            sample: 0,
+            sample_pos: 0,
+            sample_bytes_per_frame: 0,
+            sample_pos_reload: 0,
+            period: 0,
+            vibrato_phase: 0,
+            tone_effect: ChannelToneEffect::None,
+            arpeggio_periods: [0, 0],
+            portamento_target: 0,
+            portamento_speed: 0,
+            vibrato_rate: 0,
+            vibrato_depth: 0,
+            volume_effect: ChannelVolumeEffect::None,
+            volume_slide_speed: 0,
+            retrig_period: 0,
+            retrig_left: 0,
+            // so that an arpeggio before any note starts from the bottom of
+            // the MOD period table
+            xperiod: MOD_FIRST_NOTE,
+            pan: PAN_LEFT,
+        }
+    }
+
+    /// The channel output, split between the left and the right side.  The
+    /// sides of stereo samples go to their own side.
+    fn pan(&self, left: i32, right: i32) -> (i64, i64) {
+        let pan = self.pan as i64;
+        (
+            (left as i64 * (PAN_RIGHT as i64 - pan)) >> 7,
+            (right as i64 * pan) >> 7,
+        )
+    }
+
+    fn snapshot(&self) -> ChannelSnapshot {
+        ChannelSnapshot {
+            period: self.period,
+            volume: self.volume,
+            sample: self.sample as u8,
+            sfx: self.bank == SampleBank::Sfx,
+            position: (self.sample_pos >> 32) as usize,
+            effect: match self.tone_effect {
+                ChannelToneEffect::None => ActiveEffect::None,
+                ChannelToneEffect::Portamento => ActiveEffect::Portamento {
+                    target: self.portamento_target,
+                    speed: self.portamento_speed,
+                },
+                ChannelToneEffect::Vibrato => ActiveEffect::Vibrato {
+                    rate: self.vibrato_rate,
+                    depth: self.vibrato_depth,
+                    phase: self.vibrato_phase,
+                },
+                ChannelToneEffect::Arpeggio => ActiveEffect::Arpeggio {
+                    periods: self.arpeggio_periods,
+                },
+                ChannelToneEffect::Retrig => ActiveEffect::Retrig {
+                    period: self.retrig_period,
+                    left: self.retrig_left,
+                },
+            },
+            volume_slide: match self.volume_effect {
+                ChannelVolumeEffect::None => None,
+                ChannelVolumeEffect::Slide => Some(self.volume_slide_speed),
+            },
+        }
+    }
+
+    /// The next frame of the channel, left and right.
+    fn next_frame(&mut self, module: &Mod, sfx: &SfxBank) -> (i32, i32) {
+        let channel = self;
+        let sample = match channel.bank {
+            SampleBank::Music => &module.samples[channel.sample],
+            SampleBank::Sfx => &sfx.samples[channel.sample],
+        };
         let mut pos = (channel.sample_pos >> 32) as usize;
         if let Some((rs, rl)) = sample.repeat {
             while pos >= rs + rl {
@@ -542,15 +1621,219 @@ impl PlayerState {
                 channel.sample_pos -= (rl as u64) << 32;
             }
         } else if pos >= sample.data.len() {
-            return 0;
+            return (0, 0);
         }
         channel.sample_pos += channel.sample_bytes_per_frame;
-        let mut val = sample.data[pos] as i32;
-        if val >= 0x80 {
-            val -= 0x100;
+        let volume = channel.volume as i32;
+        match &sample.data {
+            // most samples, so without the detour through 16 bits
+            SampleData::Bits8(data) => {
+                let val = ((data[pos] as i8 as i32) << 16) * volume;
+                (val, val)
+            }
+            data => {
+                let (left, right) = data.stereo_frame(pos);
+                (
+                    ((left as i32) << 8) * volume,
+                    ((right as i32) << 8) * volume,
+                )
+            }
+        }
+    }
+
+    /// Moves the sample position on as `frames` calls to `next_frame` would.
+    fn skip(&mut self, frames: u32, module: &Mod, sfx: &SfxBank) {
+        if frames == 0 {
+            return;
+        }
+        let sample = match self.bank {
+            SampleBank::Music => &module.samples[self.sample],
+            SampleBank::Sfx => &sfx.samples[self.sample],
+        };
+        let step = self.sample_bytes_per_frame;
+        match sample.repeat {
+            Some((rs, rl)) => {
+                let end = ((rs + rl) as u64) << 32;
+                let len = (rl as u64) << 32;
+                // wrap where the last frame starts, as next_frame does
+                let mut pos = self.sample_pos + (frames as u64 - 1) * step;
+                if pos >= end {
+                    pos -= ((pos - end) / len + 1) * len;
+                }
+                self.sample_pos = pos + step;
+            }
+            None => {
+                let end = (sample.data.len() as u64) << 32;
+                if self.sample_pos >= end {
+                    return;
+                }
+                // the position stops once it runs past the end
+                let left = if step == 0 {
+                    frames as u64
+                } else {
+                    (end - self.sample_pos).div_ceil(step)
+                };
+                self.sample_pos += left.min(frames as u64) * step;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::sound::loader;
+
+    /// A cell playing sample 1 at C-2, with `effect`.
+    pub(super) fn cell(effect: u16) -> u32 {
+        428 << 16 | 1 << 12 | effect as u32
+    }
+
+    /// A one-pattern MOD with a looped saw wave as sample 1 and the given
+    /// cells set, as `(row, channel, cell)`.
+    pub(super) fn song(cells: &[(usize, usize, u32)]) -> Mod {
+        let mut data = vec![0; 20];
+        for i in 0..31 {
+            let mut header = [0; 30];
+            if i == 0 {
+                header[22..24].copy_from_slice(&16u16.to_be_bytes());
+                header[25] = 0x40;
+                header[28..30].copy_from_slice(&16u16.to_be_bytes());
+            }
+            data.extend(header);
+        }
+        data.extend([1, 0x7f]);
+        data.extend([0; 128]);
+        data.extend(b"M.K.");
+        let mut pattern = [[0u32; 4]; 0x40];
+        for &(row, channel, cell) in cells {
+            pattern[row][channel] = cell;
+        }
+        data.extend(pattern.iter().flatten().flat_map(|cell| cell.to_be_bytes()));
+        data.extend((0..32).map(|i| (i * 8 - 128) as u8));
+        loader::load(&mut Cursor::new(data)).unwrap()
+    }
+
+    /// An offline player with the row trace on.
+    pub(super) fn offline(module: Mod) -> (Player, PlayerState) {
+        let config = PlayerConfig {
+            trace_rows: true,
+            ..Default::default()
+        };
+        play_offline(module, SfxBank::new(), config)
+    }
+
+    #[test]
+    fn traces_rows() {
+        let (mut player, mut state) = offline(song(&[(0, 0, cell(0)), (2, 1, 0xd00)]));
+        let trace = player.take_row_trace().unwrap();
+        for _ in 0..18 {
+            state.tick();
+        }
+        let rows: Vec<_> = trace.try_iter().collect();
+        let flows: Vec<_> = rows.iter().map(|t| (t.row, t.flow)).collect();
+        assert_eq!(
+            flows,
+            [(0, RowFlow::Next), (1, RowFlow::Next), (2, RowFlow::Break)]
+        );
+        assert_eq!(rows[0].notes[0].sample, Some(1));
+        assert_eq!(rows[2].notes[0].sample, None);
+        // the break lands on the first row of the next position
+        state.tick();
+        assert_eq!(trace.try_recv().unwrap().row, 0);
+        assert!(player.events().any(|e| e == PlayerEvent::SongLooped));
+    }
+
+    #[test]
+    fn swaps_modules() {
+        let (mut player, mut state) = offline(song(&[(0, 0, cell(0))]));
+        for _ in 0..3 {
+            state.tick();
         }
-        val <<= 16;
-        val *= channel.volume as i32;
-        val
+        let next = song(&[(0, 1, cell(0))]);
+        player.load_module(next, Transition::Cut).unwrap();
+        state.process_cmd();
+        assert_eq!(state.module.patterns[0][0][1].sample, Some(1));
+        assert_eq!((state.position, state.row), (0, 0));
+        assert!(player.events().any(|e| e == PlayerEvent::ModuleChanged));
+        // a crossfade plays from the old module until it has faded out
+        player
+            .load_module(song(&[]), Transition::Crossfade(4))
+            .unwrap();
+        state.process_cmd();
+        let fading = state.fading.as_ref().unwrap();
+        assert_eq!(
+            fading.module.as_ref().unwrap().patterns[0][0][1].sample,
+            Some(1)
+        );
+        for _ in 0..4 {
+            state.tick();
+        }
+        assert!(state.fading.is_none());
+    }
+
+    #[test]
+    fn renders_offline() {
+        let render = || {
+            let (player, mut state) = offline(song(&[(0, 0, cell(0)), (0, 3, cell(0))]));
+            let mut buf = vec![0; state.sample_rate() as usize / 50 * 2 * 12];
+            state.render(&mut buf);
+            (player.status(), buf)
+        };
+        let (status, buf) = render();
+        // twelve ticks at speed 6
+        assert_eq!(status, (0, 1));
+        assert!(buf.iter().any(|&v| v != 0));
+        assert_eq!(render().1, buf);
+    }
+
+    #[test]
+    fn plays_without_a_device() {
+        let player = play_null(song(&[]), SfxBank::new(), PlayerConfig::default());
+        let start = std::time::Instant::now();
+        // a row takes 120ms at speed 6
+        while player.status() == (0, 0) {
+            assert!(start.elapsed().as_secs() < 5, "null output is not clocking");
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn steps_ticks_and_rows() {
+        // vibrato on row 0, a volume slide down on row 1; the phase moves
+        // by four times the rate
+        let (_player, mut state) = offline(song(&[(0, 0, cell(0x4a4)), (1, 0, 0xa02)]));
+        let phase = |state: &PlayerState| match state.channels()[0].effect {
+            ActiveEffect::Vibrato { phase, .. } => phase,
+            effect => panic!("no vibrato: {effect:?}"),
+        };
+        assert!(state.step_tick());
+        assert_eq!(state.status(), (0, 0));
+        let channel = state.channels()[0];
+        assert_eq!(
+            (channel.period, channel.volume, channel.sample),
+            (428, 0x40, 1)
+        );
+        assert_eq!(
+            channel.effect,
+            ActiveEffect::Vibrato {
+                rate: 0x28,
+                depth: 4,
+                phase: 0
+            }
+        );
+        assert!(!state.step_tick());
+        assert_eq!(phase(&state), 0x28);
+        assert!(!state.step_tick());
+        assert_eq!(phase(&state), 0x50);
+        assert_eq!(state.status(), (0, 0));
+        state.step_row();
+        assert_eq!(state.status(), (0, 1));
+        let channel = state.channels()[0];
+        assert_eq!(channel.effect, ActiveEffect::None);
+        assert_eq!(channel.volume_slide, Some(-2));
+        assert_eq!(state.channels()[1].sample, 0);
     }
 }
@@ -0,0 +1,654 @@
+use std::{error::Error, fmt::Display, num::NonZeroU8};
+
+use crate::sound::Mod;
+
+/// The highest jingle repeat count accepted by [`JingleRequest`].
+pub const MAX_REPEATS: u8 = 0xf;
+/// The highest priority accepted by [`JingleRequest`] and music priority.
+pub const MAX_PRIORITY: u8 = 0x3f;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum JingleMode {
+    /// Replaces the current jingle if its priority is not higher, is dropped
+    /// otherwise.
+    Normal,
+    /// Always replaces the current jingle.
+    Force,
+    /// Replaces the current jingle if its priority is not higher, waits in
+    /// the jingle queue until it is over otherwise.
+    Queue,
+}
+
+/// When a winning jingle actually cuts into the song.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum JingleStart {
+    /// At the next audio buffer, in the middle of whatever is playing.
+    Now,
+    /// At the start of the next row.
+    NextRow,
+    /// At the start of the next row that is a multiple of the given number of
+    /// rows.
+    NextBeat(NonZeroU8),
+    /// At the start of the next pattern.
+    NextPattern,
+}
+
+impl JingleStart {
+    /// Whether a held jingle should start on the row about to be played.
+    /// `pattern_start` tells if that row is the first one played in its
+    /// pattern.
+    pub fn is_due(self, row: usize, pattern_start: bool) -> bool {
+        match self {
+            JingleStart::Now | JingleStart::NextRow => true,
+            JingleStart::NextBeat(rows) => row.is_multiple_of(rows.get() as usize),
+            JingleStart::NextPattern => pattern_start,
+        }
+    }
+}
+
+/// How playback changes over from one part of the song to another.  Fade
+/// lengths are in ticks.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Transition {
+    /// A hard cut.
+    Cut,
+    /// Fade the old part out, then cut to the new one at full volume.
+    FadeOut(u16),
+    /// Cut to the new part and fade it in.
+    FadeIn(u16),
+    /// Let the notes of the old part ring out while fading them out, and fade
+    /// the new part in at the same time.
+    Crossfade(u16),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct JingleRequest {
+    /// The order position the jingle starts at.
+    pub position: u8,
+    /// The number of position jumps the jingle plays until returning to the
+    /// music, or 0 to keep playing it forever.
+    pub repeats: u8,
+    pub priority: u8,
+    pub mode: JingleMode,
+    pub start: JingleStart,
+    /// If interrupted by another jingle, wait in the jingle queue and continue
+    /// where it left off, instead of being discarded.
+    pub resume: bool,
+    /// How the jingle cuts in.
+    pub transition: Transition,
+    /// How playback goes on once the jingle runs out of repeats.
+    pub return_transition: Transition,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CommandError {
+    PositionOutOfRange(u8),
+    RepeatsOutOfRange(u8),
+    PriorityOutOfRange(u8),
+    ChannelOutOfRange(u8),
+    SampleOutOfRange(u8),
+    PeriodOutOfRange(u8),
+    RowOutOfRange(u8),
+    /// Seeking to a row the song never plays.
+    RowUnreachable {
+        position: u8,
+        row: u8,
+    },
+    /// The audio thread has not yet picked up enough of the previous commands.
+    QueueFull,
+    /// The audio thread is gone, as its output stopped or its state was
+    /// dropped.
+    Disconnected,
+    /// Replaying a module load without the module it loaded.
+    ModuleMissing(u32),
+    /// The call log to replay lost this many calls when it was recorded.
+    CallsDropped(u32),
+}
+
+impl Display for CommandError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            CommandError::PositionOutOfRange(x) => write!(f, "position {x:#x} out of range"),
+            CommandError::RepeatsOutOfRange(x) => write!(f, "repeat count {x:#x} out of range"),
+            CommandError::PriorityOutOfRange(x) => write!(f, "priority {x:#x} out of range"),
+            CommandError::ChannelOutOfRange(x) => write!(f, "channel {x} out of range"),
+            CommandError::SampleOutOfRange(x) => write!(f, "sample {x:#x} out of range"),
+            CommandError::PeriodOutOfRange(x) => write!(f, "period {x} out of range"),
+            CommandError::RowOutOfRange(x) => write!(f, "row {x:#x} out of range"),
+            CommandError::RowUnreachable { position, row } => {
+                write!(f, "row {position:02x}.{row:02x} is never played")
+            }
+            CommandError::QueueFull => write!(f, "player command queue full"),
+            CommandError::Disconnected => write!(f, "audio thread stopped"),
+            CommandError::ModuleMissing(x) => write!(f, "module {x} to load not given"),
+            CommandError::CallsDropped(x) => write!(f, "{x} calls missing from the call log"),
+        }
+    }
+}
+
+impl Error for CommandError {}
+
+impl JingleRequest {
+    pub fn validate(&self, song_len: usize) -> Result<(), CommandError> {
+        if self.position as usize >= song_len {
+            return Err(CommandError::PositionOutOfRange(self.position));
+        }
+        if self.repeats > MAX_REPEATS {
+            return Err(CommandError::RepeatsOutOfRange(self.repeats));
+        }
+        if self.priority > MAX_PRIORITY {
+            return Err(CommandError::PriorityOutOfRange(self.priority));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct SfxRequest {
+    pub period: u8,
+    pub sample: u8,
+    pub volume: u8,
+    pub channel: u8,
+}
+
+/// A control call, sent from [`super::Player`] to the audio thread.
+pub(crate) enum Command {
+    Jingle(JingleRequest),
+    SetMusicPos(u8),
+    SetMusicPrio(u8),
+    SetMusicResume(bool),
+    Sfx(SfxRequest),
+    Fade {
+        level: u32,
+        ticks: u16,
+    },
+    /// The module, how it comes in, and its number among the modules loaded
+    /// on the player, from 1.
+    LoadModule(Box<Mod>, Transition, u32),
+    /// Seek to this many ticks into the song.
+    Seek(u32),
+}
+
+/// Where a position jump effect takes the song.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum JumpTarget {
+    /// Jump within the song, as the effect says.
+    Song(u8),
+    /// The current jingle ran out of repeats; the next one from the queue
+    /// takes over.
+    Jingle { position: u8, row: u8, priority: u8 },
+    /// The current jingle ran out of repeats; go back to the music.
+    Music { position: u8, row: u8 },
+}
+
+impl JumpTarget {
+    pub fn position(self) -> u8 {
+        match self {
+            JumpTarget::Song(position)
+            | JumpTarget::Jingle { position, .. }
+            | JumpTarget::Music { position, .. } => position,
+        }
+    }
+
+    pub fn row(self) -> u8 {
+        match self {
+            JumpTarget::Song(_) => 0,
+            JumpTarget::Jingle { row, .. } | JumpTarget::Music { row, .. } => row,
+        }
+    }
+}
+
+/// What became of a jingle request.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RequestOutcome {
+    /// The jingle won; it starts once [`JingleState::start`] is called.
+    Accepted,
+    /// The jingle waits in the queue.
+    Queued,
+    Dropped,
+}
+
+#[derive(Copy, Clone, Debug)]
+struct QueuedJingle {
+    req: JingleRequest,
+    repeats: u8,
+    /// Where an interrupted jingle continues, or `None` if it has not
+    /// started yet.
+    resume_at: Option<(u8, u8)>,
+    seq: u32,
+}
+
+impl QueuedJingle {
+    /// Higher keys play first: by priority, then interrupted jingles most
+    /// recent first (like a stack), then waiting ones oldest first.
+    fn key(&self) -> (u8, bool, u32) {
+        match self.resume_at {
+            Some(_) => (self.req.priority, true, self.seq),
+            None => (self.req.priority, false, !self.seq),
+        }
+    }
+}
+
+/// The jingle state machine.  It decides which jingle requests get to play,
+/// keeps the ones waiting for their turn, and decides where the song goes
+/// when it hits a position jump.
+#[derive(Clone, Debug)]
+pub struct JingleState {
+    priority: u8,
+    repeats: u8,
+    music_pos: u8,
+    music_prio: u8,
+    music_resume: bool,
+    /// Where the music was when a jingle interrupted it.
+    music_resume_at: Option<(u8, u8)>,
+    /// The jingle playing, or `None` for the music.
+    current: Option<JingleRequest>,
+    /// The jingle that won, but has not cut in yet.
+    pending: Option<JingleRequest>,
+    queue: Vec<QueuedJingle>,
+    queue_depth: usize,
+    seq: u32,
+}
+
+impl JingleState {
+    /// Makes the state machine, with room for `queue_depth` jingles waiting
+    /// to play.
+    pub fn new(queue_depth: usize) -> Self {
+        JingleState {
+            priority: 0,
+            repeats: 0,
+            music_pos: 0,
+            music_prio: 0,
+            music_resume: false,
+            music_resume_at: None,
+            current: None,
+            pending: None,
+            queue: Vec::with_capacity(queue_depth),
+            queue_depth,
+            seq: 0,
+        }
+    }
+
+    /// The priority of whatever is playing now.
+    pub fn priority(&self) -> u8 {
+        self.priority
+    }
+
+    /// The position jumps left until the current jingle ends, or 0 if it
+    /// plays forever.
+    pub fn repeats(&self) -> u8 {
+        self.repeats
+    }
+
+    pub fn music_pos(&self) -> u8 {
+        self.music_pos
+    }
+
+    pub fn music_prio(&self) -> u8 {
+        self.music_prio
+    }
+
+    /// The jingle playing, or `None` for the music.
+    pub fn current(&self) -> Option<&JingleRequest> {
+        self.current.as_ref()
+    }
+
+    /// The number of jingles waiting in the queue.
+    pub fn queued(&self) -> usize {
+        self.queue.len()
+    }
+
+    fn enqueue(&mut self, req: JingleRequest, repeats: u8, resume_at: Option<(u8, u8)>) -> bool {
+        let entry = QueuedJingle {
+            req,
+            repeats,
+            resume_at,
+            seq: self.seq,
+        };
+        self.seq = self.seq.wrapping_add(1);
+        if self.queue.len() < self.queue_depth {
+            self.queue.push(entry);
+            return true;
+        }
+        // full; make room by dropping the least important entry, if it is
+        // less important than the new one
+        let Some((idx, worst)) = self.queue.iter().enumerate().min_by_key(|(_, e)| e.key()) else {
+            return false;
+        };
+        if worst.req.priority >= req.priority {
+            return false;
+        }
+        self.queue[idx] = entry;
+        true
+    }
+
+    fn dequeue(&mut self) -> Option<QueuedJingle> {
+        let (idx, _) = self.queue.iter().enumerate().max_by_key(|(_, e)| e.key())?;
+        Some(self.queue.swap_remove(idx))
+    }
+
+    /// Offers a jingle.
+    pub fn request(&mut self, req: &JingleRequest) -> RequestOutcome {
+        if req.priority < self.priority && req.mode != JingleMode::Force {
+            if req.mode == JingleMode::Queue && self.enqueue(*req, req.repeats, None) {
+                return RequestOutcome::Queued;
+            }
+            return RequestOutcome::Dropped;
+        }
+        if let Some(old) = self.pending.take() {
+            // it never got to play, so it may still wait for its turn
+            if old.resume || old.mode == JingleMode::Queue {
+                self.enqueue(old, old.repeats, None);
+            }
+        }
+        self.priority = req.priority;
+        self.pending = Some(*req);
+        RequestOutcome::Accepted
+    }
+
+    /// Forgets everything about the song, for when another module is loaded.
+    /// The settings made at creation and the music resume setting are kept.
+    pub fn reset(&mut self) {
+        self.priority = 0;
+        self.repeats = 0;
+        self.music_pos = 0;
+        self.music_prio = 0;
+        self.music_resume_at = None;
+        self.current = None;
+        self.pending = None;
+        self.queue.clear();
+    }
+
+    /// Drops the current, accepted and queued jingles and goes back to the
+    /// music, for when the music is moved elsewhere.
+    pub fn cancel(&mut self) {
+        self.priority = self.music_prio;
+        self.repeats = 0;
+        self.music_resume_at = None;
+        self.current = None;
+        self.pending = None;
+        self.queue.clear();
+    }
+
+    /// Cuts the accepted jingle in, with the song currently at `position` and
+    /// about to play `row`.  Returns the jingle to start.
+    pub fn start(&mut self, position: u8, row: u8) -> Option<JingleRequest> {
+        let req = self.pending.take()?;
+        match self.current {
+            None => self.music_resume_at = Some((position, row)),
+            Some(cur) => {
+                if cur.resume {
+                    self.enqueue(cur, self.repeats, Some((position, row)));
+                }
+            }
+        }
+        self.current = Some(req);
+        self.repeats = req.repeats;
+        Some(req)
+    }
+
+    /// Cuts the accepted jingle in if the row about to be played is the
+    /// boundary it waits for.
+    pub fn start_due(
+        &mut self,
+        position: u8,
+        row: usize,
+        pattern_start: bool,
+    ) -> Option<JingleRequest> {
+        if self.pending?.start.is_due(row, pattern_start) {
+            self.start(position, row as u8)
+        } else {
+            None
+        }
+    }
+
+    /// Sets the music position.  This also forgets where the music was
+    /// interrupted, as the game now wants another part of it.
+    pub fn set_music_pos(&mut self, pos: u8) {
+        self.music_pos = pos;
+        self.music_resume_at = None;
+    }
+
+    /// Sets whether the music continues where a jingle interrupted it,
+    /// instead of starting over at the music position.
+    pub fn set_music_resume(&mut self, resume: bool) {
+        self.music_resume = resume;
+    }
+
+    pub fn music_resume(&self) -> bool {
+        self.music_resume
+    }
+
+    pub fn set_music_prio(&mut self, prio: u8) {
+        self.music_prio = prio;
+    }
+
+    /// Called when the song hits a position jump to `pos`.  Returns where to
+    /// actually go.
+    pub fn position_jump(&mut self, pos: u8) -> JumpTarget {
+        match self.repeats {
+            // nothing to worry about, just jump
+            0 => JumpTarget::Song(pos),
+            // repeat ran out, jump to the next jingle or the music instead
+            1 => self.finish(),
+            _ => {
+                self.repeats -= 1;
+                JumpTarget::Song(pos)
+            }
+        }
+    }
+
+    fn finish(&mut self) -> JumpTarget {
+        self.current = None;
+        self.repeats = 0;
+        if self.pending.is_some() {
+            // another jingle is about to cut in anyway
+            return self.return_to_music();
+        }
+        let Some(next) = self.dequeue() else {
+            self.priority = self.music_prio;
+            return self.return_to_music();
+        };
+        self.current = Some(next.req);
+        self.repeats = next.repeats;
+        self.priority = next.req.priority;
+        let (position, row) = next.resume_at.unwrap_or((next.req.position, 0));
+        JumpTarget::Jingle {
+            position,
+            row,
+            priority: next.req.priority,
+        }
+    }
+
+    fn return_to_music(&mut self) -> JumpTarget {
+        let resume_at = self.music_resume_at.take();
+        let (position, row) = match resume_at {
+            Some(at) if self.music_resume => at,
+            _ => (self.music_pos, 0),
+        };
+        JumpTarget::Music { position, row }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn req(position: u8, priority: u8, mode: JingleMode) -> JingleRequest {
+        JingleRequest {
+            position,
+            repeats: 1,
+            priority,
+            mode,
+            start: JingleStart::Now,
+            resume: false,
+            transition: Transition::Cut,
+            return_transition: Transition::Cut,
+        }
+    }
+
+    /// Requests a jingle and cuts it in at once.
+    fn play(state: &mut JingleState, req: JingleRequest, at: (u8, u8)) {
+        assert_eq!(state.request(&req), RequestOutcome::Accepted);
+        assert_eq!(state.start(at.0, at.1), Some(req));
+    }
+
+    #[test]
+    fn priority() {
+        let mut state = JingleState::new(4);
+        play(&mut state, req(1, 5, JingleMode::Normal), (0, 0));
+        assert_eq!(state.priority(), 5);
+        assert_eq!(
+            state.request(&req(2, 4, JingleMode::Normal)),
+            RequestOutcome::Dropped
+        );
+        assert_eq!(state.current().unwrap().position, 1);
+        // the same priority wins
+        play(&mut state, req(3, 5, JingleMode::Normal), (1, 4));
+        assert_eq!(state.queued(), 0);
+    }
+
+    #[test]
+    fn force() {
+        let mut state = JingleState::new(4);
+        play(&mut state, req(1, 5, JingleMode::Normal), (0, 0));
+        play(&mut state, req(2, 1, JingleMode::Force), (1, 0));
+        assert_eq!(state.priority(), 1);
+    }
+
+    #[test]
+    fn queue() {
+        let mut state = JingleState::new(1);
+        state.set_music_pos(6);
+        play(&mut state, req(1, 5, JingleMode::Normal), (0, 0));
+        assert_eq!(
+            state.request(&req(2, 1, JingleMode::Queue)),
+            RequestOutcome::Queued
+        );
+        // a full queue makes room for more important jingles only
+        assert_eq!(
+            state.request(&req(3, 2, JingleMode::Queue)),
+            RequestOutcome::Queued
+        );
+        assert_eq!(
+            state.request(&req(4, 2, JingleMode::Queue)),
+            RequestOutcome::Dropped
+        );
+        assert_eq!(state.queued(), 1);
+        assert_eq!(
+            state.position_jump(0),
+            JumpTarget::Jingle {
+                position: 3,
+                row: 0,
+                priority: 2
+            }
+        );
+        assert_eq!(state.priority(), 2);
+        assert_eq!(
+            state.position_jump(0),
+            JumpTarget::Music {
+                position: 6,
+                row: 0
+            }
+        );
+        assert_eq!(state.priority(), 0);
+        assert!(state.current().is_none());
+    }
+
+    #[test]
+    fn repeats() {
+        let mut state = JingleState::new(4);
+        play(
+            &mut state,
+            JingleRequest {
+                repeats: 3,
+                ..req(1, 5, JingleMode::Normal)
+            },
+            (0, 0),
+        );
+        assert_eq!(state.position_jump(2), JumpTarget::Song(2));
+        assert_eq!(state.position_jump(2), JumpTarget::Song(2));
+        assert_eq!(state.repeats(), 1);
+        assert_eq!(
+            state.position_jump(2),
+            JumpTarget::Music {
+                position: 0,
+                row: 0
+            }
+        );
+        // no repeats plays forever
+        play(
+            &mut state,
+            JingleRequest {
+                repeats: 0,
+                ..req(1, 5, JingleMode::Normal)
+            },
+            (0, 0),
+        );
+        for _ in 0..100 {
+            assert_eq!(state.position_jump(1), JumpTarget::Song(1));
+        }
+    }
+
+    #[test]
+    fn resume() {
+        let mut state = JingleState::new(4);
+        let resumable = JingleRequest {
+            resume: true,
+            ..req(1, 2, JingleMode::Normal)
+        };
+        play(&mut state, resumable, (4, 16));
+        play(&mut state, req(3, 5, JingleMode::Normal), (1, 8));
+        assert_eq!(state.queued(), 1);
+        assert_eq!(
+            state.position_jump(0),
+            JumpTarget::Jingle {
+                position: 1,
+                row: 8,
+                priority: 2
+            }
+        );
+        // the music starts over at its position, unless told to resume
+        assert_eq!(
+            state.position_jump(0),
+            JumpTarget::Music {
+                position: 0,
+                row: 0
+            }
+        );
+        state.set_music_resume(true);
+        play(&mut state, req(1, 2, JingleMode::Normal), (4, 16));
+        assert_eq!(
+            state.position_jump(0),
+            JumpTarget::Music {
+                position: 4,
+                row: 16
+            }
+        );
+    }
+
+    #[test]
+    fn start_due() {
+        let mut state = JingleState::new(4);
+        let next_pattern = JingleRequest {
+            start: JingleStart::NextPattern,
+            ..req(1, 5, JingleMode::Normal)
+        };
+        assert_eq!(state.request(&next_pattern), RequestOutcome::Accepted);
+        assert_eq!(state.start_due(0, 5, false), None);
+        assert!(state.current().is_none());
+        assert_eq!(state.start_due(1, 0, true), Some(next_pattern));
+        assert_eq!(state.current(), Some(&next_pattern));
+    }
+
+    #[test]
+    fn start_boundaries() {
+        let beat = JingleStart::NextBeat(NonZeroU8::new(4).unwrap());
+        assert!(JingleStart::Now.is_due(5, false));
+        assert!(JingleStart::NextRow.is_due(5, false));
+        assert!(beat.is_due(8, false));
+        assert!(!beat.is_due(6, false));
+        assert!(JingleStart::NextPattern.is_due(0, true));
+        assert!(!JingleStart::NextPattern.is_due(0, false));
+    }
+}
@@ -0,0 +1,44 @@
+use crate::sound::Row;
+
+/// Something that happened on the audio thread, reported to the game through
+/// [`super::Player::events`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PlayerEvent {
+    /// A jingle request won and the song jumped to its position.
+    JingleStarted { position: u8, priority: u8 },
+    /// A jingle ran out of repeats.  Playback continues at `position`, with
+    /// either the music or the next jingle from the queue.
+    JingleFinished { position: u8 },
+    /// The song ran past the end of its order list and wrapped around.
+    SongLooped,
+    /// The song took a position jump effect.
+    PositionJump { from: u8, to: u8 },
+    /// A module loaded with [`super::Player::load_module`] was swapped in.
+    ModuleChanged,
+    /// Playback moved to a new order position.
+    PatternChanged { position: u8, pattern: u8 },
+}
+
+/// What the song did after a traced row.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RowFlow {
+    /// Went on to the next row.
+    Next,
+    /// Took a position jump.
+    Jump,
+    /// Took a pattern break.
+    Break,
+    /// Ran off the end of the pattern.
+    PatternEnd,
+}
+
+/// A row played by the audio thread, reported through the row trace stream
+/// when [`super::PlayerConfig::trace_rows`] is set.
+#[derive(Clone, Debug)]
+pub struct RowTrace {
+    pub position: u8,
+    pub pattern: u8,
+    pub row: u8,
+    pub notes: Row,
+    pub flow: RowFlow,
+}
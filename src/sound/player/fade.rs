@@ -0,0 +1,92 @@
+/// Full volume, as a fader level.
+pub(crate) const FADE_ONE: u32 = 0x10000;
+
+/// A volume ramp applied on top of the channel volumes, moving by a fixed
+/// step every tick.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct Fader {
+    level: u32,
+    target: u32,
+    step: u32,
+}
+
+impl Fader {
+    pub fn new(level: u32) -> Self {
+        Fader {
+            level,
+            target: level,
+            step: 0,
+        }
+    }
+
+    pub fn level(&self) -> u32 {
+        self.level
+    }
+
+    /// Whether the fader has reached its target.
+    pub fn is_done(&self) -> bool {
+        self.level == self.target
+    }
+
+    pub fn set(&mut self, level: u32) {
+        *self = Fader::new(level);
+    }
+
+    pub fn fade_to(&mut self, target: u32, ticks: u16) {
+        self.target = target;
+        self.step = (self.level.abs_diff(target) / ticks.max(1) as u32).max(1);
+    }
+
+    pub fn tick(&mut self) {
+        if self.level < self.target {
+            self.level = (self.level + self.step).min(self.target);
+        } else {
+            self.level = self.level.saturating_sub(self.step).max(self.target);
+        }
+    }
+
+    pub fn apply(&self, val: i64) -> i64 {
+        if self.level == FADE_ONE {
+            val
+        } else {
+            (val * self.level as i64) >> 16
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fades_in_steps() {
+        let mut fader = Fader::new(FADE_ONE);
+        fader.fade_to(0, 4);
+        assert!(!fader.is_done());
+        let levels: Vec<_> = (0..4)
+            .map(|_| {
+                fader.tick();
+                fader.level()
+            })
+            .collect();
+        assert_eq!(levels, [0xc000, 0x8000, 0x4000, 0]);
+        assert!(fader.is_done());
+        // the level stays at the target
+        fader.tick();
+        assert_eq!(fader.level(), 0);
+        fader.fade_to(FADE_ONE, 0);
+        fader.tick();
+        assert_eq!(fader.level(), FADE_ONE);
+    }
+
+    #[test]
+    fn applies_level() {
+        let mut fader = Fader::new(FADE_ONE);
+        assert_eq!(fader.apply(-1234), -1234);
+        fader.set(FADE_ONE / 2);
+        assert_eq!(fader.apply(1000), 500);
+        assert_eq!(fader.apply(-1000), -500);
+        fader.set(0);
+        assert_eq!(fader.apply(i32::MAX as i64), 0);
+    }
+}
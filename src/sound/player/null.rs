@@ -0,0 +1,52 @@
+use std::{
+    sync::mpsc::{self, RecvTimeoutError, Sender},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use super::PlayerState;
+
+/// A virtual audio output with no device behind it.  It clocks the player
+/// state in real time on its own thread and throws the rendered audio away.
+pub(super) struct NullOutput {
+    stop: Sender<()>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl NullOutput {
+    pub fn start(mut state: PlayerState) -> NullOutput {
+        let (stop, stop_rx) = mpsc::channel();
+        let thread = thread::Builder::new()
+            .name("null audio output".into())
+            .spawn(move || {
+                // same buffer size as asked of a real device
+                let frames = state.sample_rate / 50;
+                let period = Duration::from_secs(1) * frames / state.sample_rate;
+                let mut buf = vec![0; frames as usize * 2];
+                let mut deadline = Instant::now();
+                loop {
+                    state.render(&mut buf);
+                    deadline += period;
+                    let timeout = deadline.saturating_duration_since(Instant::now());
+                    match stop_rx.recv_timeout(timeout) {
+                        Err(RecvTimeoutError::Timeout) => {}
+                        Ok(()) | Err(RecvTimeoutError::Disconnected) => return,
+                    }
+                }
+            })
+            .expect("failed to spawn null audio output thread");
+        NullOutput {
+            stop,
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Drop for NullOutput {
+    fn drop(&mut self) {
+        let _ = self.stop.send(());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
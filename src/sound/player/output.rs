@@ -0,0 +1,262 @@
+use std::{
+    error::Error,
+    fmt::Display,
+    mem,
+    sync::mpsc::{self, RecvTimeoutError, Sender, SyncSender},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use cpal::{
+    traits::{DeviceTrait, HostTrait, StreamTrait},
+    BufferSize, BuildStreamError, PlayStreamError, SampleRate, Stream, StreamConfig, StreamError,
+};
+
+use super::PlayerState;
+
+/// How long to wait between attempts to reopen a lost output device.
+const REOPEN_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug)]
+pub enum OutputError {
+    NoDevice,
+    BuildStream(BuildStreamError),
+    PlayStream(PlayStreamError),
+    /// An error reported by a running stream.  The stream is dropped, and the
+    /// player keeps trying to reopen the default device in the background.
+    Stream(StreamError),
+}
+
+impl Display for OutputError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OutputError::NoDevice => write!(f, "no output device available"),
+            OutputError::BuildStream(e) => write!(f, "failed to make stream: {e}"),
+            OutputError::PlayStream(e) => write!(f, "failed to start stream: {e}"),
+            OutputError::Stream(e) => write!(f, "audio error: {e}"),
+        }
+    }
+}
+
+impl Error for OutputError {}
+
+enum OutputMsg {
+    /// An error from the stream of the given generation.
+    Error(u32, StreamError),
+    Shutdown,
+}
+
+/// Holds the player state inside the audio callback, and hands it back to the
+/// output thread when the stream owning the callback is dropped.
+struct StateSlot {
+    state: Option<PlayerState>,
+    home: Sender<PlayerState>,
+}
+
+impl Drop for StateSlot {
+    fn drop(&mut self) {
+        if let Some(state) = self.state.take() {
+            let _ = self.home.send(state);
+        }
+    }
+}
+
+/// Opens a stream on the default device.  On failure, the state is sent home.
+fn open(slot: StateSlot, generation: u32, msgs: &Sender<OutputMsg>) -> Result<Stream, OutputError> {
+    let sample_rate = slot.state.as_ref().unwrap().sample_rate;
+    let device = cpal::default_host()
+        .default_output_device()
+        .ok_or(OutputError::NoDevice)?;
+    let config = StreamConfig {
+        channels: 2,
+        sample_rate: SampleRate(sample_rate),
+        buffer_size: BufferSize::Fixed(sample_rate / 50),
+    };
+    let mut slot = slot;
+    let msgs = msgs.clone();
+    let mut failed = false;
+    let stream = device
+        .build_output_stream(
+            &config,
+            move |data: &mut [i32], _: &cpal::OutputCallbackInfo| {
+                slot.state.as_mut().unwrap().render(data)
+            },
+            move |err| {
+                // a failing stream can report the same error on every
+                // callback; the first one is enough to reopen it
+                if !mem::replace(&mut failed, true) {
+                    let _ = msgs.send(OutputMsg::Error(generation, err));
+                }
+            },
+            None, // None=blocking, Some(Duration)=timeout
+        )
+        .map_err(OutputError::BuildStream)?;
+    stream.play().map_err(OutputError::PlayStream)?;
+    Ok(stream)
+}
+
+/// The audio output.  The stream lives on its own thread, which watches it
+/// for errors and reopens the default device, with the player state intact,
+/// once the stream fails.  Backends rarely say whether the device went away,
+/// so any stream error counts as a lost stream.
+pub(super) struct Output {
+    msgs: Sender<OutputMsg>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Output {
+    pub fn start(
+        state: PlayerState,
+        errors: SyncSender<OutputError>,
+    ) -> Result<Output, OutputError> {
+        Output::start_with(state, errors, open)
+    }
+
+    /// Starts the output thread with `open` standing in for opening a stream
+    /// on the default device.
+    fn start_with<S>(
+        state: PlayerState,
+        errors: SyncSender<OutputError>,
+        open: impl Fn(StateSlot, u32, &Sender<OutputMsg>) -> Result<S, OutputError> + Send + 'static,
+    ) -> Result<Output, OutputError> {
+        let (msgs, msgs_rx) = mpsc::channel();
+        let (ready, ready_rx) = mpsc::channel();
+        let thread_msgs = msgs.clone();
+        let thread = thread::Builder::new()
+            .name("audio output".into())
+            .spawn(move || {
+                let (home, state_rx) = mpsc::channel();
+                let slot = StateSlot {
+                    state: Some(state),
+                    home: home.clone(),
+                };
+                let mut generation = 0;
+                let mut stream = match open(slot, generation, &thread_msgs) {
+                    Ok(stream) => {
+                        ready.send(Ok(())).unwrap();
+                        stream
+                    }
+                    Err(e) => {
+                        ready.send(Err(e)).unwrap();
+                        return;
+                    }
+                };
+                loop {
+                    let err = match msgs_rx.recv() {
+                        Ok(OutputMsg::Error(gen, err)) if gen == generation => err,
+                        Ok(OutputMsg::Error(..)) => continue,
+                        Ok(OutputMsg::Shutdown) | Err(_) => return,
+                    };
+                    let _ = errors.try_send(OutputError::Stream(err));
+                    drop(stream);
+                    let mut state = state_rx.recv().unwrap();
+                    stream = loop {
+                        match msgs_rx.recv_timeout(REOPEN_INTERVAL) {
+                            Ok(OutputMsg::Shutdown) | Err(RecvTimeoutError::Disconnected) => return,
+                            Ok(OutputMsg::Error(..)) | Err(RecvTimeoutError::Timeout) => {}
+                        }
+                        generation += 1;
+                        let slot = StateSlot {
+                            state: Some(state),
+                            home: home.clone(),
+                        };
+                        match open(slot, generation, &thread_msgs) {
+                            Ok(stream) => break stream,
+                            Err(_) => state = state_rx.recv().unwrap(),
+                        }
+                    };
+                }
+            })
+            .expect("failed to spawn audio output thread");
+        match ready_rx.recv().unwrap() {
+            Ok(()) => Ok(Output {
+                msgs,
+                thread: Some(thread),
+            }),
+            Err(e) => {
+                thread.join().unwrap();
+                Err(e)
+            }
+        }
+    }
+}
+
+impl Drop for Output {
+    fn drop(&mut self) {
+        let _ = self.msgs.send(OutputMsg::Shutdown);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cpal::BackendSpecificError;
+
+    use super::*;
+    use crate::sound::{
+        player::{play_offline, tests::song, PlayerConfig},
+        sfx::SfxBank,
+    };
+
+    /// Stands in for a stream, holding the player state like the callback of
+    /// a real one.
+    struct FakeStream {
+        _slot: StateSlot,
+    }
+
+    /// Runs an output on a fake stream until `err` makes it reopen, and
+    /// returns the error reported.
+    fn reopen_after(err: StreamError) -> OutputError {
+        let (opened, opened_rx) = mpsc::channel();
+        let (errors, errors_rx) = mpsc::sync_channel(4);
+        let (_, state) = play_offline(song(&[]), SfxBank::new(), PlayerConfig::default());
+        let output = Output::start_with(state, errors, move |mut slot, generation, msgs| {
+            // play a row, so that the next open can tell the state carried over
+            let state = slot.state.as_mut().unwrap();
+            for _ in 0..state.speed {
+                state.tick();
+            }
+            opened.send((generation, state.row, msgs.clone())).unwrap();
+            Ok(FakeStream { _slot: slot })
+        })
+        .unwrap();
+        let (generation, row, msgs) = opened_rx.recv().unwrap();
+        assert_eq!(generation, 0);
+        msgs.send(OutputMsg::Error(0, err)).unwrap();
+        let reported = errors_rx.recv().unwrap();
+        let (generation, next_row, _) = opened_rx.recv().unwrap();
+        assert_eq!(generation, 1);
+        assert_eq!(next_row, row + 1);
+        // errors from the lost stream are ignored
+        msgs.send(OutputMsg::Error(0, StreamError::DeviceNotAvailable))
+            .unwrap();
+        drop(output);
+        assert!(errors_rx.try_recv().is_err());
+        assert!(opened_rx.try_recv().is_err());
+        reported
+    }
+
+    #[test]
+    fn reopens_lost_device() {
+        assert!(matches!(
+            reopen_after(StreamError::DeviceNotAvailable),
+            OutputError::Stream(StreamError::DeviceNotAvailable)
+        ));
+    }
+
+    #[test]
+    fn reopens_after_backend_error() {
+        // what ALSA and WASAPI report when the device is unplugged
+        let err = StreamError::BackendSpecific {
+            err: BackendSpecificError {
+                description: "device disconnected".into(),
+            },
+        };
+        assert!(matches!(
+            reopen_after(err),
+            OutputError::Stream(StreamError::BackendSpecific { .. })
+        ));
+    }
+}
@@ -0,0 +1,150 @@
+use std::sync::atomic::Ordering;
+
+use super::{
+    command::{Command, CommandError, JingleRequest, Transition},
+    set_bits, Player, PlayerState,
+};
+use crate::sound::Mod;
+
+/// A control call, as logged with [`super::PlayerConfig::record_calls`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Call {
+    Jingle(JingleRequest),
+    SetMusicPos(u8),
+    SetMusicPrio(u8),
+    SetMusicResume(bool),
+    Sfx {
+        period: u8,
+        sample: u8,
+        volume: u8,
+        channel: u8,
+    },
+    FadeOut(u16),
+    FadeIn(u16),
+    /// A seek, already resolved to a tick of the song.
+    Seek(u32),
+    SetPaused(bool),
+    SetMasterVolume(u8),
+    /// The mutes of all channels, one bit per channel.
+    SetChannelMutes(u32),
+    /// The solos of all channels, one bit per channel.
+    SetChannelSolos(u32),
+    /// A module load, with the number of the module among those loaded on
+    /// the player, from 1.  The module itself is not part of the call.
+    LoadModule {
+        number: u32,
+        transition: Transition,
+    },
+}
+
+/// A control call and when the audio thread picked it up.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct RecordedCall {
+    /// Frames rendered before the call took effect.
+    pub frame: u64,
+    /// Ticks played before the call took effect.
+    pub tick: u32,
+    /// Calls lost before this one because the log was full, which makes the
+    /// log useless for a replay.
+    pub dropped: u32,
+    pub call: Call,
+}
+
+impl Call {
+    pub(super) fn from_command(cmd: &Command) -> Call {
+        match *cmd {
+            Command::Jingle(req) => Call::Jingle(req),
+            Command::SetMusicPos(pos) => Call::SetMusicPos(pos),
+            Command::SetMusicPrio(prio) => Call::SetMusicPrio(prio),
+            Command::SetMusicResume(resume) => Call::SetMusicResume(resume),
+            Command::Sfx(sfx) => Call::Sfx {
+                period: sfx.period,
+                sample: sfx.sample,
+                volume: sfx.volume,
+                channel: sfx.channel,
+            },
+            Command::Fade { level: 0, ticks } => Call::FadeOut(ticks),
+            Command::Fade { ticks, .. } => Call::FadeIn(ticks),
+            Command::Seek(tick) => Call::Seek(tick),
+            Command::LoadModule(_, transition, number) => Call::LoadModule { number, transition },
+        }
+    }
+}
+
+impl Player {
+    /// Makes a control call described by a [`Call`].  Module loads need the
+    /// module, which only [`replay`] is given, so they fail here with
+    /// [`CommandError::ModuleMissing`].
+    pub fn call(&self, call: Call) -> Result<(), CommandError> {
+        match call {
+            Call::Jingle(req) => self.jingle(req),
+            Call::SetMusicPos(pos) => self.set_music_pos(pos),
+            Call::SetMusicPrio(prio) => self.set_music_prio(prio),
+            Call::SetMusicResume(resume) => self.set_music_resume(resume),
+            Call::Sfx {
+                period,
+                sample,
+                volume,
+                channel,
+            } => self.play_sfx(period, sample, volume, channel),
+            Call::FadeOut(ticks) => self.fade_out(ticks),
+            Call::FadeIn(ticks) => self.fade_in(ticks),
+            Call::Seek(tick) => self.seek_tick(tick as u128),
+            Call::SetPaused(paused) => {
+                self.set_paused(paused);
+                Ok(())
+            }
+            Call::SetMasterVolume(volume) => {
+                self.set_master_volume(volume);
+                Ok(())
+            }
+            Call::SetChannelMutes(bits) => set_bits(&self.control.mutes, bits, self.channels),
+            Call::SetChannelSolos(bits) => set_bits(&self.control.solos, bits, self.channels),
+            Call::LoadModule { number, .. } => Err(CommandError::ModuleMissing(number)),
+        }
+    }
+
+    /// Recorded calls lost so far because the call log was not drained fast
+    /// enough.  A log with calls missing does not replay the session.
+    pub fn dropped_calls(&self) -> u32 {
+        self.control.calls_dropped.load(Ordering::Relaxed)
+    }
+}
+
+/// Renders into `data` offline, making every call of `log` right before the
+/// frame it took effect at when it was recorded.  Given the module, sfx bank
+/// and config of the recorded session, and the modules it loaded in the order
+/// it loaded them, the output is the same bit for bit.  Frames count from the
+/// start of `state`, so a replay can be split over several calls.  A log that
+/// lost calls is refused.
+pub fn replay(
+    player: &mut Player,
+    state: &mut PlayerState,
+    log: &[RecordedCall],
+    modules: &[Mod],
+    data: &mut [i32],
+) -> Result<(), CommandError> {
+    let dropped = log.iter().map(|rec| rec.dropped).sum::<u32>();
+    if dropped != 0 {
+        return Err(CommandError::CallsDropped(dropped));
+    }
+    let mut pos = 0;
+    for rec in log {
+        let frames = rec.frame.saturating_sub(state.frames) as usize;
+        let end = (pos + frames * 2).min(data.len());
+        state.render(&mut data[pos..end]);
+        pos = end;
+        match rec.call {
+            Call::LoadModule { number, transition } => {
+                let module = (number as usize)
+                    .checked_sub(1)
+                    .and_then(|i| modules.get(i))
+                    .ok_or(CommandError::ModuleMissing(number))?;
+                player.load_module(module.clone(), transition)?;
+            }
+            call => player.call(call)?,
+        }
+    }
+    state.render(&mut data[pos..]);
+    Ok(())
+}
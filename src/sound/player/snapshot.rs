@@ -0,0 +1,52 @@
+/// The tone effect a channel is running on the ticks between rows.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ActiveEffect {
+    None,
+    Portamento { target: u16, speed: u8 },
+    Vibrato { rate: u8, depth: u8, phase: u8 },
+    Arpeggio { periods: [u16; 2] },
+    Retrig { period: u8, left: u8 },
+}
+
+/// What one channel is doing right now, returned by
+/// [`super::PlayerState::channels`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ChannelSnapshot {
+    /// The period of the channel, Amiga or linear as the module has it.
+    /// Arpeggio and portamento change it, vibrato bends the pitch on top of
+    /// it.
+    pub period: u16,
+    pub volume: u8,
+    /// Index of the sample in its bank; 0 is silence.
+    pub sample: u8,
+    /// True if the sample comes from the sound effect bank rather than the
+    /// module.
+    pub sfx: bool,
+    /// Integer part of the playback position within the sample data.
+    pub position: usize,
+    pub effect: ActiveEffect,
+    /// Volume slide speed per tick, if one is running.
+    pub volume_slide: Option<i8>,
+}
+
+/// How loud a channel is, returned by [`super::Player::channel_levels`].
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ChannelLevel {
+    pub volume: u8,
+    /// Peak of the channel output, 0xff being a full-scale sample at full
+    /// volume.
+    pub peak: u8,
+}
+
+impl ChannelLevel {
+    pub(super) fn to_bits(self) -> u32 {
+        self.volume as u32 | (self.peak as u32) << 8
+    }
+
+    pub(super) fn from_bits(bits: u32) -> Self {
+        ChannelLevel {
+            volume: bits as u8,
+            peak: (bits >> 8) as u8,
+        }
+    }
+}
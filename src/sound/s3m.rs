@@ -0,0 +1,502 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    io::{self, Read, Seek},
+    num::NonZeroU8,
+};
+
+use arrayref::array_ref;
+
+use super::{
+    MiscEffect, Mod, Note, Sample, SampleData, ToneEffect, VolumeEffect, LAST_NOTE, MAX_CHANNELS,
+    PAN_RIGHT,
+};
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn u16_at(buf: &[u8], pos: usize) -> u16 {
+    u16::from_le_bytes(*array_ref![buf, pos, 2])
+}
+
+fn u32_at(buf: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes(*array_ref![buf, pos, 4])
+}
+
+/// `len` bytes of the file from `pos`, or an error if the file ends first.
+fn chunk<'a>(file: &'a [u8], pos: usize, len: usize, what: &str) -> io::Result<&'a [u8]> {
+    file.get(pos..pos + len)
+        .ok_or_else(|| invalid(&format!("truncated {what}")))
+}
+
+fn name(buf: &[u8]) -> String {
+    String::from_utf8_lossy(buf)
+        .trim_end_matches('\0')
+        .to_string()
+}
+
+/// The only tempo the player runs at, 50 ticks a second.
+const TEMPO: u8 = 125;
+
+/// Something in an S3M file that does not play the way it would in Scream
+/// Tracker.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Warning {
+    /// The song is not at the tempo the player runs at.
+    Tempo(u8),
+    /// An effect that is left out, by its letter (and the first digit of the
+    /// argument for S), and how many times it is used.
+    UnsupportedEffect { effect: String, count: usize },
+    /// An effect that only plays roughly right, and how many times it is
+    /// used.
+    ApproximatedEffect { effect: String, count: usize },
+    /// How many Adlib channels are left out.
+    AdlibChannels(usize),
+    /// An Adlib instrument, left empty.
+    AdlibInstrument(u8),
+    /// A sample that runs past the end of the file, cut short there.
+    TruncatedSample(u8),
+}
+
+impl Display for Warning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Warning::Tempo(tempo) => {
+                write!(f, "tempo {tempo} is not supported, playing at {TEMPO}")
+            }
+            Warning::UnsupportedEffect { effect, count } => {
+                write!(
+                    f,
+                    "effect {effect} is not supported, left out {count} times"
+                )
+            }
+            Warning::ApproximatedEffect { effect, count } => {
+                write!(f, "effect {effect} is approximated, {count} times")
+            }
+            Warning::AdlibChannels(n) => {
+                write!(f, "{n} Adlib channels are not supported, left out")
+            }
+            Warning::AdlibInstrument(i) => {
+                write!(f, "instrument {i:02x} is an Adlib instrument, left empty")
+            }
+            Warning::TruncatedSample(i) => {
+                write!(f, "sample {i:02x} is truncated, cut short")
+            }
+        }
+    }
+}
+
+/// How often effects are left out or approximated, by name.
+#[derive(Default)]
+struct EffectCounts {
+    unsupported: BTreeMap<String, usize>,
+    approximated: BTreeMap<String, usize>,
+}
+
+impl EffectCounts {
+    fn unsupported(&mut self, effect: String) {
+        *self.unsupported.entry(effect).or_default() += 1;
+    }
+
+    fn approximated(&mut self, effect: String) {
+        *self.approximated.entry(effect).or_default() += 1;
+    }
+
+    fn warnings(self) -> impl Iterator<Item = Warning> {
+        let approximated = self
+            .approximated
+            .into_iter()
+            .map(|(effect, count)| Warning::ApproximatedEffect { effect, count });
+        self.unsupported
+            .into_iter()
+            .map(|(effect, count)| Warning::UnsupportedEffect { effect, count })
+            .chain(approximated)
+    }
+}
+
+/// Scream Tracker's panning, 0 to 0xf, as [`Mod::panning`].
+fn pan(pan: u8) -> u8 {
+    (pan as u16 * PAN_RIGHT as u16 / 0xf) as u8
+}
+
+/// A Dxy volume slide, fine or not.
+fn volume_slide(arg: u8) -> VolumeEffect {
+    let (hi, lo) = (arg >> 4, arg & 0xf);
+    match (hi, lo) {
+        (1.., 0xf) => VolumeEffect::FineVolumeSlide(hi as i8),
+        (0xf, 1..) => VolumeEffect::FineVolumeSlide(-(lo as i8)),
+        (_, 0) => VolumeEffect::VolumeSlide(hi as i8),
+        _ => VolumeEffect::VolumeSlide(-(lo as i8)),
+    }
+}
+
+/// Converts an S3M effect, `command` being 1 for A.  `positions` maps the
+/// order list of the file to [`Mod::positions`], which leaves out the
+/// markers.
+fn convert_effect(
+    note: &mut Note,
+    command: u8,
+    arg: u8,
+    slide_memory: &mut u8,
+    positions: &[u8],
+    counts: &mut EffectCounts,
+) {
+    let (hi, lo) = (arg >> 4, arg & 0xf);
+    let letter = match command {
+        1..=26 => (b'@' + command) as char,
+        _ => '?',
+    };
+    match letter {
+        'A' if arg != 0 => note.misc_effect = MiscEffect::SetSpeed(arg),
+        'A' => {}
+        'B' => {
+            let position = positions.get(arg as usize).copied().unwrap_or(0);
+            note.misc_effect = MiscEffect::PositionJump(position);
+        }
+        'C' => note.misc_effect = MiscEffect::PatternBreak(arg),
+        'D' | 'K' | 'L' => {
+            // the three of them share the last slide
+            if arg != 0 {
+                *slide_memory = arg;
+            }
+            note.volume_effect = volume_slide(*slide_memory);
+            match letter {
+                'K' => {
+                    note.tone_effect = ToneEffect::Vibrato {
+                        rate: None,
+                        depth: None,
+                    }
+                }
+                'L' => {
+                    note.tone_effect = ToneEffect::Portamento {
+                        target: note.period,
+                        speed: None,
+                    };
+                    note.period = None;
+                    note.sample = None;
+                }
+                _ => {}
+            }
+        }
+        'E' | 'F' => {
+            // E slides the pitch down, so the period up
+            let sign = if letter == 'E' { 1 } else { -1 };
+            match hi {
+                0xf => note.tone_effect = ToneEffect::FinePortamento(sign * lo as i8),
+                0xe => {
+                    // a quarter of a period, which periods are too coarse for
+                    counts.approximated(format!("{letter}E"));
+                    note.tone_effect = ToneEffect::FinePortamento(sign * (lo as i8 + 2) / 4);
+                }
+                _ => {
+                    note.tone_effect = ToneEffect::Portamento {
+                        target: Some(if letter == 'E' { 0 } else { LAST_NOTE }),
+                        speed: NonZeroU8::new(arg),
+                    }
+                }
+            }
+        }
+        'G' => {
+            note.tone_effect = ToneEffect::Portamento {
+                target: note.period,
+                speed: NonZeroU8::new(arg),
+            };
+            if note.sample.is_some() {
+                note.volume_effect = VolumeEffect::Reset;
+            }
+            note.period = None;
+            note.sample = None;
+        }
+        'H' => {
+            note.tone_effect = ToneEffect::Vibrato {
+                rate: NonZeroU8::new(hi),
+                depth: NonZeroU8::new(lo),
+            }
+        }
+        'J' if arg != 0 => note.tone_effect = ToneEffect::Arpeggio(hi, lo),
+        'J' => {}
+        'O' => note.misc_effect = MiscEffect::SetSampleOffset(arg),
+        'Q' if lo != 0 => {
+            if hi != 0 && hi != 8 {
+                // the volume change on every retrig
+                counts.approximated("Q".into());
+            }
+            note.misc_effect = MiscEffect::RetrigNote(lo);
+        }
+        'Q' => {}
+        'S' if hi == 8 => note.misc_effect = MiscEffect::SetPanning(pan(lo)),
+        'S' => counts.unsupported(format!("S{hi:X}")),
+        'V' => note.misc_effect = MiscEffect::SetGlobalVolume(arg.min(0x40)),
+        'X' if arg <= PAN_RIGHT => note.misc_effect = MiscEffect::SetPanning(arg),
+        'X' => {
+            // surround, which plays in the middle
+            counts.approximated(format!("X{arg:02X}"));
+            note.misc_effect = MiscEffect::SetPanning(PAN_RIGHT / 2);
+        }
+        'B'..='Z' => counts.unsupported(letter.to_string()),
+        _ => counts.unsupported(format!("{command:02x}")),
+    }
+}
+
+/// Reads the data of a sample, converted to signed.
+fn sample_data(
+    file: &[u8],
+    hdr: &[u8],
+    index: u8,
+    signed: bool,
+    warnings: &mut Vec<Warning>,
+) -> io::Result<SampleData> {
+    let offset = ((hdr[0x0d] as usize) << 16 | u16_at(hdr, 0x0e) as usize) * 16;
+    let len = u32_at(hdr, 0x10) as usize;
+    let flags = hdr[0x1f];
+    let stereo = flags & 2 != 0;
+    let width = if flags & 4 != 0 { 2 } else { 1 };
+    if hdr[0x1e] != 0 {
+        return Err(invalid(&format!("sample {index:02x} is packed")));
+    }
+    let bytes = len * width * if stereo { 2 } else { 1 };
+    let mut raw = file.get(offset..).unwrap_or(&[]);
+    // a truncated stereo sample ends with its left channel, the right one
+    // padded with silence
+    let mut frames = len;
+    if raw.len() < bytes {
+        warnings.push(Warning::TruncatedSample(index));
+        frames = frames.min(raw.len() / width);
+    } else {
+        raw = &raw[..bytes];
+    }
+    let frame = |pos: usize| -> i16 {
+        let pos = pos * width;
+        let val = match width {
+            1 => (raw.get(pos).copied().unwrap_or(0) as u16) << 8,
+            _ => raw.get(pos..pos + 2).map_or(0, |b| u16_at(b, 0)),
+        };
+        (if signed { val } else { val ^ 0x8000 }) as i16
+    };
+    let byte = |pos: usize| (frame(pos) >> 8) as u8;
+    // stereo samples have all of the left channel, then all of the right
+    Ok(match (width, stereo) {
+        (1, false) => SampleData::Bits8((0..frames).map(byte).collect()),
+        (1, true) => SampleData::Stereo8((0..frames).map(|i| [byte(i), byte(len + i)]).collect()),
+        (_, false) => SampleData::Bits16((0..frames).map(frame).collect()),
+        (_, true) => {
+            SampleData::Stereo16((0..frames).map(|i| [frame(i), frame(len + i)]).collect())
+        }
+    })
+}
+
+/// Loads an S3M file.  What does not convert is left out or approximated,
+/// and listed in the warnings.
+pub fn load(f: &mut (impl Read + Seek)) -> io::Result<(Mod, Vec<Warning>)> {
+    let mut file = vec![];
+    f.read_to_end(&mut file)?;
+    let hdr = chunk(&file, 0, 0x60, "S3M header")?;
+    if &hdr[0x2c..0x30] != b"SCRM" {
+        return Err(invalid("not an S3M file"));
+    }
+    let mut warnings = vec![];
+    let num_orders = u16_at(hdr, 0x20) as usize;
+    let num_instruments = u16_at(hdr, 0x22) as usize;
+    let num_patterns = u16_at(hdr, 0x24) as usize;
+    let signed = u16_at(hdr, 0x2a) == 1;
+    let global_volume = hdr[0x30].min(0x40);
+    let speed = match hdr[0x31] {
+        0 | 0xff => 6,
+        speed => speed,
+    };
+    let tempo = hdr[0x32];
+    if tempo != TEMPO {
+        warnings.push(Warning::Tempo(tempo));
+    }
+    let stereo = hdr[0x33] & 0x80 != 0;
+    if num_instruments > 0xff || num_patterns > 0x100 {
+        return Err(invalid("too many instruments or patterns"));
+    }
+
+    // the sample channels, in order; Adlib and unused channels are dropped
+    let settings = &hdr[0x40..0x60];
+    let mut channel_map = [None; MAX_CHANNELS];
+    let mut panning = vec![];
+    let mut adlib = 0;
+    for (i, &setting) in settings.iter().enumerate() {
+        match setting {
+            0..=15 => {
+                channel_map[i] = Some(panning.len());
+                panning.push(match (stereo, setting) {
+                    (false, _) => PAN_RIGHT / 2,
+                    (true, 0..=7) => pan(3),
+                    (true, _) => pan(0xc),
+                });
+            }
+            16..=31 => adlib += 1,
+            _ => {}
+        }
+    }
+    if adlib > 0 {
+        warnings.push(Warning::AdlibChannels(adlib));
+    }
+    if panning.is_empty() {
+        return Err(invalid("no sample channels"));
+    }
+
+    let mut pos = 0x60;
+    let orders = chunk(&file, pos, num_orders, "order list")?;
+    pos += num_orders;
+    let instruments = chunk(&file, pos, num_instruments * 2, "instrument list")?;
+    pos += num_instruments * 2;
+    let pattern_ptrs = chunk(&file, pos, num_patterns * 2, "pattern list")?;
+    pos += num_patterns * 2;
+    if stereo && hdr[0x35] == 252 {
+        let pans = chunk(&file, pos, 32, "panning table")?;
+        for (&setting, map) in pans.iter().zip(channel_map) {
+            if let (Some(ch), true) = (map, setting & 0x20 != 0) {
+                panning[ch] = pan(setting & 0xf);
+            }
+        }
+    }
+
+    // 254 marks an order to skip, 255 the end of the song
+    let orders = match orders.iter().position(|&o| o == 255) {
+        Some(end) => &orders[..end],
+        None => orders,
+    };
+    let positions: Vec<u8> = orders.iter().copied().filter(|&o| o != 254).collect();
+    if positions.is_empty() {
+        return Err(invalid("empty order list"));
+    }
+    if positions.len() > 0x100 {
+        return Err(invalid("song longer than 256 orders"));
+    }
+    if let Some(&o) = positions.iter().find(|&&o| o as usize >= num_patterns) {
+        return Err(invalid(&format!(
+            "order list refers to missing pattern {o}"
+        )));
+    }
+    // where position jumps land, by the order in the file
+    let mut jump_targets = vec![];
+    let mut next = 0;
+    for &o in orders {
+        jump_targets.push((next % positions.len()) as u8);
+        if o != 254 {
+            next += 1;
+        }
+    }
+
+    let mut samples = vec![Sample {
+        name: "".into(),
+        data: SampleData::Bits8(vec![]),
+        finetune: 0,
+        volume: 0,
+        repeat: None,
+        c2spd: None,
+        tuning: 0,
+        panning: None,
+    }];
+    for i in 1..=num_instruments {
+        let index = i as u8;
+        let offset = u16_at(instruments, (i - 1) * 2) as usize * 16;
+        let hdr = chunk(&file, offset, 0x50, "instrument header")?;
+        let mut sample = Sample {
+            name: name(&hdr[0x30..0x4c]),
+            data: SampleData::Bits8(vec![]),
+            finetune: 0,
+            volume: hdr[0x1c].min(0x40),
+            repeat: None,
+            // kept low enough for the period maths of the player
+            c2spd: Some(u32_at(hdr, 0x20).min(0xffff)),
+            tuning: 0,
+            panning: None,
+        };
+        match hdr[0] {
+            1 => {
+                sample.data = sample_data(&file, hdr, index, signed, &mut warnings)?;
+                let (start, end) = (u32_at(hdr, 0x14) as usize, u32_at(hdr, 0x18) as usize);
+                let end = end.min(sample.data.len());
+                if hdr[0x1f] & 1 != 0 && start < end {
+                    sample.repeat = Some((start, end - start));
+                }
+            }
+            2.. => warnings.push(Warning::AdlibInstrument(index)),
+            0 => {}
+        }
+        samples.push(sample);
+    }
+
+    let mut counts = EffectCounts::default();
+    let mut slide_memory = [0; MAX_CHANNELS];
+    let mut patterns = vec![];
+    for i in 0..num_patterns {
+        let mut pattern = vec![vec![Note::EMPTY; panning.len()]; 0x40];
+        let offset = u16_at(pattern_ptrs, i * 2) as usize * 16;
+        // a pattern at 0 is empty
+        if offset != 0 {
+            let len = u16_at(chunk(&file, offset, 2, "pattern")?, 0) as usize;
+            let data = chunk(&file, offset + 2, len.saturating_sub(2), "pattern")?;
+            let mut bytes = data.iter().copied();
+            let mut next = || bytes.next().ok_or_else(|| invalid("truncated pattern"));
+            for row in &mut pattern {
+                loop {
+                    let what = next()?;
+                    if what == 0 {
+                        break;
+                    }
+                    let ch = (what & 0x1f) as usize;
+                    let mut note = Note::EMPTY;
+                    if what & 0x20 != 0 {
+                        let (key, instrument) = (next()?, next()?);
+                        match key {
+                            // note off
+                            254 => note.volume = Some(0),
+                            _ if key & 0xf < 12 => {
+                                let period = (key >> 4) * 12 + (key & 0xf);
+                                note.period = Some(period.min(LAST_NOTE));
+                            }
+                            _ => {}
+                        }
+                        if instrument as usize > num_instruments {
+                            return Err(invalid(&format!("missing instrument {instrument}")));
+                        }
+                        note.sample = (instrument != 0).then_some(instrument);
+                    }
+                    if what & 0x40 != 0 {
+                        note.volume = Some(next()?.min(0x40));
+                    }
+                    if what & 0x80 != 0 {
+                        let (command, arg) = (next()?, next()?);
+                        if command != 0 {
+                            convert_effect(
+                                &mut note,
+                                command,
+                                arg,
+                                &mut slide_memory[ch],
+                                &jump_targets,
+                                &mut counts,
+                            );
+                        }
+                    }
+                    if let Some(ch) = channel_map[ch] {
+                        row[ch] = note;
+                    }
+                }
+            }
+        }
+        patterns.push(pattern);
+    }
+    warnings.extend(counts.warnings());
+
+    Ok((
+        Mod {
+            name: name(&hdr[..0x1c]),
+            samples,
+            patterns,
+            positions,
+            pos_restart: 0,
+            panning,
+            speed,
+            global_volume,
+            linear_periods: false,
+        },
+        warnings,
+    ))
+}
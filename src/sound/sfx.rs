@@ -0,0 +1,86 @@
+use std::io::{self, Read};
+
+use super::{wav, Mod, Sample, SampleData};
+
+/// A bank of sound effect samples, owned by the player independently of the
+/// music module.  Effect IDs are indices into the bank; ID 0 is always an
+/// empty sample, like sample 0 of a module.
+#[derive(Clone, Debug)]
+pub struct SfxBank {
+    pub samples: Vec<Sample>,
+}
+
+impl SfxBank {
+    pub fn new() -> Self {
+        SfxBank {
+            samples: vec![Sample {
+                name: "".into(),
+                data: SampleData::Bits8(vec![]),
+                finetune: 0,
+                volume: 0,
+                repeat: None,
+                c2spd: None,
+                tuning: 0,
+                panning: None,
+            }],
+        }
+    }
+
+    /// Makes a bank out of the samples of a module, keeping their numbering.
+    pub fn from_mod(module: &Mod) -> Self {
+        SfxBank {
+            samples: module.samples.clone(),
+        }
+    }
+
+    /// Adds a sample to the bank, returning its effect ID, or an error if the
+    /// bank is full.
+    pub fn add(&mut self, sample: Sample) -> io::Result<u8> {
+        self.check_room(1)?;
+        self.samples.push(sample);
+        Ok((self.samples.len() - 1) as u8)
+    }
+
+    /// Adds all samples of a module to the bank, returning the ID of the first
+    /// one.  Effect IDs of the module samples are consecutive.  If they do not
+    /// all fit, none are added.
+    pub fn add_mod(&mut self, module: &Mod) -> io::Result<u8> {
+        self.check_room(module.samples.len() - 1)?;
+        let first = self.samples.len() as u8;
+        self.samples.extend_from_slice(&module.samples[1..]);
+        Ok(first)
+    }
+
+    fn check_room(&self, count: usize) -> io::Result<()> {
+        if self.samples.len() + count > 0x100 {
+            return Err(io::Error::other("sfx bank full"));
+        }
+        Ok(())
+    }
+
+    pub fn load_wav(&mut self, f: &mut impl Read, name: &str) -> io::Result<u8> {
+        self.add(wav::load(f, name)?)
+    }
+
+    /// Loads raw signed 8-bit sample data.
+    pub fn load_raw(&mut self, f: &mut impl Read, name: &str) -> io::Result<u8> {
+        let mut data = vec![];
+        f.read_to_end(&mut data)?;
+        self.add(Sample {
+            name: name.into(),
+            data: SampleData::Bits8(data),
+            finetune: 0,
+            volume: 0x40,
+            repeat: None,
+            c2spd: None,
+            tuning: 0,
+            panning: None,
+        })
+    }
+}
+
+impl Default for SfxBank {
+    fn default() -> Self {
+        Self::new()
+    }
+}
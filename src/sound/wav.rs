@@ -0,0 +1,320 @@
+use std::io::{self, Read, Write};
+
+use arrayref::array_ref;
+
+use super::{Sample, SampleData};
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// The rate a MOD sample plays at as C-2 without finetune, on a PAL Amiga.
+pub const AMIGA_C2_RATE: u32 = 8287;
+
+/// The longest sample a MOD file can hold, in bytes.
+pub const MAX_SAMPLE_LEN: usize = 0xffff * 2;
+
+/// What is read from a WAV file.
+struct Wav {
+    rate: u32,
+    channels: usize,
+    /// Bytes per value, 1 or 2.
+    width: usize,
+    /// Interleaved frames, scaled to 16 bits.
+    data: Vec<i16>,
+    /// The first loop of the `smpl` chunk, as the first and the last frame.
+    repeat: Option<(u32, u32)>,
+}
+
+fn u16_at(buf: &[u8], pos: usize) -> u16 {
+    u16::from_le_bytes(*array_ref![buf, pos, 2])
+}
+
+fn u32_at(buf: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes(*array_ref![buf, pos, 4])
+}
+
+fn read(f: &mut impl Read) -> io::Result<Wav> {
+    let mut buf = [0; 12];
+    f.read_exact(&mut buf)?;
+    if &buf[0..4] != b"RIFF" || &buf[8..12] != b"WAVE" {
+        return Err(invalid("not a RIFF WAVE file"));
+    }
+    let mut format = None;
+    let mut data = None;
+    let mut repeat = None;
+    loop {
+        let mut hdr = [0; 8];
+        match f.read_exact(&mut hdr) {
+            // the chunks after the data are optional
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof && data.is_some() => break,
+            res => res?,
+        }
+        let len = u32_at(&hdr, 4) as usize;
+        let mut chunk = vec![];
+        f.by_ref().take(len as u64).read_to_end(&mut chunk)?;
+        if chunk.len() < len {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        if len & 1 != 0 {
+            // some writers leave out the pad byte of the last chunk
+            f.read_exact(&mut [0]).or_else(|e| match e.kind() {
+                io::ErrorKind::UnexpectedEof => Ok(()),
+                _ => Err(e),
+            })?;
+        }
+        match &hdr[0..4] {
+            b"fmt " => {
+                if len < 16 {
+                    return Err(invalid("truncated fmt chunk"));
+                }
+                let tag = u16_at(&chunk, 0);
+                let channels = u16_at(&chunk, 2);
+                let bits = u16_at(&chunk, 14);
+                if tag != 1 {
+                    return Err(invalid("only PCM WAV files are supported"));
+                }
+                if channels != 1 && channels != 2 {
+                    return Err(invalid("only mono and stereo WAV files are supported"));
+                }
+                if bits != 8 && bits != 16 {
+                    return Err(invalid("only 8-bit and 16-bit WAV files are supported"));
+                }
+                let rate = u32_at(&chunk, 4);
+                if rate == 0 {
+                    return Err(invalid("WAV file with a rate of 0"));
+                }
+                format = Some((rate, channels as usize, bits as usize / 8));
+            }
+            b"data" => {
+                let Some((_, _, width)) = format else {
+                    return Err(invalid("data chunk before fmt chunk"));
+                };
+                data = Some(
+                    chunk
+                        .chunks_exact(width)
+                        .map(|s| match *s {
+                            [x] => ((x ^ 0x80) as i16) << 8,
+                            [lo, hi] => i16::from_le_bytes([lo, hi]),
+                            _ => unreachable!(),
+                        })
+                        .collect(),
+                );
+            }
+            b"smpl" if len >= 36 + 24 && u32_at(&chunk, 28) != 0 => {
+                repeat = Some((u32_at(&chunk, 36 + 8), u32_at(&chunk, 36 + 12)));
+            }
+            _ => {}
+        }
+    }
+    let (rate, channels, width) = format.ok_or_else(|| invalid("no fmt chunk"))?;
+    let mut data: Vec<i16> = data.ok_or_else(|| invalid("no data chunk"))?;
+    data.truncate(data.len() / channels * channels);
+    Ok(Wav {
+        rate,
+        channels,
+        width,
+        data,
+        repeat,
+    })
+}
+
+impl Wav {
+    /// The frames mixed down to mono.
+    fn mono(&self) -> impl Iterator<Item = i32> + '_ {
+        self.data
+            .chunks_exact(self.channels)
+            .map(|frame| frame.iter().map(|&s| s as i32).sum::<i32>() / self.channels as i32)
+    }
+}
+
+/// Loads a PCM WAV file as a sample, keeping its depth and channels.  The
+/// sample plays at the rate of the file as C-4 (C-2 in MOD), with the loop of
+/// its `smpl` chunk if it has one.
+pub fn load(f: &mut impl Read, name: &str) -> io::Result<Sample> {
+    let wav = read(f)?;
+    let byte = |s: i16| (s >> 8) as u8;
+    let data = match (wav.width, wav.channels) {
+        (1, 1) => SampleData::Bits8(wav.data.iter().map(|&s| byte(s)).collect()),
+        (1, _) => SampleData::Stereo8(
+            wav.data
+                .chunks_exact(2)
+                .map(|f| [byte(f[0]), byte(f[1])])
+                .collect(),
+        ),
+        (_, 1) => SampleData::Bits16(wav.data),
+        _ => SampleData::Stereo16(wav.data.chunks_exact(2).map(|f| [f[0], f[1]]).collect()),
+    };
+    // the loop ends on its last frame, not after it
+    let len = data.len();
+    let repeat = wav.repeat.and_then(|(first, last)| {
+        let start = first as usize;
+        let end = (last as usize).saturating_add(1).min(len);
+        (start < end).then_some((start, end - start))
+    });
+    Ok(Sample {
+        name: name.into(),
+        data,
+        finetune: 0,
+        volume: 0x40,
+        repeat,
+        // as high as an S3M goes
+        c2spd: Some(wav.rate.min(0xffff)),
+        tuning: 0,
+        panning: None,
+    })
+}
+
+/// Loads a PCM WAV file as a sample to put in a MOD, resampled to play at
+/// `rate` as C-2, with the loop of its `smpl` chunk if it has one.  Fails if
+/// the result does not fit in a MOD.
+pub fn import(f: &mut impl Read, name: &str, rate: u32) -> io::Result<Sample> {
+    check_rate(rate)?;
+    let wav = read(f)?;
+    let mono: Vec<i32> = wav.mono().collect();
+    let step = wav.rate as f64 / rate as f64;
+    let len = (mono.len() as f64 / step) as usize;
+    // MOD samples are a whole number of words long
+    let padded = len + (len & 1);
+    if padded > MAX_SAMPLE_LEN {
+        return Err(invalid(&format!(
+            "sample is {padded} bytes long at {rate} Hz, MOD samples hold at most {MAX_SAMPLE_LEN}"
+        )));
+    }
+    let mut data: Vec<u8> = (0..len)
+        .map(|i| {
+            let pos = i as f64 * step;
+            let at = pos as usize;
+            let frac = pos - at as f64;
+            let a = mono[at] as f64;
+            let b = mono.get(at + 1).map_or(a, |&b| b as f64);
+            let v = (a + (b - a) * frac) as i32;
+            (v >> 8) as i8 as u8
+        })
+        .collect();
+    data.resize(padded, 0);
+    let repeat = wav.repeat.and_then(|(first, last)| {
+        let start = (first as f64 / step) as usize & !1;
+        let end = ((last as f64 + 1.) / step).round() as usize;
+        let end = (end + (end & 1)).min(padded);
+        (end >= start + 2).then_some((start, end - start))
+    });
+    Ok(Sample {
+        name: name.into(),
+        data: SampleData::Bits8(data),
+        finetune: 0,
+        volume: 0x40,
+        repeat,
+        c2spd: None,
+        tuning: 0,
+        panning: None,
+    })
+}
+
+fn check_rate(rate: u32) -> io::Result<()> {
+    if rate == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "a sample rate of 0",
+        ));
+    }
+    Ok(())
+}
+
+/// Writes a sample as a WAV file playing at `rate`, 8 or 16-bit and mono or
+/// stereo like the sample, with its loop in a `smpl` chunk.
+pub fn export(f: &mut impl Write, sample: &Sample, rate: u32) -> io::Result<()> {
+    check_rate(rate)?;
+    let (data, width): (Vec<u8>, u16) = match &sample.data {
+        SampleData::Bits8(data) => (data.iter().map(|&x| x ^ 0x80).collect(), 1),
+        SampleData::Bits16(data) => (data.iter().flat_map(|x| x.to_le_bytes()).collect(), 2),
+        SampleData::Stereo8(data) => (data.iter().flatten().map(|&x| x ^ 0x80).collect(), 1),
+        SampleData::Stereo16(data) => (
+            data.iter()
+                .flatten()
+                .flat_map(|x| x.to_le_bytes())
+                .collect(),
+            2,
+        ),
+    };
+    let channels: u16 = if sample.data.is_stereo() { 2 } else { 1 };
+    // an empty loop has no last frame to write down
+    let repeat = sample.repeat.filter(|&(_, len)| len != 0);
+    let align = width * channels;
+    let byte_rate = rate
+        .checked_mul(align as u32)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "sample rate too high"))?;
+    let len = data.len() as u32;
+    let pad = len & 1;
+    let smpl_len = if repeat.is_some() { 36 + 24 } else { 0 };
+    f.write_all(b"RIFF")?;
+    let riff_len = 4 + 8 + 16 + 8 + len + pad + if smpl_len != 0 { 8 + smpl_len } else { 0 };
+    f.write_all(&riff_len.to_le_bytes())?;
+    f.write_all(b"WAVEfmt ")?;
+    f.write_all(&16u32.to_le_bytes())?;
+    f.write_all(&1u16.to_le_bytes())?;
+    f.write_all(&channels.to_le_bytes())?;
+    f.write_all(&rate.to_le_bytes())?;
+    f.write_all(&byte_rate.to_le_bytes())?;
+    f.write_all(&align.to_le_bytes())?;
+    f.write_all(&(width * 8).to_le_bytes())?;
+    f.write_all(b"data")?;
+    f.write_all(&len.to_le_bytes())?;
+    f.write_all(&data)?;
+    if pad != 0 {
+        f.write_all(&[0])?;
+    }
+    if let Some((start, len)) = repeat {
+        f.write_all(b"smpl")?;
+        f.write_all(&smpl_len.to_le_bytes())?;
+        let fields = [
+            // manufacturer, product
+            0,
+            0,
+            // sample period in nanoseconds
+            1_000_000_000 / rate,
+            // MIDI note played at the sample's own rate: C-4, C-2 in MOD
+            60,
+            // pitch fraction, SMPTE format and offset
+            0,
+            0,
+            0,
+            // one loop, no extra data
+            1,
+            0,
+            // forward loop over frames start..=start+len-1, forever
+            0,
+            0,
+            start as u32,
+            (start + len) as u32 - 1,
+            0,
+            0,
+        ];
+        for field in fields {
+            f.write_all(&u32::to_le_bytes(field))?;
+        }
+    }
+    Ok(())
+}
+
+/// Writes 16-bit PCM frames, interleaved if there is more than one channel,
+/// as a WAV file.
+pub fn write(f: &mut impl Write, sample_rate: u32, channels: u16, data: &[i16]) -> io::Result<()> {
+    let len = data.len() as u32 * 2;
+    f.write_all(b"RIFF")?;
+    f.write_all(&(36 + len).to_le_bytes())?;
+    f.write_all(b"WAVEfmt ")?;
+    f.write_all(&16u32.to_le_bytes())?;
+    f.write_all(&1u16.to_le_bytes())?;
+    f.write_all(&channels.to_le_bytes())?;
+    f.write_all(&sample_rate.to_le_bytes())?;
+    f.write_all(&(sample_rate * channels as u32 * 2).to_le_bytes())?;
+    f.write_all(&(channels * 2).to_le_bytes())?;
+    f.write_all(&16u16.to_le_bytes())?;
+    f.write_all(b"data")?;
+    f.write_all(&len.to_le_bytes())?;
+    for v in data {
+        f.write_all(&v.to_le_bytes())?;
+    }
+    Ok(())
+}
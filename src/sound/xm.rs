@@ -0,0 +1,586 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    io::{self, Read, Seek},
+    num::NonZeroU8,
+};
+
+use arrayref::array_ref;
+
+use super::{
+    MiscEffect, Mod, Note, Sample, SampleData, ToneEffect, VolumeEffect, LAST_NOTE, MAX_CHANNELS,
+    PAN_RIGHT,
+};
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn u16_at(buf: &[u8], pos: usize) -> u16 {
+    u16::from_le_bytes(*array_ref![buf, pos, 2])
+}
+
+fn u32_at(buf: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes(*array_ref![buf, pos, 4])
+}
+
+/// `len` bytes of the file from `pos`, or an error if the file ends first.
+fn chunk<'a>(file: &'a [u8], pos: usize, len: usize, what: &str) -> io::Result<&'a [u8]> {
+    file.get(pos..pos + len)
+        .ok_or_else(|| invalid(&format!("truncated {what}")))
+}
+
+fn name(buf: &[u8]) -> String {
+    String::from_utf8_lossy(buf)
+        .trim_end_matches(['\0', ' '])
+        .to_string()
+}
+
+/// The tag an XM file starts with.
+pub const TAG: &[u8] = b"Extended Module: ";
+
+/// The only tempo the player runs at, 50 ticks a second.
+const TEMPO: u16 = 125;
+
+/// The XM note that releases the key.
+const KEY_OFF: u8 = 97;
+
+/// Something in an XM file that does not play the way it would in
+/// FastTracker 2.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Warning {
+    /// The song is not at the tempo the player runs at.
+    Tempo(u16),
+    /// An effect that is left out, and how many times it is used.  Effects of
+    /// the volume column start with "volume".
+    UnsupportedEffect { effect: String, count: usize },
+    /// An effect that only plays roughly right, and how many times it is
+    /// used.
+    ApproximatedEffect { effect: String, count: usize },
+    /// An instrument with envelopes, which are left out.
+    Envelope(u8),
+    /// An instrument with auto-vibrato, which is left out.
+    AutoVibrato(u8),
+    /// An instrument with more than one sample.  Notes play the sample the
+    /// instrument maps them to only when they come with the instrument.
+    MultiSample(u8),
+    /// A sample with a ping-pong loop, played as a forward loop.
+    PingPongLoop(u8),
+    /// A sample that runs past the end of the file, cut short there.
+    TruncatedSample(u8),
+}
+
+impl Display for Warning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Warning::Tempo(tempo) => {
+                write!(f, "tempo {tempo} is not supported, playing at {TEMPO}")
+            }
+            Warning::UnsupportedEffect { effect, count } => {
+                write!(
+                    f,
+                    "effect {effect} is not supported, left out {count} times"
+                )
+            }
+            Warning::ApproximatedEffect { effect, count } => {
+                write!(f, "effect {effect} is approximated, {count} times")
+            }
+            Warning::Envelope(i) => {
+                write!(f, "instrument {i:02x} has envelopes, which are left out")
+            }
+            Warning::AutoVibrato(i) => {
+                write!(f, "instrument {i:02x} has auto-vibrato, which is left out")
+            }
+            Warning::MultiSample(i) => write!(
+                f,
+                "instrument {i:02x} has several samples, picked only where the instrument is given"
+            ),
+            Warning::PingPongLoop(i) => {
+                write!(f, "sample {i:02x} has a ping-pong loop, played forward")
+            }
+            Warning::TruncatedSample(i) => {
+                write!(f, "sample {i:02x} is truncated, cut short")
+            }
+        }
+    }
+}
+
+/// How often effects are left out or approximated, by name.
+#[derive(Default)]
+struct EffectCounts {
+    unsupported: BTreeMap<String, usize>,
+    approximated: BTreeMap<String, usize>,
+}
+
+impl EffectCounts {
+    fn unsupported(&mut self, effect: String) {
+        *self.unsupported.entry(effect).or_default() += 1;
+    }
+
+    fn approximated(&mut self, effect: String) {
+        *self.approximated.entry(effect).or_default() += 1;
+    }
+
+    fn warnings(self) -> impl Iterator<Item = Warning> {
+        let approximated = self
+            .approximated
+            .into_iter()
+            .map(|(effect, count)| Warning::ApproximatedEffect { effect, count });
+        self.unsupported
+            .into_iter()
+            .map(|(effect, count)| Warning::UnsupportedEffect { effect, count })
+            .chain(approximated)
+    }
+}
+
+/// FastTracker 2's panning, 0 to 0xff, as [`Mod::panning`].
+fn pan(pan: u8) -> u8 {
+    (pan as u16 * PAN_RIGHT as u16 / 0xff) as u8
+}
+
+/// An Axy volume slide: up by x, or else down by y.
+fn volume_slide(arg: u8) -> VolumeEffect {
+    match (arg >> 4, arg & 0xf) {
+        (0, down) => VolumeEffect::VolumeSlide(-(down as i8)),
+        (up, _) => VolumeEffect::VolumeSlide(up as i8),
+    }
+}
+
+/// Converts an XM effect.  `positions` is the length of the song, for the
+/// position jumps.
+fn convert_effect(
+    note: &mut Note,
+    effect: u8,
+    arg: u8,
+    slide_memory: &mut u8,
+    positions: usize,
+    counts: &mut EffectCounts,
+) {
+    let (hi, lo) = (arg >> 4, arg & 0xf);
+    let name = match effect {
+        0..=0xf => format!("{effect:X}"),
+        0x10..=0x23 => ((b'G' + effect - 0x10) as char).to_string(),
+        _ => format!("{effect:02x}"),
+    };
+    match effect {
+        0 if arg != 0 => note.tone_effect = ToneEffect::Arpeggio(hi, lo),
+        0 => {}
+        1 | 2 => {
+            note.tone_effect = ToneEffect::Portamento {
+                target: Some(if effect == 1 { LAST_NOTE } else { 0 }),
+                speed: NonZeroU8::new(arg),
+            }
+        }
+        3 => {
+            note.tone_effect = ToneEffect::Portamento {
+                target: note.period,
+                speed: NonZeroU8::new(arg),
+            };
+            if note.sample.is_some() {
+                note.volume_effect = VolumeEffect::Reset;
+            }
+            note.period = None;
+            note.sample = None;
+        }
+        4 => {
+            note.tone_effect = ToneEffect::Vibrato {
+                rate: NonZeroU8::new(hi),
+                depth: NonZeroU8::new(lo),
+            }
+        }
+        5 | 6 | 0xa => {
+            // the three of them share the last slide
+            if arg != 0 {
+                *slide_memory = arg;
+            }
+            note.volume_effect = volume_slide(*slide_memory);
+            match effect {
+                5 => {
+                    note.tone_effect = ToneEffect::Portamento {
+                        target: note.period,
+                        speed: None,
+                    };
+                    note.period = None;
+                    note.sample = None;
+                }
+                6 => {
+                    note.tone_effect = ToneEffect::Vibrato {
+                        rate: None,
+                        depth: None,
+                    }
+                }
+                _ => {}
+            }
+        }
+        8 => note.misc_effect = MiscEffect::SetPanning(pan(arg)),
+        9 => note.misc_effect = MiscEffect::SetSampleOffset(arg),
+        0xb => {
+            let position = if (arg as usize) < positions { arg } else { 0 };
+            note.misc_effect = MiscEffect::PositionJump(position);
+        }
+        0xc => note.volume_effect = VolumeEffect::SetVolume(arg.min(0x40)),
+        0xd => note.misc_effect = MiscEffect::PatternBreak(arg),
+        0xe => match hi {
+            1 => note.tone_effect = ToneEffect::FinePortamento(-(lo as i8)),
+            2 => note.tone_effect = ToneEffect::FinePortamento(lo as i8),
+            9 if lo != 0 => note.misc_effect = MiscEffect::RetrigNote(lo),
+            9 => {}
+            0xa => note.volume_effect = VolumeEffect::FineVolumeSlide(lo as i8),
+            0xb => note.volume_effect = VolumeEffect::FineVolumeSlide(-(lo as i8)),
+            _ => counts.unsupported(format!("E{hi:X}")),
+        },
+        0xf if arg == 0 => {}
+        0xf if arg < 0x20 => note.misc_effect = MiscEffect::SetSpeed(arg),
+        0xf => counts.unsupported("F (tempo)".into()),
+        // G
+        0x10 => note.misc_effect = MiscEffect::SetGlobalVolume(arg.min(0x40)),
+        // K, the key off without envelopes
+        0x14 => {
+            if arg != 0 {
+                // on a later tick
+                counts.approximated(name);
+            }
+            note.volume = Some(0);
+        }
+        // R
+        0x1b if lo != 0 => {
+            if hi != 0 && hi != 8 {
+                // the volume change on every retrig
+                counts.approximated(name);
+            }
+            note.misc_effect = MiscEffect::RetrigNote(lo);
+        }
+        0x1b => {}
+        // X, extra fine portamento
+        0x21 if hi == 1 || hi == 2 => {
+            // a quarter of the fine one
+            counts.approximated(format!("X{hi}"));
+            let delta = (lo as i8 + 2) / 4;
+            note.tone_effect = ToneEffect::FinePortamento(if hi == 1 { -delta } else { delta });
+        }
+        _ => counts.unsupported(name),
+    }
+}
+
+/// Converts the volume column, into the effects the effect column leaves
+/// free.
+fn convert_volume(note: &mut Note, volume: u8, counts: &mut EffectCounts) {
+    let (hi, lo) = (volume >> 4, volume & 0xf);
+    let free = (
+        matches!(note.tone_effect, ToneEffect::None),
+        matches!(note.volume_effect, VolumeEffect::None),
+        matches!(note.misc_effect, MiscEffect::None),
+    );
+    let symbol = match hi {
+        6 => '-',
+        7 => '+',
+        8 => 'D',
+        9 => 'U',
+        0xa => 'S',
+        0xb => 'V',
+        0xc => 'P',
+        0xd => 'L',
+        0xe => 'R',
+        _ => 'M',
+    };
+    match (hi, free) {
+        (0 | 0xf, _) if volume < 0x10 => {}
+        (1..=5, _) => note.volume = Some((volume - 0x10).min(0x40)),
+        (6, (_, true, _)) => note.volume_effect = VolumeEffect::VolumeSlide(-(lo as i8)),
+        (7, (_, true, _)) => note.volume_effect = VolumeEffect::VolumeSlide(lo as i8),
+        (8, (_, true, _)) => note.volume_effect = VolumeEffect::FineVolumeSlide(-(lo as i8)),
+        (9, (_, true, _)) => note.volume_effect = VolumeEffect::FineVolumeSlide(lo as i8),
+        (0xa, (true, _, _)) => {
+            note.tone_effect = ToneEffect::Vibrato {
+                rate: NonZeroU8::new(lo),
+                depth: None,
+            }
+        }
+        (0xb, (true, _, _)) => {
+            note.tone_effect = ToneEffect::Vibrato {
+                rate: None,
+                depth: NonZeroU8::new(lo),
+            }
+        }
+        (0xc, (_, _, true)) => note.misc_effect = MiscEffect::SetPanning(pan(lo * 0x11)),
+        (0xf, (true, _, _)) => {
+            note.tone_effect = ToneEffect::Portamento {
+                target: note.period,
+                speed: NonZeroU8::new(lo << 4),
+            };
+            if note.sample.is_some() && free.1 {
+                note.volume_effect = VolumeEffect::Reset;
+            }
+            note.period = None;
+            note.sample = None;
+        }
+        _ => counts.unsupported(format!("volume {symbol}")),
+    }
+}
+
+/// Decodes the delta-coded data of a sample, `bytes` long.
+fn sample_data(
+    file: &[u8],
+    offset: usize,
+    bytes: usize,
+    sixteen_bit: bool,
+    index: u8,
+    warnings: &mut Vec<Warning>,
+) -> SampleData {
+    let mut raw = file.get(offset..).unwrap_or(&[]);
+    if raw.len() < bytes {
+        warnings.push(Warning::TruncatedSample(index));
+    } else {
+        raw = &raw[..bytes];
+    }
+    if sixteen_bit {
+        let mut acc = 0i16;
+        let data = raw.chunks_exact(2).map(|b| {
+            acc = acc.wrapping_add(u16_at(b, 0) as i16);
+            acc
+        });
+        SampleData::Bits16(data.collect())
+    } else {
+        let mut acc = 0u8;
+        let data = raw.iter().map(|&b| {
+            acc = acc.wrapping_add(b);
+            acc
+        });
+        SampleData::Bits8(data.collect())
+    }
+}
+
+/// Where the samples of an instrument went in [`Mod::samples`].
+struct Instrument {
+    /// Index of the sample for each note, 0 if none.
+    keymap: [u8; 96],
+}
+
+/// Loads an XM file.  Instrument samples all become samples of the module.
+/// What does not convert is left out or approximated, and listed in the
+/// warnings.
+pub fn load(f: &mut (impl Read + Seek)) -> io::Result<(Mod, Vec<Warning>)> {
+    let mut file = vec![];
+    f.read_to_end(&mut file)?;
+    let hdr = chunk(&file, 0, 80, "XM header")?;
+    if &hdr[..TAG.len()] != TAG {
+        return Err(invalid("not an XM file"));
+    }
+    let version = u16_at(hdr, 58);
+    if version < 0x0104 {
+        return Err(invalid(&format!(
+            "XM version {version:04x} is not supported"
+        )));
+    }
+    let mut warnings = vec![];
+    let header_len = u32_at(hdr, 60) as usize;
+    let song_len = u16_at(hdr, 64) as usize;
+    let restart = u16_at(hdr, 66);
+    let channels = u16_at(hdr, 68) as usize;
+    let num_patterns = u16_at(hdr, 70) as usize;
+    let num_instruments = u16_at(hdr, 72) as usize;
+    let linear_periods = u16_at(hdr, 74) & 1 != 0;
+    let speed = match u16_at(hdr, 76) {
+        0 => 6,
+        speed => speed.min(0x1f) as u8,
+    };
+    let tempo = u16_at(hdr, 78);
+    if tempo != TEMPO {
+        warnings.push(Warning::Tempo(tempo));
+    }
+    if !(1..=MAX_CHANNELS).contains(&channels) {
+        return Err(invalid(&format!("{channels} channels is not supported")));
+    }
+    if !(1..=0x100).contains(&song_len) || num_patterns > 0x100 {
+        return Err(invalid("bad song length or pattern count"));
+    }
+    let positions = chunk(&file, 80, song_len, "order list")?.to_vec();
+    if let Some(&o) = positions.iter().find(|&&o| o as usize >= num_patterns) {
+        return Err(invalid(&format!(
+            "order list refers to missing pattern {o}"
+        )));
+    }
+
+    let mut pos = 60 + header_len;
+    let mut patterns = vec![];
+    let mut cells = vec![];
+    for _ in 0..num_patterns {
+        let hdr = chunk(&file, pos, 9, "pattern header")?;
+        let rows = u16_at(hdr, 5) as usize;
+        let packed_len = u16_at(hdr, 7) as usize;
+        pos += u32_at(hdr, 0) as usize;
+        if !(1..=0x100).contains(&rows) {
+            return Err(invalid(&format!("pattern with {rows} rows")));
+        }
+        let data = chunk(&file, pos, packed_len, "pattern")?;
+        pos += packed_len;
+        // each cell as the raw note, instrument, volume, effect and argument
+        let mut pattern = vec![vec![[0u8; 5]; channels]; rows];
+        if packed_len != 0 {
+            let mut bytes = data.iter().copied();
+            let mut next = || bytes.next().ok_or_else(|| invalid("truncated pattern"));
+            for row in &mut pattern {
+                for cell in row {
+                    let first = next()?;
+                    if first & 0x80 == 0 {
+                        *cell = [first, next()?, next()?, next()?, next()?];
+                    } else {
+                        for (i, field) in cell.iter_mut().enumerate() {
+                            if first & 1 << i != 0 {
+                                *field = next()?;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        cells.push(pattern);
+    }
+
+    let mut samples = vec![Sample {
+        name: "".into(),
+        data: SampleData::Bits8(vec![]),
+        finetune: 0,
+        volume: 0,
+        repeat: None,
+        c2spd: None,
+        tuning: 0,
+        panning: None,
+    }];
+    let mut instruments = vec![];
+    for i in 1..=num_instruments {
+        let index = i as u8;
+        let hdr = chunk(&file, pos, 29, "instrument header")?;
+        let header_len = u32_at(hdr, 0) as usize;
+        let num_samples = u16_at(hdr, 27) as usize;
+        let mut instrument = Instrument { keymap: [0; 96] };
+        if num_samples == 0 {
+            pos += header_len;
+            instruments.push(instrument);
+            continue;
+        }
+        let hdr = chunk(&file, pos, 241, "instrument header")?;
+        let sample_header_len = u32_at(hdr, 29) as usize;
+        if hdr[233] & 1 != 0 || hdr[234] & 1 != 0 {
+            warnings.push(Warning::Envelope(index));
+        }
+        if hdr[237] != 0 {
+            warnings.push(Warning::AutoVibrato(index));
+        }
+        if num_samples > 1 {
+            warnings.push(Warning::MultiSample(index));
+        }
+        let first = samples.len();
+        if first + num_samples > 0x100 {
+            return Err(invalid("more than 255 samples"));
+        }
+        for (key, &sample) in instrument.keymap.iter_mut().zip(&hdr[33..129]) {
+            if (sample as usize) < num_samples {
+                *key = (first + sample as usize) as u8;
+            }
+        }
+        pos += header_len;
+        let mut headers = vec![];
+        for _ in 0..num_samples {
+            headers.push(chunk(&file, pos, 40, "sample header")?);
+            pos += sample_header_len;
+        }
+        for hdr in headers {
+            let index = samples.len() as u8;
+            let bytes = u32_at(hdr, 0) as usize;
+            let flags = hdr[14];
+            let width = if flags & 0x10 != 0 { 2 } else { 1 };
+            if hdr[17] == 0xad {
+                return Err(invalid(&format!("sample {index:02x} is ADPCM packed")));
+            }
+            let data = sample_data(&file, pos, bytes, width == 2, index, &mut warnings);
+            pos += bytes;
+            let (start, len) = (
+                u32_at(hdr, 4) as usize / width,
+                u32_at(hdr, 8) as usize / width,
+            );
+            let end = (start + len).min(data.len());
+            let repeat = match flags & 3 {
+                0 => None,
+                loop_type => {
+                    if loop_type == 2 {
+                        warnings.push(Warning::PingPongLoop(index));
+                    }
+                    (start < end).then_some((start, end - start))
+                }
+            };
+            let tuning = (hdr[16] as i8 as i16) * 128 + hdr[13] as i8 as i16;
+            samples.push(Sample {
+                name: name(&hdr[18..40]),
+                data,
+                finetune: 0,
+                volume: hdr[12].min(0x40),
+                repeat,
+                c2spd: Some((8363. * (tuning as f64 / (12. * 128.)).exp2()).round() as u32),
+                tuning,
+                panning: Some(pan(hdr[15])),
+            });
+        }
+        instruments.push(instrument);
+    }
+
+    let mut counts = EffectCounts::default();
+    let mut slide_memory = [0; MAX_CHANNELS];
+    // the last note of each channel, for instruments given without one
+    let mut last_note = [0; MAX_CHANNELS];
+    for pattern in cells {
+        let mut rows = vec![];
+        for cells in pattern {
+            let mut row = vec![];
+            for (ch, [key, instrument, volume, effect, arg]) in cells.into_iter().enumerate() {
+                let mut note = Note::EMPTY;
+                match key {
+                    1..=96 => {
+                        note.period = Some(key - 1);
+                        last_note[ch] = key - 1;
+                    }
+                    KEY_OFF => note.volume = Some(0),
+                    _ => {}
+                }
+                if instrument != 0 {
+                    let sample = instruments
+                        .get(instrument as usize - 1)
+                        .map_or(0, |i| i.keymap[last_note[ch] as usize]);
+                    note.sample = Some(sample);
+                }
+                convert_effect(
+                    &mut note,
+                    effect,
+                    arg,
+                    &mut slide_memory[ch],
+                    positions.len(),
+                    &mut counts,
+                );
+                convert_volume(&mut note, volume, &mut counts);
+                row.push(note);
+            }
+            rows.push(row);
+        }
+        patterns.push(rows);
+    }
+    warnings.extend(counts.warnings());
+
+    Ok((
+        Mod {
+            name: name(&hdr[17..37]),
+            samples,
+            patterns,
+            pos_restart: if (restart as usize) < positions.len() {
+                restart as u8
+            } else {
+                0
+            },
+            positions,
+            panning: vec![PAN_RIGHT / 2; channels],
+            speed,
+            global_volume: 0x40,
+            linear_periods,
+        },
+        warnings,
+    ))
+}
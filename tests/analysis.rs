@@ -0,0 +1,77 @@
+mod common;
+
+use common::{effect, note, pattern, TestMod};
+use pfr::sound::{
+    player::{play_offline, PlayerConfig},
+    sfx::SfxBank,
+    MiscEffect,
+};
+
+#[test]
+fn plain_song() {
+    let timing = TestMod::new(vec![pattern(&[]), pattern(&[])])
+        .load()
+        .timing();
+    assert_eq!(timing.position_starts, [Some(0), Some(384)]);
+    assert_eq!(timing.length, 768);
+    assert_eq!((timing.loop_position, timing.loop_row), (0, 0));
+    assert_eq!(timing.loop_tick, 0);
+}
+
+#[test]
+fn speed_and_flow() {
+    let module = TestMod::new(vec![
+        pattern(&[(0, 0, note(12, 1, 0xf02)), (3, 0, effect(0xd10))]),
+        pattern(&[(0x10, 0, effect(0xf03)), (0x11, 1, effect(0xb03))]),
+        pattern(&[]),
+        pattern(&[(1, 2, effect(0xb01)), (1, 3, effect(0xd00))]),
+    ]);
+    let timing = module.load().timing();
+    // 4 rows at speed 2, then 2 rows at speed 3
+    assert_eq!(timing.position_starts, [Some(0), Some(8), None, Some(14)]);
+    // 2 more rows, then 1.00 to 1.0f before it starts over at 1.10
+    assert_eq!(timing.length, 68);
+    assert_eq!((timing.loop_position, timing.loop_row), (1, 0x10));
+    assert_eq!(timing.loop_tick, 8);
+}
+
+#[test]
+fn jingles() {
+    let module = TestMod::new(vec![
+        pattern(&[(0, 0, effect(0xf02)), (7, 0, effect(0xb00))]),
+        pattern(&[(1, 0, effect(0xd00))]),
+    ]);
+    let module = module.load();
+    assert_eq!(module.jingle_length(0, 1), Some(16));
+    assert_eq!(module.jingle_length(0, 3), Some(48));
+    assert_eq!(module.jingle_length(0, 0), None);
+    // wraps around to position 0 first
+    assert_eq!(module.jingle_length(1, 1), Some(28));
+    let module = TestMod::new(vec![pattern(&[])]).load();
+    assert_eq!(module.jingle_length(0, 1), None);
+}
+
+#[test]
+fn player_follows_the_analysis() {
+    let mut module = TestMod::new(vec![pattern(&[]), pattern(&[])]).load();
+    // neither loads from a MOD any more, but a module made in code can have
+    // them
+    module.patterns[0][0][0].misc_effect = MiscEffect::SetSpeed(0);
+    module.patterns[0][3][0].misc_effect = MiscEffect::PositionJump(9);
+    let timing = module.timing();
+    assert_eq!(timing.length, 24);
+    assert_eq!((timing.loop_position, timing.loop_row), (0, 0));
+    let (_player, mut state) = play_offline(module, SfxBank::new(), PlayerConfig::default());
+    let mut ticks = 0;
+    let mut rows = vec![];
+    for _ in 0..5 {
+        ticks += 1;
+        while !state.step_tick() {
+            ticks += 1;
+        }
+        rows.push(state.status());
+    }
+    assert_eq!(rows, [(0, 0), (0, 1), (0, 2), (0, 3), (0, 0)]);
+    assert_eq!(ticks, timing.length + 1);
+    assert_eq!(state.speed(), 6);
+}
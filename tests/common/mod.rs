@@ -0,0 +1,217 @@
+//! Builds small MODs in code and checks their renders against the reference
+//! hashes in `tests/golden`.
+//!
+//! Every tick of a render is hashed separately, together with the song
+//! position and a snapshot of each channel, one line per tick.  When a render
+//! stops matching, the first diverging tick is reported and the whole render
+//! is written next to the reference as `<name>.actual`, so the two can be
+//! compared with `diff`.  Run with `PFR_BLESS=1` to accept new renders as the
+//! reference.
+
+// each test crate uses its own part of this
+#![allow(dead_code)]
+
+use std::{env, fmt::Write, fs, io::Cursor, path::PathBuf};
+
+use pfr::sound::{
+    loader,
+    player::{self, PlayerConfig, PlayerState},
+    sfx::SfxBank,
+    Mod, PERIODS,
+};
+
+pub type Pattern = [[u32; 4]; 0x40];
+
+/// A note cell as stored in a MOD file.  `period` is an index into the
+/// period table, from C-1 up.
+pub fn note(period: usize, sample: u8, effect: u16) -> u32 {
+    let period = PERIODS[0][period] as u32;
+    (sample as u32 & 0xf0) << 24 | period << 16 | (sample as u32 & 0xf) << 12 | effect as u32
+}
+
+/// A cell with just an effect.
+pub fn effect(effect: u16) -> u32 {
+    effect as u32
+}
+
+/// A pattern with the given cells set, as `(row, channel, cell)`.
+pub fn pattern(cells: &[(usize, usize, u32)]) -> Pattern {
+    let mut pattern = [[0; 4]; 0x40];
+    for &(row, channel, cell) in cells {
+        pattern[row][channel] = cell;
+    }
+    pattern
+}
+
+pub struct TestSample {
+    pub data: Vec<u8>,
+    pub finetune: u8,
+    pub volume: u8,
+    pub repeat: Option<(usize, usize)>,
+}
+
+impl TestSample {
+    /// A short looped saw wave.
+    pub fn saw() -> Self {
+        TestSample {
+            data: (0..32).map(|i| (i * 8 - 128) as u8).collect(),
+            finetune: 0,
+            volume: 0x40,
+            repeat: Some((0, 32)),
+        }
+    }
+
+    /// A decaying square wave that is not looped.
+    pub fn pluck() -> Self {
+        TestSample {
+            data: (0..2048)
+                .map(|i| {
+                    let level = 127 - i / 16;
+                    (if i & 16 == 0 { level } else { -level }) as u8
+                })
+                .collect(),
+            finetune: 0,
+            volume: 0x30,
+            repeat: None,
+        }
+    }
+}
+
+pub struct TestMod {
+    pub samples: Vec<TestSample>,
+    pub patterns: Vec<Pattern>,
+    pub positions: Vec<u8>,
+    pub restart: u8,
+}
+
+impl TestMod {
+    /// The saw and the pluck as samples 1 and 2, playing the given patterns
+    /// in order.
+    pub fn new(patterns: Vec<Pattern>) -> Self {
+        TestMod {
+            samples: vec![TestSample::saw(), TestSample::pluck()],
+            positions: (0..patterns.len() as u8).collect(),
+            patterns,
+            restart: 0,
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut data = vec![];
+        data.extend_from_slice(b"golden");
+        data.resize(20, 0);
+        for i in 0..31 {
+            data.extend_from_slice(&[0; 22]);
+            match self.samples.get(i) {
+                None => data.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 1]),
+                Some(sample) => {
+                    let (rep_pos, rep_len) = sample.repeat.unwrap_or((0, 2));
+                    data.extend_from_slice(&(sample.data.len() as u16 / 2).to_be_bytes());
+                    data.push(sample.finetune);
+                    data.push(sample.volume);
+                    data.extend_from_slice(&(rep_pos as u16 / 2).to_be_bytes());
+                    data.extend_from_slice(&(rep_len as u16 / 2).to_be_bytes());
+                }
+            }
+        }
+        data.push(self.positions.len() as u8);
+        data.push(self.restart);
+        let mut positions = self.positions.clone();
+        positions.resize(128, 0);
+        data.extend_from_slice(&positions);
+        data.extend_from_slice(b"M.K.");
+        for pattern in &self.patterns {
+            for row in pattern {
+                for cell in row {
+                    data.extend_from_slice(&cell.to_be_bytes());
+                }
+            }
+        }
+        for sample in &self.samples {
+            data.extend_from_slice(&sample.data);
+        }
+        data
+    }
+
+    pub fn load(&self) -> Mod {
+        loader::load(&mut Cursor::new(self.encode())).unwrap()
+    }
+}
+
+/// Renders `ticks` ticks of the module, one line per tick.
+pub fn render(module: Mod, ticks: usize) -> String {
+    let (_player, mut state) =
+        player::play_offline(module, SfxBank::new(), PlayerConfig::default());
+    let mut buf = vec![0; state.sample_rate() as usize / 50 * 2];
+    let mut out = String::new();
+    for tick in 0..ticks {
+        state.render(&mut buf);
+        let (position, row) = state.status();
+        write!(out, "{tick:04} {position:02x}.{row:02x} {:016x}", fnv(&buf)).unwrap();
+        for channel in state.channels() {
+            write!(out, " {:03x}:{:02x}", channel.period, channel.volume).unwrap();
+        }
+        out.push('\n');
+    }
+    out
+}
+
+fn fnv(data: &[i32]) -> u64 {
+    let mut hash = 0xcbf29ce484222325u64;
+    for v in data {
+        for b in v.to_le_bytes() {
+            hash ^= b as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+    hash
+}
+
+/// Renders the module and compares it with `tests/golden/<name>.txt`.
+pub fn check(name: &str, module: &TestMod, ticks: usize) {
+    let actual = render(module.load(), ticks);
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+    let path = dir.join(format!("{name}.txt"));
+    let actual_path = dir.join(format!("{name}.actual"));
+    if env::var_os("PFR_BLESS").is_some() {
+        fs::write(&path, &actual).unwrap();
+        let _ = fs::remove_file(&actual_path);
+        return;
+    }
+    let expected = fs::read_to_string(&path).unwrap_or_else(|e| {
+        panic!(
+            "{}: {e} (run with PFR_BLESS=1 to create it)",
+            path.display()
+        )
+    });
+    if expected == actual {
+        let _ = fs::remove_file(&actual_path);
+        return;
+    }
+    fs::write(&actual_path, &actual).unwrap();
+    let mut lines = expected.lines().zip(actual.lines());
+    let msg = match lines.position(|(e, a)| e != a) {
+        Some(tick) => format!(
+            "first divergence at tick {tick}:\n  expected {}\n  actual   {}",
+            expected.lines().nth(tick).unwrap(),
+            actual.lines().nth(tick).unwrap(),
+        ),
+        None => "render length differs".to_string(),
+    };
+    panic!(
+        "{name} does not match the reference, {msg}\nfull render written to {}",
+        actual_path.display()
+    );
+}
+
+/// Values in a tick of the output of `state`, both channels.
+pub fn tick_len(state: &PlayerState) -> usize {
+    state.sample_rate() as usize / 50 * 2
+}
+
+/// Renders `ticks` ticks of `state` as they are.
+pub fn render_ticks(state: &mut PlayerState, ticks: usize) -> Vec<i32> {
+    let mut buf = vec![0; tick_len(state) * ticks];
+    state.render(&mut buf);
+    buf
+}
@@ -0,0 +1,246 @@
+//! Renders a small MOD for each effect and edge case and compares it with the
+//! reference in `tests/golden`.  See `common/mod.rs` for blessing and
+//! diffing.
+
+mod common;
+
+use common::{check, effect, note, pattern, TestMod, TestSample};
+
+/// C-1, C-2 and C-3 in the period table.
+const C1: usize = 0;
+const C2: usize = 12;
+const C3: usize = 24;
+
+const SAW: u8 = 1;
+const PLUCK: u8 = 2;
+
+const TICKS: usize = 64;
+
+#[test]
+fn plain_notes() {
+    let module = TestMod::new(vec![pattern(&[
+        (0, 0, note(C2, SAW, 0)),
+        (0, 1, note(C2 + 4, PLUCK, 0)),
+        (0, 2, note(C2 + 7, SAW, 0)),
+        (0, 3, note(C3, PLUCK, 0)),
+        (4, 0, note(C1, SAW, 0)),
+        (4, 2, note(C3 + 11, SAW, 0)),
+        (6, 1, note(C2, 0, 0)),
+    ])]);
+    check("plain_notes", &module, TICKS);
+}
+
+#[test]
+fn sample_end() {
+    let module = TestMod::new(vec![pattern(&[
+        (0, 0, note(C3 + 11, PLUCK, 0)),
+        (0, 3, note(C1, PLUCK, 0)),
+    ])]);
+    check("sample_end", &module, TICKS);
+}
+
+#[test]
+fn finetune() {
+    let mut module = TestMod::new(vec![pattern(&[
+        (0, 0, note(C2, SAW, 0)),
+        (0, 2, note(C2, PLUCK, 0)),
+    ])]);
+    module.samples[0].finetune = 5;
+    module.samples[1].finetune = 0xb;
+    check("finetune", &module, TICKS);
+}
+
+#[test]
+fn arpeggio() {
+    let module = TestMod::new(vec![pattern(&[
+        (0, 0, note(C2, SAW, 0x047)),
+        (1, 0, effect(0x047)),
+        (2, 0, effect(0x037)),
+        (3, 0, effect(0x0c0)),
+    ])]);
+    check("arpeggio", &module, TICKS);
+}
+
+#[test]
+fn portamento() {
+    let module = TestMod::new(vec![pattern(&[
+        (0, 0, note(C2, SAW, 0x104)),
+        (1, 0, effect(0x104)),
+        (2, 0, effect(0x100)),
+        (0, 2, note(C2, SAW, 0x208)),
+        (1, 2, effect(0x208)),
+        (0, 3, note(C3 + 11, PLUCK, 0x1ff)),
+        (1, 3, effect(0x1ff)),
+    ])]);
+    check("portamento", &module, TICKS);
+}
+
+#[test]
+fn tone_portamento() {
+    let module = TestMod::new(vec![pattern(&[
+        (0, 0, note(C2, SAW, 0)),
+        (2, 0, note(C3, 0, 0x308)),
+        (3, 0, effect(0x300)),
+        (4, 0, effect(0x300)),
+        (6, 0, note(C1, SAW, 0x320)),
+        (0, 1, note(C3, PLUCK, 0)),
+        (1, 1, note(C2, PLUCK, 0x304)),
+    ])]);
+    check("tone_portamento", &module, TICKS);
+}
+
+#[test]
+fn tone_portamento_volume_slide() {
+    let module = TestMod::new(vec![pattern(&[
+        (0, 0, note(C2, SAW, 0)),
+        (1, 0, note(C2 + 8, 0, 0x306)),
+        (2, 0, effect(0x504)),
+        (3, 0, effect(0x504)),
+        (4, 0, effect(0x520)),
+    ])]);
+    check("tone_portamento_volume_slide", &module, TICKS);
+}
+
+#[test]
+fn vibrato() {
+    let module = TestMod::new(vec![pattern(&[
+        (0, 0, note(C2, SAW, 0x486)),
+        (1, 0, effect(0x400)),
+        (2, 0, effect(0x4f2)),
+        (3, 0, effect(0x400)),
+        (0, 2, note(C3, SAW, 0x41f)),
+        (1, 2, effect(0x400)),
+    ])]);
+    check("vibrato", &module, TICKS);
+}
+
+#[test]
+fn vibrato_volume_slide() {
+    let module = TestMod::new(vec![pattern(&[
+        (0, 0, note(C2, SAW, 0x486)),
+        (1, 0, effect(0x602)),
+        (2, 0, effect(0x602)),
+        (3, 0, effect(0x610)),
+    ])]);
+    check("vibrato_volume_slide", &module, TICKS);
+}
+
+#[test]
+fn sample_offset() {
+    let module = TestMod::new(vec![pattern(&[
+        (0, 0, note(C2, PLUCK, 0x904)),
+        (2, 0, note(C2, PLUCK, 0x900)),
+        (4, 0, note(C2, PLUCK, 0x9ff)),
+        (0, 2, note(C2, SAW, 0x901)),
+    ])]);
+    check("sample_offset", &module, TICKS);
+}
+
+#[test]
+fn volume() {
+    let module = TestMod::new(vec![pattern(&[
+        (0, 0, note(C2, SAW, 0xc20)),
+        (2, 0, effect(0xc00)),
+        (4, 0, effect(0xc40)),
+        (6, 0, effect(0xc7f)),
+        (0, 2, note(C2, PLUCK, 0xc08)),
+        (3, 2, note(C2, PLUCK, 0)),
+    ])]);
+    check("volume", &module, TICKS);
+}
+
+#[test]
+fn volume_slide() {
+    let module = TestMod::new(vec![pattern(&[
+        (0, 0, note(C2, SAW, 0xa04)),
+        (1, 0, effect(0xa0f)),
+        (2, 0, effect(0xa0f)),
+        (3, 0, effect(0xa40)),
+        (4, 0, effect(0xaf0)),
+        (0, 2, note(C2, PLUCK, 0xc10)),
+        (1, 2, effect(0xa20)),
+        (2, 2, effect(0xa20)),
+    ])]);
+    check("volume_slide", &module, TICKS);
+}
+
+#[test]
+fn retrig() {
+    let module = TestMod::new(vec![pattern(&[
+        (0, 0, note(C2, PLUCK, 0xe92)),
+        (1, 0, effect(0xe93)),
+        (2, 0, note(C3, PLUCK, 0xe91)),
+    ])]);
+    check("retrig", &module, TICKS);
+}
+
+#[test]
+fn set_speed() {
+    let module = TestMod::new(vec![pattern(&[
+        (0, 0, note(C2, SAW, 0xf02)),
+        (2, 0, note(C3, SAW, 0xf01)),
+        (4, 1, note(C2, PLUCK, 0xf0a)),
+        (5, 1, effect(0xa02)),
+    ])]);
+    check("set_speed", &module, TICKS);
+}
+
+#[test]
+fn position_jump() {
+    let module = TestMod::new(vec![
+        pattern(&[(0, 0, note(C2, SAW, 0xf02)), (2, 0, effect(0xb02))]),
+        pattern(&[(0, 0, note(C3, SAW, 0))]),
+        pattern(&[(0, 1, note(C2, PLUCK, 0)), (3, 1, effect(0xb00))]),
+    ]);
+    check("position_jump", &module, TICKS);
+}
+
+#[test]
+fn pattern_break() {
+    let module = TestMod::new(vec![
+        pattern(&[(0, 0, note(C2, SAW, 0xf02)), (1, 0, effect(0xd04))]),
+        pattern(&[
+            (3, 0, note(C3, SAW, 0)),
+            (4, 0, note(C1, PLUCK, 0)),
+            (6, 2, effect(0xd3f)),
+        ]),
+    ]);
+    check("pattern_break", &module, TICKS);
+}
+
+#[test]
+fn jump_and_break() {
+    let module = TestMod::new(vec![
+        pattern(&[
+            (0, 0, note(C2, SAW, 0xf02)),
+            (2, 0, effect(0xb02)),
+            (2, 1, effect(0xd05)),
+        ]),
+        pattern(&[(0, 0, note(C1, SAW, 0))]),
+        pattern(&[(5, 0, note(C3, PLUCK, 0)), (7, 0, effect(0xb00))]),
+    ]);
+    check("jump_and_break", &module, TICKS);
+}
+
+#[test]
+fn song_restart() {
+    let mut module = TestMod::new(vec![
+        pattern(&[(0, 0, note(C2, SAW, 0xf01)), (3, 0, effect(0xd00))]),
+        pattern(&[(0, 0, note(C3, PLUCK, 0)), (5, 0, effect(0xd00))]),
+    ]);
+    module.restart = 1;
+    check("song_restart", &module, TICKS);
+}
+
+#[test]
+fn short_loop() {
+    let mut module = TestMod::new(vec![pattern(&[
+        (0, 0, note(C2, PLUCK, 0)),
+        (0, 2, note(C1, PLUCK, 0)),
+    ])]);
+    module.samples[1] = TestSample {
+        repeat: Some((1024, 2)),
+        ..TestSample::pluck()
+    };
+    check("short_loop", &module, TICKS);
+}
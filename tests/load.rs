@@ -0,0 +1,39 @@
+mod common;
+
+use std::{thread, time::Duration};
+
+use common::{note, pattern, TestMod};
+use pfr::sound::{
+    player::{self, CommandError, PlayerConfig, Transition},
+    sfx::SfxBank,
+};
+
+fn song() -> TestMod {
+    TestMod::new(vec![pattern(&[(0, 0, note(12, 1, 0x000))])])
+}
+
+#[test]
+fn module_loads_wait_for_the_reaper() {
+    let (mut player, mut state) =
+        player::play_offline(song().load(), SfxBank::new(), PlayerConfig::default());
+    let mut loaded = 0;
+    while player.load_module(song().load(), Transition::Cut).is_ok() {
+        loaded += 1;
+    }
+    assert!(loaded > 1);
+    assert_eq!(
+        player.load_module(song().load(), Transition::Cut),
+        Err(CommandError::QueueFull)
+    );
+    // once the audio thread has swapped them in, the reaper drops the old
+    // ones and makes room again
+    let mut buf = vec![0; state.sample_rate() as usize / 50 * 2];
+    state.render(&mut buf);
+    for _ in 0..1000 {
+        if player.load_module(song().load(), Transition::Cut).is_ok() {
+            return;
+        }
+        thread::sleep(Duration::from_millis(1));
+    }
+    panic!("the reaper never caught up");
+}
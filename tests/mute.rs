@@ -0,0 +1,81 @@
+mod common;
+
+use common::{effect, note, pattern, render_ticks, TestMod};
+use pfr::sound::{
+    player::{self, CommandError, Player, PlayerConfig, PlayerState},
+    sfx::SfxBank,
+};
+
+fn song() -> TestMod {
+    TestMod::new(vec![pattern(&[
+        (0, 0, note(12, 1, 0xf03)),
+        (0, 1, note(20, 2, 0x486)),
+        (0, 2, note(17, 1, 0x000)),
+        (0, 3, note(5, 2, 0x937)),
+        (2, 0, effect(0xa01)),
+        (4, 2, note(24, 2, 0x304)),
+        (8, 3, note(30, 1, 0xc20)),
+        (12, 1, note(7, 1, 0x047)),
+    ])])
+}
+
+fn start() -> (Player, PlayerState) {
+    player::play_offline(song().load(), SfxBank::new(), PlayerConfig::default())
+}
+
+fn left(data: &[i32]) -> impl Iterator<Item = i32> + '_ {
+    data.iter().step_by(2).copied()
+}
+
+fn right(data: &[i32]) -> impl Iterator<Item = i32> + '_ {
+    data.iter().skip(1).step_by(2).copied()
+}
+
+#[test]
+fn mute_keeps_channels_playing() {
+    let reference = render_ticks(&mut start().1, 120);
+    let (player, mut state) = start();
+    player.set_channel_mute(0, true).unwrap();
+    player.set_channel_mute(1, true).unwrap();
+    let muted = render_ticks(&mut state, 40);
+    assert!(left(&muted).all(|v| v == 0));
+    assert!(right(&muted).eq(right(&reference[..muted.len()])));
+    assert_eq!(
+        player.channel_mutes(),
+        (vec![true, true, false, false], vec![false; 4])
+    );
+    // the muted channels come back where they would be without the mute
+    player.set_channel_mute(0, false).unwrap();
+    player.set_channel_mute(1, false).unwrap();
+    assert!(render_ticks(&mut state, 80) == reference[muted.len()..]);
+}
+
+#[test]
+fn solo_overrides_mutes() {
+    let (player, mut state) = start();
+    for channel in [0, 1, 3] {
+        player.set_channel_mute(channel, true).unwrap();
+    }
+    let only_2 = render_ticks(&mut state, 60);
+    assert!(right(&only_2).any(|v| v != 0));
+
+    let (player, mut state) = start();
+    player.set_channel_mute(2, true).unwrap();
+    player.set_channel_solo(2, true).unwrap();
+    assert!(render_ticks(&mut state, 60) == only_2);
+    assert_eq!(
+        player.set_channel_solo(4, true),
+        Err(CommandError::ChannelOutOfRange(4))
+    );
+}
+
+#[test]
+fn odd_buffers_end_in_silence() {
+    let (_player, mut state) = start();
+    let (_player, mut reference) = start();
+    let mut buf = vec![1; 7];
+    state.render(&mut buf);
+    let whole = render_ticks(&mut reference, 1);
+    assert_eq!(buf[..6], whole[..6]);
+    assert_eq!(buf[6], 0);
+}
@@ -0,0 +1,587 @@
+mod common;
+
+use std::io::{self, Cursor};
+
+use common::{effect, note, pattern, TestMod, TestSample};
+use pfr::sound::{
+    load_any,
+    packed::{self, Packer},
+    Format, MiscEffect, Mod, Note, PERIODS,
+};
+
+fn song() -> TestMod {
+    let mut song = TestMod::new(vec![
+        pattern(&[
+            (0, 0, note(12, 1, 0x000)),
+            (0, 1, note(20, 2, 0xa20)),
+            (1, 1, effect(0xa03)),
+            (2, 1, effect(0xa03)),
+            (4, 2, note(24, 1, 0x30a)),
+            (8, 3, note(5, 2, 0xc20)),
+            (9, 3, note(5, 2, 0xc20)),
+            (16, 0, effect(0xf04)),
+        ]),
+        pattern(&[
+            (0, 0, note(30, 1, 0x500)),
+            (1, 0, effect(0x602)),
+            (2, 2, note(35, 2, 0x000)),
+            (3, 2, effect(0xd00)),
+        ]),
+    ]);
+    song.positions = vec![0, 1, 0];
+    song
+}
+
+/// A cell as its note number from 1, sample, effect and argument.
+fn unpack(cell: u32) -> [u8; 4] {
+    let period = (cell >> 16 & 0xfff) as u16;
+    let note = PERIODS[0]
+        .iter()
+        .position(|&p| p == period)
+        .map_or(0, |n| n + 1);
+    let sample = (cell >> 24 & 0xf0 | cell >> 12 & 0xf) as u8;
+    [note as u8, sample, (cell >> 8 & 0xf) as u8, cell as u8]
+}
+
+/// The usual 3 packed bytes of a cell.
+fn pack3(cell: u32) -> [u8; 3] {
+    let [note, sample, effect, arg] = unpack(cell);
+    [note << 1 | sample >> 4, sample << 4 | effect, arg]
+}
+
+/// The length, finetune, volume, loop start and loop length of a sample, in
+/// words.
+fn header(sample: &TestSample) -> (u16, u8, u8, u16, u16) {
+    let (start, len) = sample.repeat.unwrap_or((0, 2));
+    (
+        sample.data.len() as u16 / 2,
+        sample.finetune,
+        sample.volume,
+        start as u16 / 2,
+        len as u16 / 2,
+    )
+}
+
+/// The 8-byte sample headers of ProPacker and ProRunner 2, all 31.
+fn headers8(song: &TestMod) -> Vec<u8> {
+    let mut out = vec![];
+    for i in 0..31 {
+        let (len, finetune, volume, start, repeat) =
+            song.samples.get(i).map_or((0, 0, 0, 0, 1), header);
+        out.extend_from_slice(&len.to_be_bytes());
+        out.extend_from_slice(&[finetune, volume]);
+        out.extend_from_slice(&start.to_be_bytes());
+        out.extend_from_slice(&repeat.to_be_bytes());
+    }
+    out
+}
+
+fn sample_data(song: &TestMod) -> Vec<u8> {
+    song.samples.iter().flat_map(|s| s.data.clone()).collect()
+}
+
+fn track(song: &TestMod, pattern: usize, channel: usize) -> Vec<u32> {
+    song.patterns[pattern]
+        .iter()
+        .map(|row| row[channel])
+        .collect()
+}
+
+/// Index of `item` in `list`, added if it is not there yet.
+fn index_of<T: PartialEq>(list: &mut Vec<T>, item: T) -> usize {
+    list.iter().position(|x| *x == item).unwrap_or_else(|| {
+        list.push(item);
+        list.len() - 1
+    })
+}
+
+fn prorunner1(song: &TestMod) -> Vec<u8> {
+    let mut out = song.encode();
+    out[1080..1084].copy_from_slice(b"SNT.");
+    for (i, cell) in song.patterns.iter().flatten().flatten().enumerate() {
+        let [note, sample, effect, arg] = unpack(*cell);
+        out[1084 + i * 4..1088 + i * 4].copy_from_slice(&[sample, note, effect, arg]);
+    }
+    out
+}
+
+fn prorunner2(song: &TestMod) -> Vec<u8> {
+    let mut out = b"SNT!\0\0\0\0".to_vec();
+    out.extend_from_slice(&headers8(song));
+    out.extend_from_slice(&[song.positions.len() as u8, 0x7f]);
+    let mut positions = song.positions.clone();
+    positions.resize(128, 0);
+    out.extend_from_slice(&positions);
+    out.resize(770, 0);
+    let mut last = [0; 4];
+    for pattern in &song.patterns {
+        for row in pattern {
+            for (channel, &cell) in row.iter().enumerate() {
+                if cell == last[channel] {
+                    out.push(0xc0);
+                } else if cell == 0 {
+                    out.push(0x80);
+                } else {
+                    out.extend_from_slice(&pack3(cell));
+                }
+                last[channel] = cell;
+            }
+        }
+    }
+    let at = out.len() as u32;
+    out[4..8].copy_from_slice(&at.to_be_bytes());
+    out.extend_from_slice(&sample_data(song));
+    out
+}
+
+/// ProPacker 2.1, or 1.0 with the notes in the tracks.
+fn propacker(song: &TestMod, v21: bool) -> Vec<u8> {
+    let mut out = headers8(song);
+    out.extend_from_slice(&[song.positions.len() as u8, 0x7f]);
+    let mut tracks = vec![];
+    for channel in 0..4 {
+        let mut list = [0; 128];
+        for (i, &p) in song.positions.iter().enumerate() {
+            list[i] = index_of(&mut tracks, track(song, p as usize, channel)) as u8;
+        }
+        out.extend_from_slice(&list);
+    }
+    let mut notes = vec![];
+    for cell in tracks.iter().flatten() {
+        if v21 {
+            out.extend_from_slice(&(index_of(&mut notes, *cell) as u16).to_be_bytes());
+        } else {
+            out.extend_from_slice(&cell.to_be_bytes());
+        }
+    }
+    if v21 {
+        out.extend_from_slice(&(notes.len() as u32 * 4).to_be_bytes());
+        for cell in notes {
+            out.extend_from_slice(&cell.to_be_bytes());
+        }
+    }
+    out.extend_from_slice(&sample_data(song));
+    out
+}
+
+fn noisepacker(song: &TestMod, np3: bool) -> Vec<u8> {
+    let samples = song.samples.len() as u16;
+    let mut tracks = vec![];
+    let mut list = vec![];
+    for p in 0..song.patterns.len() {
+        for channel in (0..4).rev() {
+            list.push(index_of(&mut tracks, track(song, p, channel)));
+        }
+    }
+    let mut data = vec![];
+    let mut offsets = vec![];
+    for track in &tracks {
+        offsets.push(data.len() as u16);
+        let mut empty = 0;
+        for (row, &cell) in track.iter().enumerate() {
+            if np3 && cell == 0 {
+                empty += 1;
+                if row + 1 < track.len() && track[row + 1] == 0 {
+                    continue;
+                }
+                data.push((0x100 - empty) as u8);
+                empty = 0;
+                continue;
+            }
+            let [a, b, mut arg] = pack3(cell);
+            if matches!(b & 0xf, 5 | 6 | 0xa) {
+                arg = if arg >> 4 != 0 {
+                    arg >> 4
+                } else {
+                    (0x100 - (arg & 0xf) as u16) as u8
+                };
+            }
+            data.extend_from_slice(&[a, b, arg]);
+        }
+    }
+    let mut out = vec![];
+    for field in [
+        samples << 4 | 0xc,
+        song.positions.len() as u16 * 2,
+        list.len() as u16 * 2,
+        data.len() as u16,
+    ] {
+        out.extend_from_slice(&field.to_be_bytes());
+    }
+    for sample in &song.samples {
+        let (len, finetune, volume, start, repeat) = header(sample);
+        if np3 {
+            out.extend_from_slice(&[finetune, volume, 0, 0, 0, 0]);
+            out.extend_from_slice(&len.to_be_bytes());
+        } else {
+            out.extend_from_slice(&[0; 4]);
+            out.extend_from_slice(&len.to_be_bytes());
+            out.extend_from_slice(&[finetune, volume]);
+        }
+        out.extend_from_slice(&[0; 4]);
+        out.extend_from_slice(&repeat.to_be_bytes());
+        out.extend_from_slice(&(start * 2).to_be_bytes());
+    }
+    for &p in &song.positions {
+        out.extend_from_slice(&(p as u16 * 8).to_be_bytes());
+    }
+    for &track in &list {
+        out.extend_from_slice(&offsets[track].to_be_bytes());
+    }
+    out.extend_from_slice(&data);
+    out.extend_from_slice(&sample_data(song));
+    out
+}
+
+/// The Player 6.1, with runs of empty rows and repeated notes packed.
+fn player61(song: &TestMod, tagged: bool) -> Vec<u8> {
+    let mut tracks = vec![];
+    let mut table = vec![];
+    for p in 0..song.patterns.len() {
+        for channel in 0..4 {
+            table.push(index_of(&mut tracks, track(song, p, channel)));
+        }
+    }
+    let mut data = vec![];
+    let mut offsets = vec![];
+    for track in &tracks {
+        offsets.push(data.len() as u16);
+        let mut row = 0;
+        while row < track.len() {
+            let cell = track[row];
+            let [note, sample, effect, arg] = unpack(cell);
+            let at = data.len();
+            match (cell, note, sample) {
+                (0, _, _) => data.push(0x70),
+                (_, 0, 0) => data.extend_from_slice(&[0x60 | effect, arg]),
+                _ => data.extend_from_slice(&pack3(cell)),
+            }
+            let same = track[row + 1..].iter().take_while(|&&c| c == cell).count();
+            if same > 0 {
+                data[at] |= 0x80;
+                data.push(if cell == 0 { 0 } else { 0x40 } | same as u8);
+            }
+            row += 1 + same;
+        }
+    }
+    let mut out = vec![];
+    if tagged {
+        out.extend_from_slice(b"P61A");
+    }
+    let base = out.len();
+    out.extend_from_slice(&[0, 0, song.patterns.len() as u8, song.samples.len() as u8]);
+    for sample in &song.samples {
+        let (len, finetune, volume, start, _) = header(sample);
+        out.extend_from_slice(&len.to_be_bytes());
+        out.extend_from_slice(&[finetune, volume]);
+        let start = if sample.repeat.is_some() {
+            start
+        } else {
+            0xffff
+        };
+        out.extend_from_slice(&start.to_be_bytes());
+    }
+    for &track in &table {
+        out.extend_from_slice(&offsets[track].to_be_bytes());
+    }
+    out.extend_from_slice(&song.positions);
+    out.push(0xff);
+    out.extend_from_slice(&data);
+    let at = (out.len() - base) as u16;
+    out[base..base + 2].copy_from_slice(&at.to_be_bytes());
+    out.extend_from_slice(&sample_data(song));
+    out
+}
+
+/// Crunches like PowerPacker does, greedily, with offsets up to 127.
+fn crunch(data: &[u8]) -> Vec<u8> {
+    const WIDTHS: [u8; 4] = [9, 10, 11, 12];
+    let mut bits = vec![];
+    let put = |bits: &mut Vec<bool>, val: usize, count: usize| {
+        bits.extend((0..count).rev().map(|i| val >> i & 1 != 0));
+    };
+    let mut literals = vec![];
+    let mut pos = data.len();
+    while pos > 0 {
+        let (count, offset) = (0..(data.len() - pos).min(0x80))
+            .map(|offset| {
+                let count = (0..pos)
+                    .take_while(|&i| data[pos - 1 - i] == data[pos + offset - i])
+                    .count();
+                (count, offset)
+            })
+            .max()
+            .unwrap_or((0, 0));
+        if count < 2 {
+            literals.push(data[pos - 1]);
+            pos -= 1;
+            continue;
+        }
+        if literals.is_empty() {
+            put(&mut bits, 1, 1);
+        } else {
+            put(&mut bits, 0, 1);
+            let mut run = literals.len() - 1;
+            while run >= 3 {
+                put(&mut bits, 3, 2);
+                run -= 3;
+            }
+            put(&mut bits, run, 2);
+            for &b in &literals {
+                put(&mut bits, b as usize, 8);
+            }
+            literals.clear();
+        }
+        if count <= 4 {
+            put(&mut bits, count - 2, 2);
+            put(&mut bits, offset, WIDTHS[count - 2] as usize);
+        } else {
+            put(&mut bits, 3, 2);
+            put(&mut bits, 0, 1);
+            put(&mut bits, offset, 7);
+            let mut more = count - 5;
+            while more >= 7 {
+                put(&mut bits, 7, 3);
+                more -= 7;
+            }
+            put(&mut bits, more, 3);
+        }
+        pos -= count;
+    }
+    if !literals.is_empty() {
+        put(&mut bits, 0, 1);
+        let mut run = literals.len() - 1;
+        while run >= 3 {
+            put(&mut bits, 3, 2);
+            run -= 3;
+        }
+        put(&mut bits, run, 2);
+        for &b in &literals {
+            put(&mut bits, b as usize, 8);
+        }
+    }
+    // read from the last byte back, lowest bit first, after the skipped ones
+    let skip = (8 - bits.len() % 8) % 8;
+    let bits: Vec<bool> = std::iter::repeat_n(false, skip).chain(bits).collect();
+    let mut packed = vec![0; bits.len() / 8];
+    let last = packed.len() - 1;
+    for (i, _) in bits.iter().enumerate().filter(|(_, &b)| b) {
+        packed[last - i / 8] |= 1 << (i % 8);
+    }
+    let mut out = packed::POWERPACKER_TAG.to_vec();
+    out.extend_from_slice(&WIDTHS);
+    out.extend_from_slice(&packed);
+    let len = data.len() as u32;
+    out.extend_from_slice(&[(len >> 16) as u8, (len >> 8) as u8, len as u8, skip as u8]);
+    out
+}
+
+fn load(file: &[u8]) -> (Mod, Format) {
+    let (module, format, warnings) = load_any(&mut Cursor::new(file)).unwrap();
+    assert!(warnings.is_empty());
+    (module, format)
+}
+
+/// Checks that two modules play the same patterns and samples.
+fn assert_same_song(module: &Mod, reference: &Mod, what: &str) {
+    assert_eq!(module.positions.len(), reference.positions.len(), "{what}");
+    for (&p, &q) in module.positions.iter().zip(&reference.positions) {
+        assert_eq!(
+            format!("{:?}", module.patterns[p as usize]),
+            format!("{:?}", reference.patterns[q as usize]),
+            "{what}"
+        );
+    }
+    for (a, b) in module.samples.iter().zip(&reference.samples) {
+        assert_eq!(
+            (&a.data, a.repeat, a.volume, a.finetune),
+            (&b.data, b.repeat, b.volume, b.finetune),
+            "{what}"
+        );
+    }
+}
+
+#[test]
+fn depacks_to_the_same_song() {
+    let song = song();
+    let reference = song.load();
+    let (plain, format) = load(&song.encode());
+    assert_eq!(format, Format::Mod("M.K.".into()));
+    assert_same_song(&plain, &reference, "plain");
+    for (file, packer) in [
+        (prorunner1(&song), Packer::ProRunner1),
+        (prorunner2(&song), Packer::ProRunner2),
+        (propacker(&song, false), Packer::ProPacker10),
+        (propacker(&song, true), Packer::ProPacker21),
+        (noisepacker(&song, false), Packer::NoisePacker2),
+        (noisepacker(&song, true), Packer::NoisePacker3),
+        (player61(&song, true), Packer::ThePlayer61),
+        (player61(&song, false), Packer::ThePlayer61),
+    ] {
+        assert_eq!(packed::detect(&file), Some(packer));
+        let (module, format) = load(&file);
+        assert_eq!(format, Format::Packed(packer));
+        assert_same_song(&module, &reference, &packer.to_string());
+    }
+}
+
+#[test]
+fn decrunches_powerpacker() {
+    let song = song();
+    let reference = song.load();
+    let plain = song.encode();
+    let crunched = crunch(&plain);
+    assert!(crunched.len() < plain.len() / 2);
+    assert_eq!(packed::decrunch(&crunched).unwrap(), plain);
+    let (module, format) = load(&crunched);
+    assert_eq!(
+        format,
+        Format::PowerPacker(Box::new(Format::Mod("M.K.".into())))
+    );
+    assert_same_song(&module, &reference, "PowerPacker");
+
+    let (module, format) = load(&crunch(&prorunner2(&song)));
+    assert_eq!(format.to_string(), "ProRunner 2 in PowerPacker");
+    assert_same_song(&module, &reference, "crunched ProRunner 2");
+
+    let mut broken = crunched.clone();
+    broken.drain(8..crunched.len() / 2);
+    assert!(packed::decrunch(&broken).is_err());
+}
+
+#[test]
+fn player61_repeats_earlier_rows() {
+    let mut song = TestMod::new(vec![pattern(&[
+        (0, 0, note(0, 1, 0x000)),
+        (1, 0, effect(0xc20)),
+        (2, 0, note(2, 1, 0x000)),
+        (3, 0, note(0, 1, 0x000)),
+        (4, 0, effect(0xc20)),
+    ])]);
+    song.samples.truncate(1);
+    let mut file = b"P61A".to_vec();
+    file.extend_from_slice(&[0, 34, 1, 1]);
+    file.extend_from_slice(&[0, 16, 0, 0x40, 0, 0]);
+    file.extend_from_slice(&[0, 0, 0, 12, 0, 12, 0, 12]);
+    file.extend_from_slice(&[0, 0xff]);
+    file.extend_from_slice(&[
+        // C-1, then C20 alone
+        0x02, 0x10, 0x00, 0x6c, 0x20, // D-1, then the two rows 10 bytes back again
+        0x86, 0x10, 0x00, 0x82, 10, // the rest of the rows empty
+        0xf0, 58, // an empty track
+        0xf0, 63,
+    ]);
+    file.extend_from_slice(&song.samples[0].data);
+    let (module, _) = load(&file);
+    assert_same_song(&module, &song.load(), "P61 repeat");
+
+    file[10] = 0x80;
+    assert!(load_any(&mut Cursor::new(&file))
+        .err()
+        .unwrap()
+        .to_string()
+        .contains("packed"));
+}
+
+#[test]
+fn rejects_broken_packed_files() {
+    let song = song();
+    let mut truncated = prorunner2(&song);
+    truncated.truncate(800);
+    assert!(load_any(&mut Cursor::new(&truncated)).is_err());
+    let mut bad_note = prorunner1(&song);
+    bad_note[1085] = 40;
+    assert!(load_any(&mut Cursor::new(&bad_note))
+        .err()
+        .unwrap()
+        .to_string()
+        .contains("note 40"));
+}
+
+#[test]
+fn rejects_broken_mods() {
+    let song = song();
+    let kind = |file: &[u8]| load_any(&mut Cursor::new(file)).err().unwrap().kind();
+    let mut bad_name = song.encode();
+    bad_name[0] = 0xff;
+    assert_eq!(kind(&bad_name), io::ErrorKind::InvalidData);
+    let mut bad_finetune = song.encode();
+    bad_finetune[20 + 24] = 0x10;
+    assert_eq!(kind(&bad_finetune), io::ErrorKind::InvalidData);
+    let mut empty = song.encode();
+    empty[950] = 0;
+    assert_eq!(kind(&empty), io::ErrorKind::InvalidData);
+    let mut bad_period = song.encode();
+    bad_period[1084..1086].copy_from_slice(&[0x01, 0x23]);
+    assert!(load_any(&mut Cursor::new(&bad_period))
+        .err()
+        .unwrap()
+        .to_string()
+        .contains("period 123"));
+}
+
+#[test]
+fn loops_end_with_their_sample() {
+    let mut song = song();
+    // a word past the end, which packed files get away with
+    song.samples[0].repeat = Some((30, 4));
+    for file in [song.encode(), prorunner2(&song)] {
+        let (module, _) = load(&file);
+        assert_eq!(module.samples[1].repeat, Some((30, 2)));
+    }
+}
+
+#[test]
+fn rejects_samples_past_31() {
+    let mut song = song();
+    song.patterns[0][5][0] = note(0, 0x20, 0x000);
+    for file in [song.encode(), propacker(&song, true)] {
+        let err = load_any(&mut Cursor::new(&file)).err().unwrap();
+        assert!(err.to_string().contains("sample 32"), "{err}");
+    }
+}
+
+/// Row 8 of the second pattern of the test song, with `cell` on channel 0.
+fn load_cell(cell: u32) -> Note {
+    let mut song = song();
+    song.patterns[1][8][0] = cell;
+    song.load().patterns[1][8][0]
+}
+
+#[test]
+fn drops_speed_0() {
+    assert!(matches!(
+        load_cell(effect(0xf00)).misc_effect,
+        MiscEffect::None
+    ));
+}
+
+#[test]
+fn drops_retrig_every_0_ticks() {
+    assert!(matches!(
+        load_cell(effect(0xe90)).misc_effect,
+        MiscEffect::None
+    ));
+}
+
+#[test]
+fn jumps_past_the_end_start_the_song_over() {
+    // the song has three positions
+    let jump = |cell| match load_cell(cell).misc_effect {
+        MiscEffect::PositionJump(position) => position,
+        effect => panic!("no jump: {effect:?}"),
+    };
+    assert_eq!(jump(effect(0xb02)), 2);
+    assert_eq!(jump(effect(0xb03)), 0);
+    assert_eq!(jump(effect(0xbff)), 0);
+}
+
+#[test]
+fn notes_keep_their_protracker_names() {
+    let module = song().load();
+    let name = |row: usize, channel: usize| module.patterns[0][row][channel].to_string();
+    assert!(name(0, 0).starts_with("C-2 "), "{}", name(0, 0));
+    assert!(name(8, 3).starts_with("F-1 "), "{}", name(8, 3));
+    // tone portamento names its target
+    assert!(name(4, 2).contains(" Po0a C-3 "), "{}", name(4, 2));
+}
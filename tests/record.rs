@@ -0,0 +1,194 @@
+mod common;
+
+use std::num::NonZeroU8;
+
+use common::{effect, note, pattern, TestMod};
+use pfr::sound::{
+    player::{
+        self, Call, CommandError, JingleMode, JingleRequest, JingleStart, PlayerConfig,
+        RecordedCall, Transition,
+    },
+    sfx::SfxBank,
+};
+
+fn song() -> TestMod {
+    TestMod::new(vec![
+        pattern(&[
+            (0, 0, note(12, 1, 0xf03)),
+            (0, 1, note(20, 2, 0x486)),
+            (2, 0, effect(0xa01)),
+            (4, 2, note(17, 0, 0x304)),
+            (8, 3, note(5, 2, 0x937)),
+            (16, 0, note(24, 1, 0x000)),
+            (32, 1, note(7, 2, 0xc20)),
+        ]),
+        pattern(&[
+            (0, 0, note(30, 1, 0x047)),
+            (4, 2, effect(0xa20)),
+            (8, 1, note(7, 2, 0xf04)),
+            (12, 3, effect(0xb00)),
+        ]),
+    ])
+}
+
+fn jingle(position: u8, start: JingleStart, transition: Transition) -> JingleRequest {
+    JingleRequest {
+        position,
+        repeats: 1,
+        priority: 5,
+        mode: JingleMode::Normal,
+        start,
+        resume: false,
+        transition,
+        return_transition: Transition::FadeIn(20),
+    }
+}
+
+#[test]
+fn replay_is_bit_exact() {
+    let module = song();
+    let mut other = song();
+    other.positions = vec![1, 0];
+    let sfx = || SfxBank::from_mod(&module.load());
+    let config = PlayerConfig {
+        record_calls: true,
+        ..Default::default()
+    };
+    let (mut player, mut state) = player::play_offline(module.load(), sfx(), config);
+    let log = player.take_call_log().unwrap();
+    // buffers of odd sizes, so calls land in the middle of ticks
+    let mut recorded = vec![];
+    let mut render = |state: &mut player::PlayerState, frames: usize| {
+        let mut buf = vec![0; frames * 2];
+        state.render(&mut buf);
+        recorded.extend_from_slice(&buf);
+    };
+    render(&mut state, 3001);
+    player
+        .jingle(jingle(1, JingleStart::Now, Transition::Crossfade(10)))
+        .unwrap();
+    player.play_sfx(12, 2, 0, 3).unwrap();
+    render(&mut state, 1234);
+    player.set_paused(true);
+    render(&mut state, 500);
+    player.set_paused(false);
+    player.set_music_pos(1).unwrap();
+    render(&mut state, 17777);
+    player.set_master_volume(60);
+    player.set_channel_mute(1, true).unwrap();
+    render(&mut state, 2500);
+    player.set_channel_solo(2, true).unwrap();
+    render(&mut state, 2500);
+    player
+        .load_module(other.load(), Transition::Crossfade(20))
+        .unwrap();
+    render(&mut state, 5000);
+    player
+        .jingle(jingle(
+            0,
+            JingleStart::NextBeat(NonZeroU8::new(4).unwrap()),
+            Transition::FadeOut(8),
+        ))
+        .unwrap();
+    render(&mut state, 9999);
+    player.seek(0, 8).unwrap();
+    render(&mut state, 4321);
+    player.fade_out(30).unwrap();
+    render(&mut state, 20000);
+    player.fade_in(5).unwrap();
+    render(&mut state, 30000);
+    let log: Vec<RecordedCall> = log.try_iter().collect();
+    assert_eq!(player.dropped_calls(), 0);
+    assert_eq!(log.len(), 13);
+    assert_eq!(log[0].frame, 3001);
+    assert_eq!(log[0].tick, 4);
+    assert_eq!(log[2].call, Call::SetPaused(true));
+    assert_eq!(log[2].frame, 4235);
+    assert_eq!(log[4].call, Call::SetMusicPos(1));
+    assert_eq!(log[4].frame, 4735);
+    assert_eq!(log[5].call, Call::SetMasterVolume(60));
+    assert_eq!(log[6].call, Call::SetChannelMutes(0b10));
+    assert_eq!(log[7].call, Call::SetChannelSolos(0b100));
+    assert_eq!(
+        log[8].call,
+        Call::LoadModule {
+            number: 1,
+            transition: Transition::Crossfade(20)
+        }
+    );
+    assert!(matches!(log[10].call, Call::Seek(_)));
+
+    let (mut player, mut state) =
+        player::play_offline(module.load(), sfx(), PlayerConfig::default());
+    let mut replayed = vec![0; recorded.len()];
+    assert_eq!(
+        player::replay(&mut player, &mut state, &log, &[], &mut replayed),
+        Err(CommandError::ModuleMissing(1))
+    );
+    let (mut player, mut state) =
+        player::play_offline(module.load(), sfx(), PlayerConfig::default());
+    let (start, end) = replayed.split_at_mut(24000 * 2);
+    let modules = [other.load()];
+    player::replay(&mut player, &mut state, &log[..6], &modules, start).unwrap();
+    player::replay(&mut player, &mut state, &log[6..], &modules, end).unwrap();
+    assert!(
+        replayed == recorded,
+        "replay differs from the recorded session"
+    );
+}
+
+#[test]
+fn lost_calls_are_reported() {
+    let config = PlayerConfig {
+        record_calls: true,
+        ..Default::default()
+    };
+    let (mut player, mut state) = player::play_offline(song().load(), SfxBank::new(), config);
+    let log = player.take_call_log().unwrap();
+    let mut buf = vec![0; 200];
+    for i in 0..2000 {
+        player.set_music_prio(i as u8 & 0x3f).unwrap();
+        state.render(&mut buf);
+    }
+    assert_eq!(player.dropped_calls(), 2000 - 1024);
+    // the next call that makes it into the log tells how many were lost
+    let mut calls: Vec<RecordedCall> = log.try_iter().collect();
+    player.set_music_prio(1).unwrap();
+    state.render(&mut buf);
+    calls.extend(log.try_iter());
+    assert_eq!(calls.len(), 1025);
+    assert_eq!(calls[1024].dropped, 2000 - 1024);
+    let (mut player, mut state) =
+        player::play_offline(song().load(), SfxBank::new(), PlayerConfig::default());
+    assert_eq!(
+        player::replay(&mut player, &mut state, &calls, &[], &mut buf),
+        Err(CommandError::CallsDropped(2000 - 1024))
+    );
+}
+
+#[test]
+fn module_numbers_count_from_1() {
+    let load = |number| RecordedCall {
+        frame: 100,
+        tick: 0,
+        dropped: 0,
+        call: Call::LoadModule {
+            number,
+            transition: Transition::Cut,
+        },
+    };
+    let modules = [song().load()];
+    let mut buf = vec![0; 400];
+    for (number, res) in [
+        (0, Err(CommandError::ModuleMissing(0))),
+        (1, Ok(())),
+        (2, Err(CommandError::ModuleMissing(2))),
+    ] {
+        let (mut player, mut state) =
+            player::play_offline(song().load(), SfxBank::new(), PlayerConfig::default());
+        assert_eq!(
+            player::replay(&mut player, &mut state, &[load(number)], &modules, &mut buf),
+            res
+        );
+    }
+}
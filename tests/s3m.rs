@@ -0,0 +1,384 @@
+mod common;
+
+use std::io::{self, Cursor};
+
+use common::render_ticks;
+use pfr::sound::{
+    player::{self, PlayerConfig},
+    s3m::{self, Warning},
+    sfx::SfxBank,
+    MiscEffect, Mod, SampleData, ToneEffect, VolumeEffect,
+};
+
+/// A cell of an S3M pattern.  `note` is the octave in the high nibble and
+/// the key in the low one, as stored.
+#[derive(Clone, Copy, Default)]
+struct Cell {
+    note: Option<(u8, u8)>,
+    volume: Option<u8>,
+    effect: Option<(char, u8)>,
+}
+
+fn note(note: u8, instrument: u8) -> Cell {
+    Cell {
+        note: Some((note, instrument)),
+        ..Default::default()
+    }
+}
+
+fn fx(effect: char, arg: u8) -> Cell {
+    Cell {
+        effect: Some((effect, arg)),
+        ..Default::default()
+    }
+}
+
+impl Cell {
+    fn volume(self, volume: u8) -> Self {
+        Cell {
+            volume: Some(volume),
+            ..self
+        }
+    }
+
+    fn fx(self, effect: char, arg: u8) -> Self {
+        Cell {
+            effect: Some((effect, arg)),
+            ..self
+        }
+    }
+}
+
+struct TestSample {
+    /// As stored, unsigned.
+    data: Vec<u8>,
+    frames: u32,
+    flags: u8,
+    repeat: (u32, u32),
+    c2spd: u32,
+}
+
+impl TestSample {
+    /// A looped 8-bit saw.
+    fn saw() -> Self {
+        TestSample {
+            data: (0..32).map(|i| i * 8).collect(),
+            frames: 32,
+            flags: 1,
+            repeat: (0, 32),
+            c2spd: 8363,
+        }
+    }
+}
+
+struct TestS3m {
+    settings: [u8; 32],
+    panning: Option<[u8; 32]>,
+    stereo: bool,
+    speed: u8,
+    tempo: u8,
+    global_volume: u8,
+    orders: Vec<u8>,
+    samples: Vec<TestSample>,
+    patterns: Vec<Vec<(usize, usize, Cell)>>,
+}
+
+impl TestS3m {
+    /// A mono song of `channels` channels and the saw as instrument 1.
+    fn new(channels: usize, patterns: Vec<Vec<(usize, usize, Cell)>>) -> Self {
+        let mut settings = [0xff; 32];
+        for (i, setting) in settings.iter_mut().enumerate().take(channels) {
+            *setting = i as u8;
+        }
+        TestS3m {
+            settings,
+            panning: None,
+            stereo: false,
+            speed: 6,
+            tempo: 125,
+            global_volume: 0x40,
+            orders: (0..patterns.len() as u8).collect(),
+            samples: vec![TestSample::saw()],
+            patterns,
+        }
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut out = vec![0; 0x60];
+        out[..4].copy_from_slice(b"test");
+        out[0x1c] = 0x1a;
+        out[0x1d] = 16;
+        out[0x20..0x22].copy_from_slice(&(self.orders.len() as u16).to_le_bytes());
+        out[0x22..0x24].copy_from_slice(&(self.samples.len() as u16).to_le_bytes());
+        out[0x24..0x26].copy_from_slice(&(self.patterns.len() as u16).to_le_bytes());
+        out[0x2a] = 2;
+        out[0x2c..0x30].copy_from_slice(b"SCRM");
+        out[0x30] = self.global_volume;
+        out[0x31] = self.speed;
+        out[0x32] = self.tempo;
+        out[0x33] = if self.stereo { 0xb0 } else { 0x30 };
+        out[0x35] = if self.panning.is_some() { 252 } else { 0 };
+        out[0x40..0x60].copy_from_slice(&self.settings);
+        out.extend_from_slice(&self.orders);
+        let ptrs = out.len();
+        out.resize(ptrs + 2 * (self.samples.len() + self.patterns.len()), 0);
+        if let Some(panning) = self.panning {
+            out.extend_from_slice(&panning);
+        }
+        // appends a blob at the next paragraph, returning its parapointer
+        let place = |out: &mut Vec<u8>, blob: &[u8]| {
+            out.resize(out.len().next_multiple_of(16), 0);
+            let at = out.len() / 16;
+            out.extend_from_slice(blob);
+            at
+        };
+        for (i, pattern) in self.patterns.iter().enumerate() {
+            let mut data = vec![0, 0];
+            for row in 0..0x40 {
+                for &(_, channel, cell) in pattern.iter().filter(|c| c.0 == row) {
+                    let mut what = channel as u8;
+                    let mut bytes = vec![];
+                    if let Some((note, instrument)) = cell.note {
+                        what |= 0x20;
+                        bytes.extend_from_slice(&[note, instrument]);
+                    }
+                    if let Some(volume) = cell.volume {
+                        what |= 0x40;
+                        bytes.push(volume);
+                    }
+                    if let Some((effect, arg)) = cell.effect {
+                        what |= 0x80;
+                        bytes.extend_from_slice(&[effect as u8 - b'@', arg]);
+                    }
+                    data.push(what);
+                    data.extend_from_slice(&bytes);
+                }
+                data.push(0);
+            }
+            let len = data.len() as u16;
+            data[..2].copy_from_slice(&len.to_le_bytes());
+            let at = place(&mut out, &data) as u16;
+            let ptr = ptrs + 2 * (self.samples.len() + i);
+            out[ptr..ptr + 2].copy_from_slice(&at.to_le_bytes());
+        }
+        for (i, sample) in self.samples.iter().enumerate() {
+            let data_at = place(&mut out, &sample.data);
+            let mut hdr = vec![0; 0x50];
+            hdr[0] = 1;
+            hdr[0x0d] = (data_at >> 16) as u8;
+            hdr[0x0e..0x10].copy_from_slice(&(data_at as u16).to_le_bytes());
+            hdr[0x10..0x14].copy_from_slice(&sample.frames.to_le_bytes());
+            hdr[0x14..0x18].copy_from_slice(&sample.repeat.0.to_le_bytes());
+            hdr[0x18..0x1c].copy_from_slice(&sample.repeat.1.to_le_bytes());
+            hdr[0x1c] = 0x40;
+            hdr[0x1f] = sample.flags;
+            hdr[0x20..0x24].copy_from_slice(&sample.c2spd.to_le_bytes());
+            hdr[0x30..0x34].copy_from_slice(b"saw!");
+            hdr[0x4c..0x50].copy_from_slice(b"SCRS");
+            let at = place(&mut out, &hdr) as u16;
+            out[ptrs + 2 * i..ptrs + 2 * i + 2].copy_from_slice(&at.to_le_bytes());
+        }
+        out
+    }
+
+    fn load(&self) -> (Mod, Vec<Warning>) {
+        s3m::load(&mut Cursor::new(self.encode())).unwrap()
+    }
+}
+
+#[test]
+fn converts_channels_notes_and_effects() {
+    let mut song = TestS3m::new(
+        0,
+        vec![
+            vec![
+                (0, 0, note(0x40, 1).volume(0x20).fx('A', 3)),
+                (0, 1, note(0x54, 2).fx('D', 0xf2)),
+                (0, 2, note(0x40, 1)),
+                (0, 4, fx('T', 0x80)),
+                (1, 0, fx('X', 0x40)),
+                (1, 1, fx('E', 0xe4)),
+                (2, 0, fx('S', 0xd3)),
+                (3, 1, fx('D', 0)),
+                (4, 0, fx('B', 2)),
+            ],
+            vec![(0, 0, note(0xfe, 0).fx('S', 0x8f))],
+        ],
+    );
+    // sample channels on the left and right, an Adlib one in between
+    song.settings[..4].copy_from_slice(&[0, 8, 1, 16]);
+    song.settings[4] = 2;
+    song.stereo = true;
+    song.orders = vec![0, 254, 1, 255, 0];
+    song.speed = 3;
+    song.global_volume = 0x30;
+    let mut panning = [0; 32];
+    panning[4] = 0x20 | 0xf;
+    song.panning = Some(panning);
+    // 16-bit stereo, full scale on the left and silence on the right
+    let mut data = vec![0xff; 8];
+    data.extend_from_slice(&[0, 0x80, 0, 0x80, 0, 0x80, 0, 0x80]);
+    song.samples.push(TestSample {
+        data,
+        frames: 4,
+        flags: 6,
+        repeat: (0, 0),
+        c2spd: 16726,
+    });
+    let (module, warnings) = song.load();
+
+    assert_eq!(module.name, "test");
+    assert_eq!(module.channels(), 4);
+    assert_eq!(module.panning, [25, 102, 25, 0x80]);
+    assert_eq!((module.speed, module.global_volume), (3, 0x30));
+    assert_eq!(module.positions, [0, 1]);
+    let saw = &module.samples[1];
+    assert_eq!(saw.c2spd, Some(8363));
+    assert_eq!(saw.repeat, Some((0, 32)));
+    assert_eq!(saw.data.frame(0), i16::MIN);
+    assert_eq!(saw.data.frame(31), 0x78 << 8);
+    assert_eq!(
+        module.samples[2].data,
+        SampleData::Stereo16(vec![[0x7fff, 0]; 4])
+    );
+
+    let cells = &module.patterns[0];
+    assert_eq!(cells[0][0].period, Some(48));
+    assert_eq!(cells[0][0].volume, Some(0x20));
+    assert!(matches!(cells[0][0].misc_effect, MiscEffect::SetSpeed(3)));
+    assert_eq!(cells[0][1].period, Some(64));
+    assert!(matches!(
+        cells[0][1].volume_effect,
+        VolumeEffect::FineVolumeSlide(-2)
+    ));
+    assert!(matches!(
+        cells[1][0].misc_effect,
+        MiscEffect::SetPanning(0x40)
+    ));
+    assert!(matches!(
+        cells[1][1].tone_effect,
+        ToneEffect::FinePortamento(1)
+    ));
+    // D00 slides as the last D of the channel did
+    assert!(matches!(
+        cells[3][1].volume_effect,
+        VolumeEffect::FineVolumeSlide(-2)
+    ));
+    // the skipped order does not count
+    assert!(matches!(
+        cells[4][0].misc_effect,
+        MiscEffect::PositionJump(1)
+    ));
+    let cut = module.patterns[1][0][0];
+    assert_eq!((cut.period, cut.volume), (None, Some(0)));
+    assert!(matches!(cut.misc_effect, MiscEffect::SetPanning(0x80)));
+
+    assert_eq!(
+        warnings,
+        [
+            Warning::AdlibChannels(1),
+            Warning::UnsupportedEffect {
+                effect: "SD".into(),
+                count: 1
+            },
+            Warning::UnsupportedEffect {
+                effect: "T".into(),
+                count: 1
+            },
+            Warning::ApproximatedEffect {
+                effect: "EE".into(),
+                count: 1
+            },
+        ]
+    );
+}
+
+#[test]
+fn plays_at_c2spd() {
+    let mut song = TestS3m::new(2, vec![vec![(0, 0, note(0x40, 1)), (0, 1, note(0x50, 2))]]);
+    song.samples.push(TestSample {
+        c2spd: 4181,
+        ..TestSample::saw()
+    });
+    let (_player, mut state) =
+        player::play_offline(song.load().0, SfxBank::new(), PlayerConfig::default());
+    render_ticks(&mut state, 1);
+    let channels = state.channels();
+    assert_eq!(channels.len(), 2);
+    // C-4 at 8363Hz is about ProTracker's C-2, and so is C-5 at half the
+    // rate
+    assert_eq!(channels[0].period, 424);
+    assert_eq!(channels[1].period, 424);
+
+    // rates too high to play are turned down rather than overflowing
+    let mut fast = TestS3m::new(1, vec![vec![(0, 0, note(0x90, 1))]]);
+    fast.samples[0].c2spd = u32::MAX;
+    let module = fast.load().0;
+    assert_eq!(module.samples[1].c2spd, Some(0xffff));
+    let (_player, mut state) =
+        player::play_offline(module, SfxBank::new(), PlayerConfig::default());
+    render_ticks(&mut state, 1);
+    assert_eq!(state.channels()[0].period, 1);
+}
+
+#[test]
+fn pans_and_scales_the_mix() {
+    let song = |pan: u8, global_volume: u8| {
+        let mut song = TestS3m::new(1, vec![vec![(0, 0, note(0x40, 1).fx('X', pan))]]);
+        song.global_volume = global_volume;
+        let (_player, mut state) =
+            player::play_offline(song.load().0, SfxBank::new(), PlayerConfig::default());
+        render_ticks(&mut state, 10)
+    };
+    let sides = |data: &[i32]| {
+        let left: Vec<i32> = data.iter().step_by(2).copied().collect();
+        let right: Vec<i32> = data.iter().skip(1).step_by(2).copied().collect();
+        (left, right)
+    };
+    let (left, right) = sides(&song(0x40, 0x40));
+    assert!(left.iter().any(|&v| v != 0));
+    assert_eq!(left, right);
+    let (hard_left, silent) = sides(&song(0, 0x40));
+    assert!(silent.iter().all(|&v| v == 0));
+    let (quiet, _) = sides(&song(0, 0x20));
+    let level = |data: &[i32]| data.iter().map(|v| v.unsigned_abs() as u64).sum::<u64>();
+    let (full, half) = (level(&hard_left), level(&quiet));
+    assert!(full.abs_diff(half * 2) < full / 100);
+}
+
+#[test]
+fn rejects_what_does_not_convert() {
+    let err = |data: Vec<u8>| match s3m::load(&mut Cursor::new(data)) {
+        Ok(_) => panic!("loaded a broken S3M"),
+        Err(e) => e,
+    };
+    let song = TestS3m::new(1, vec![vec![]]);
+    let mut data = song.encode();
+    data[0x2c] = b'X';
+    assert_eq!(err(data).kind(), io::ErrorKind::InvalidData);
+    let mut missing = TestS3m::new(1, vec![vec![]]);
+    missing.orders = vec![0, 3];
+    assert!(err(missing.encode()).to_string().contains("pattern 3"));
+    let no_channels = TestS3m::new(0, vec![vec![]]);
+    assert_eq!(err(no_channels.encode()).kind(), io::ErrorKind::InvalidData);
+    let mut truncated = song.encode();
+    truncated.truncate(0x50);
+    assert_eq!(err(truncated).kind(), io::ErrorKind::InvalidData);
+    let mut long = TestS3m::new(1, vec![vec![]]);
+    long.orders = vec![0; 0x100];
+    long.load();
+    long.orders.push(0);
+    assert!(err(long.encode()).to_string().contains("256 orders"));
+}
+
+#[test]
+fn truncated_samples_are_cut_short() {
+    let mut song = TestS3m::new(1, vec![vec![]]);
+    song.samples[0].frames = u32::MAX;
+    song.samples[0].flags = 6;
+    let data = song.encode();
+    let size = data.len();
+    let (module, warnings) = s3m::load(&mut Cursor::new(data)).unwrap();
+    assert!(module.samples[1].data.len() < size);
+    assert!(warnings.contains(&Warning::TruncatedSample(1)));
+}
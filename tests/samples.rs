@@ -0,0 +1,229 @@
+mod common;
+
+use std::io::{self, Cursor};
+
+use common::{note, pattern, TestMod, TestSample};
+use pfr::sound::{
+    loader,
+    player::{self, PlayerConfig},
+    sfx::SfxBank,
+    wav, Sample, SampleData,
+};
+
+fn song() -> TestMod {
+    let mut song = TestMod::new(vec![pattern(&[
+        (0, 0, note(12, 1, 0x000)),
+        (4, 1, note(20, 2, 0xc20)),
+        (8, 2, note(17, 3, 0x000)),
+    ])]);
+    song.samples.push(TestSample::saw());
+    song
+}
+
+fn export(sample: &Sample, rate: u32) -> Vec<u8> {
+    let mut out = vec![];
+    wav::export(&mut out, sample, rate).unwrap();
+    out
+}
+
+#[test]
+fn export_import_round_trip() {
+    let module = song().load();
+    for sample in &module.samples[1..4] {
+        let wav = export(sample, wav::AMIGA_C2_RATE);
+        let back = wav::import(&mut Cursor::new(wav), "back", wav::AMIGA_C2_RATE).unwrap();
+        assert_eq!(back.data, sample.data);
+        assert_eq!(back.repeat, sample.repeat);
+    }
+}
+
+#[test]
+fn import_resamples() {
+    let module = song().load();
+    let pluck = &module.samples[2];
+    let wav = export(pluck, 16000);
+    let half = wav::import(&mut Cursor::new(&wav), "half", 8000).unwrap();
+    assert_eq!(half.data.len(), pluck.data.len() / 2);
+    for i in (0..half.data.len()).step_by(7) {
+        let x = half.data.frame(i);
+        let (a, b) = (pluck.data.frame(i * 2), pluck.data.frame(i * 2 + 1));
+        assert!(x.abs_diff(a) <= (b.abs_diff(a)));
+    }
+    let saw = export(&module.samples[1], 8000);
+    let doubled = wav::import(&mut Cursor::new(&saw), "saw", 16000).unwrap();
+    assert_eq!(doubled.data.len(), 64);
+    assert_eq!(doubled.repeat, Some((0, 64)));
+
+    let err = wav::import(&mut Cursor::new(&wav), "long", 16000 * 64).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn replace_sample_keeps_the_rest() {
+    let song = song();
+    let file = song.encode();
+    let new = Sample {
+        name: "replaced".into(),
+        data: SampleData::Bits8((0..100).map(|i| (i * 3) as u8).collect()),
+        finetune: 0xd,
+        volume: 0x20,
+        repeat: Some((10, 50)),
+        c2spd: None,
+        tuning: 0,
+        panning: None,
+    };
+    let file = loader::replace_sample(&file, 2, &new).unwrap();
+    let module = loader::load(&mut Cursor::new(file)).unwrap();
+    let old = song.load();
+    let sample = &module.samples[2];
+    assert_eq!(sample.name, "replaced");
+    assert_eq!(sample.data, new.data);
+    assert_eq!((sample.finetune, sample.volume), (0xd, 0x20));
+    assert_eq!(sample.repeat, Some((10, 50)));
+    for i in [1, 3] {
+        assert_eq!(module.samples[i].data, old.samples[i].data);
+        assert_eq!(module.samples[i].repeat, old.samples[i].repeat);
+    }
+    assert_eq!(module.positions, old.positions);
+    assert_eq!(
+        format!("{:?}", module.patterns),
+        format!("{:?}", old.patterns)
+    );
+
+    let odd = Sample {
+        data: SampleData::Bits8(vec![0; 3]),
+        ..new
+    };
+    assert!(loader::replace_sample(&song.encode(), 1, &odd).is_err());
+    assert!(loader::replace_sample(&song.encode(), 32, &odd).is_err());
+}
+
+#[test]
+fn load_keeps_depth_and_channels() {
+    let ramp = |i: i32| (i * 0x1234) as i16;
+    for data in [
+        SampleData::Bits8((0..40).map(|i| i * 5).collect()),
+        SampleData::Bits16((0..40).map(ramp).collect()),
+        SampleData::Stereo8((0..40).map(|i| [i * 5, 0xff - i]).collect()),
+        SampleData::Stereo16((0..40).map(|i| [ramp(i), -ramp(i)]).collect()),
+    ] {
+        let sample = Sample {
+            data,
+            ..song().load().samples[1].clone()
+        };
+        let wav = export(&sample, 22050);
+        let back = wav::load(&mut Cursor::new(&wav), "back").unwrap();
+        assert_eq!(back.data, sample.data);
+        // a MOD only takes 8-bit mono
+        assert_eq!(
+            loader::replace_sample(&song().encode(), 1, &sample).is_ok(),
+            !sample.data.is_16_bit() && !sample.data.is_stereo()
+        );
+        let mono = wav::import(&mut Cursor::new(&wav), "mono", 22050).unwrap();
+        assert_eq!(mono.data.len(), 40);
+        assert_eq!(mono.data.frame(7) >> 8, sample.data.frame(7) >> 8);
+    }
+}
+
+#[test]
+fn stereo_samples_keep_their_sides() {
+    let render = |data: SampleData| {
+        let mut module = song().load();
+        module.samples[1].data = data;
+        module.samples[1].repeat = None;
+        module.panning[0] = 0x40;
+        let (_player, mut state) =
+            player::play_offline(module, SfxBank::new(), PlayerConfig::default());
+        let mut buf = vec![0; state.sample_rate() as usize / 50 * 2];
+        state.render(&mut buf);
+        buf
+    };
+    let stereo = render(SampleData::Stereo16(vec![[0x4000, 0]; 1000]));
+    let mono = render(SampleData::Bits16(vec![0x4000; 1000]));
+    // the left side of the stereo sample goes left only, as loud as a mono
+    // sample puts there
+    for (s, m) in stereo.chunks(2).zip(mono.chunks(2)) {
+        assert_eq!((s[0], s[1]), (m[0], 0));
+    }
+    assert!(mono[0] != 0 && mono[0] == mono[1]);
+    // and mono 16-bit data plays as the 8-bit data it extends
+    assert_eq!(render(SampleData::Bits8(vec![0x40; 1000])), mono);
+}
+
+#[test]
+fn sfx_bank_reports_running_full() {
+    let module = song().load();
+    let mut bank = SfxBank::new();
+    for i in 1..0x100 {
+        assert_eq!(bank.add(module.samples[1].clone()).unwrap(), i as u8);
+    }
+    assert!(bank.add(module.samples[1].clone()).is_err());
+    assert!(bank.add_mod(&module).is_err());
+    assert_eq!(bank.samples.len(), 0x100);
+}
+
+#[test]
+fn truncated_wav_chunks_are_errors() {
+    let mut wav = export(&song().load().samples[2], 8000);
+    // a data chunk claiming 4 GiB
+    let data = wav.windows(4).position(|w| w == b"data").unwrap();
+    wav[data + 4..data + 8].copy_from_slice(&u32::MAX.to_le_bytes());
+    let err = wav::load(&mut Cursor::new(&wav), "long").unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+}
+
+#[test]
+fn rates_of_0_are_errors() {
+    let sample = &song().load().samples[1];
+    assert!(sample.repeat.is_some());
+    let err = wav::export(&mut vec![], sample, 0).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    let mut wav = export(sample, 8000);
+    let err = wav::import(&mut Cursor::new(&wav), "none", 0).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    wav[24..28].copy_from_slice(&0u32.to_le_bytes());
+    let err = wav::import(&mut Cursor::new(&wav), "stopped", 8000).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn loaded_samples_play_at_their_rate() {
+    let sample = Sample {
+        data: SampleData::Bits16(vec![0x1000; 4000]),
+        repeat: Some((1000, 2000)),
+        ..song().load().samples[1].clone()
+    };
+    // how far C-2 gets into the sample in one tick
+    let position = |rate: u32| {
+        let mut bank = SfxBank::new();
+        let wav = export(&sample, rate);
+        let index = bank.load_wav(&mut Cursor::new(&wav), "sfx").unwrap();
+        assert_eq!(bank.samples[index as usize].repeat, Some((1000, 2000)));
+        let (player, mut state) =
+            player::play_offline(song().load(), bank, PlayerConfig::default());
+        player.play_sfx(12, index, 0, 3).unwrap();
+        state.step_tick();
+        state.step_tick();
+        state.channels()[3].position
+    };
+    // a tick is 1/50s
+    for rate in [11025, 22050, 44100] {
+        let expected = rate as usize / 50;
+        assert!(position(rate).abs_diff(expected) <= expected / 100);
+    }
+    let wav = export(&sample, 96000);
+    let fast = wav::load(&mut Cursor::new(&wav), "fast").unwrap();
+    assert_eq!(fast.c2spd, Some(0xffff));
+}
+
+#[test]
+fn empty_loops_are_left_out() {
+    let sample = Sample {
+        repeat: Some((4, 0)),
+        ..song().load().samples[1].clone()
+    };
+    let wav = export(&sample, 8000);
+    assert!(!wav.windows(4).any(|w| w == b"smpl"));
+    let back = wav::load(&mut Cursor::new(&wav), "back").unwrap();
+    assert_eq!(back.repeat, None);
+}
@@ -0,0 +1,140 @@
+mod common;
+
+use std::time::Duration;
+
+use common::{effect, note, pattern, render_ticks, tick_len, TestMod};
+use pfr::sound::{
+    player::{self, CommandError, PlayerConfig},
+    sfx::SfxBank,
+    Mod,
+};
+
+fn song() -> TestMod {
+    TestMod::new(vec![
+        pattern(&[
+            (0, 0, note(12, 1, 0xf03)),
+            (0, 1, note(20, 2, 0x486)),
+            (1, 0, effect(0x102)),
+            (2, 0, effect(0xa01)),
+            (3, 2, note(24, 1, 0xc20)),
+            (4, 2, note(17, 0, 0x304)),
+            (5, 2, effect(0x300)),
+            (6, 3, note(5, 2, 0x937)),
+            (7, 1, effect(0x400)),
+            (9, 3, effect(0xe92)),
+            (12, 0, effect(0xd08)),
+        ]),
+        pattern(&[
+            (8, 0, note(30, 1, 0x047)),
+            (9, 0, effect(0x047)),
+            (10, 2, effect(0xa20)),
+            (14, 1, note(7, 2, 0xf02)),
+            (20, 3, effect(0xb00)),
+        ]),
+    ])
+}
+
+fn straight(module: Mod, ticks: usize) -> Vec<i32> {
+    let (_player, mut state) =
+        player::play_offline(module, SfxBank::new(), PlayerConfig::default());
+    render_ticks(&mut state, ticks)
+}
+
+#[test]
+fn seek_to_row() {
+    let module = song();
+    let timing = module.load().timing();
+    let reference = straight(module.load(), timing.length as usize + 60);
+    for (position, row) in [(0, 0), (0, 3), (0, 10), (1, 8), (1, 9), (1, 15), (1, 20)] {
+        let (player, mut state) =
+            player::play_offline(module.load(), SfxBank::new(), PlayerConfig::default());
+        // play a bit of something else first
+        render_ticks(&mut state, 7);
+        player.seek(position, row).unwrap();
+        let tick = timing.row_starts[position as usize][row as usize].unwrap() as usize;
+        let at = tick * tick_len(&state);
+        let ticks = timing.length as usize + 60 - tick;
+        assert!(
+            render_ticks(&mut state, ticks) == reference[at..],
+            "seek to {position:02x}.{row:02x} differs from straight playback"
+        );
+    }
+}
+
+#[test]
+fn seek_to_time() {
+    let module = song();
+    let reference = straight(module.load(), 300);
+    let (player, mut state) =
+        player::play_offline(module.load(), SfxBank::new(), PlayerConfig::default());
+    player.seek_time(Duration::from_millis(2345)).unwrap();
+    assert!(render_ticks(&mut state, 183) == reference[117 * tick_len(&state)..]);
+}
+
+#[test]
+fn seek_errors() {
+    let (player, _state) =
+        player::play_offline(song().load(), SfxBank::new(), PlayerConfig::default());
+    assert_eq!(player.seek(2, 0), Err(CommandError::PositionOutOfRange(2)));
+    assert_eq!(player.seek(0, 0x40), Err(CommandError::RowOutOfRange(0x40)));
+    assert_eq!(
+        player.seek(0, 13),
+        Err(CommandError::RowUnreachable {
+            position: 0,
+            row: 13
+        })
+    );
+}
+
+#[test]
+fn commands_fail_once_the_audio_thread_is_gone() {
+    let (player, state) =
+        player::play_offline(song().load(), SfxBank::new(), PlayerConfig::default());
+    drop(state);
+    assert_eq!(player.seek(0, 0), Err(CommandError::Disconnected));
+}
+
+#[test]
+fn long_seeks_go_on_over_silent_ticks() {
+    // 31 ticks a row
+    let module = TestMod::new(vec![pattern(&[
+        (0, 0, note(12, 1, 0xf1f)),
+        (40, 1, note(20, 2, 0x000)),
+        (63, 2, note(24, 1, 0x000)),
+    ])]);
+    let reference = straight(module.load(), 64 * 31);
+    let (player, mut state) =
+        player::play_offline(module.load(), SfxBank::new(), PlayerConfig::default());
+    render_ticks(&mut state, 5);
+    player.seek(0, 63).unwrap();
+    // 0x200 ticks at once, then 0x200 a tick
+    let silent = render_ticks(&mut state, 3);
+    assert!(silent.iter().all(|&v| v == 0));
+    let tick_len = tick_len(&state);
+    assert!(render_ticks(&mut state, 31) == reference[63 * 31 * tick_len..]);
+
+    // past the end, the seek lands where the song has looped around to
+    let (player, mut state) =
+        player::play_offline(module.load(), SfxBank::new(), PlayerConfig::default());
+    player
+        .seek_time(Duration::from_secs(3600) + Duration::from_millis(40 * 31 * 20))
+        .unwrap();
+    let tick = (3600 * 50 + 40 * 31) % (64 * 31);
+    let rendered = render_ticks(&mut state, tick / 0x200 + 64 * 31 - tick);
+    assert!(rendered[tick / 0x200 * tick_len..] == reference[tick * tick_len..]);
+    player.seek_time(Duration::MAX).unwrap();
+    render_ticks(&mut state, 10);
+}
+
+#[test]
+fn stepping_finishes_long_seeks_at_once() {
+    let module = TestMod::new(vec![pattern(&[
+        (0, 0, note(12, 1, 0xf1f)),
+        (63, 2, note(24, 1, 0x000)),
+    ])]);
+    let (player, mut state) =
+        player::play_offline(module.load(), SfxBank::new(), PlayerConfig::default());
+    player.seek(0, 62).unwrap();
+    state.step_row();
+    assert_eq!(player.status(), (0, 62));
+}
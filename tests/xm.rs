@@ -0,0 +1,445 @@
+mod common;
+
+use std::io::{self, Cursor};
+
+use common::render_ticks;
+use pfr::sound::{
+    player::{self, CommandError, PlayerConfig},
+    sfx::SfxBank,
+    xm::{self, Warning},
+    MiscEffect, Mod, SampleData, ToneEffect, VolumeEffect,
+};
+
+/// A cell of an XM pattern: note, instrument, volume, effect and argument,
+/// as stored.
+type Cell = [u8; 5];
+
+/// Row count and the cells of a pattern, by row and channel.
+type Pattern = (u16, Vec<(usize, usize, Cell)>);
+
+fn note(note: u8, instrument: u8) -> Cell {
+    [note, instrument, 0, 0, 0]
+}
+
+fn fx(effect: u8, arg: u8) -> Cell {
+    [0, 0, 0, effect, arg]
+}
+
+struct TestSample {
+    data: SampleData,
+    repeat: (u32, u32),
+    loop_type: u8,
+    volume: u8,
+    finetune: i8,
+    panning: u8,
+    relative_note: i8,
+}
+
+impl TestSample {
+    /// A looped 8-bit saw.
+    fn saw() -> Self {
+        TestSample {
+            data: SampleData::Bits8((0..32).map(|i| (i * 8) as u8 ^ 0x80).collect()),
+            repeat: (0, 32),
+            loop_type: 1,
+            volume: 0x40,
+            finetune: 0,
+            panning: 0x80,
+            relative_note: 0,
+        }
+    }
+
+    /// A long unlooped 16-bit sample at one level.
+    fn flat16(level: i16) -> Self {
+        TestSample {
+            data: SampleData::Bits16(vec![level; 2000]),
+            repeat: (0, 0),
+            loop_type: 0,
+            ..TestSample::saw()
+        }
+    }
+}
+
+#[derive(Default)]
+struct TestInstrument {
+    samples: Vec<TestSample>,
+    keymap: Vec<(usize, u8)>,
+    envelope: bool,
+}
+
+struct TestXm {
+    channels: u16,
+    linear: bool,
+    speed: u16,
+    tempo: u16,
+    orders: Vec<u8>,
+    instruments: Vec<TestInstrument>,
+    patterns: Vec<Pattern>,
+}
+
+impl TestXm {
+    /// A song with linear periods and the saw as instrument 1.
+    fn new(channels: u16, patterns: Vec<Pattern>) -> Self {
+        TestXm {
+            channels,
+            linear: true,
+            speed: 6,
+            tempo: 125,
+            orders: (0..patterns.len() as u8).collect(),
+            instruments: vec![TestInstrument {
+                samples: vec![TestSample::saw()],
+                ..Default::default()
+            }],
+            patterns,
+        }
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut out = b"Extended Module: test".to_vec();
+        out.resize(37, 0);
+        out.push(0x1a);
+        out.extend_from_slice(b"FastTracker v2.00   ");
+        out.extend_from_slice(&0x0104u16.to_le_bytes());
+        out.extend_from_slice(&276u32.to_le_bytes());
+        for field in [
+            self.orders.len() as u16,
+            0,
+            self.channels,
+            self.patterns.len() as u16,
+            self.instruments.len() as u16,
+            self.linear as u16,
+            self.speed,
+            self.tempo,
+        ] {
+            out.extend_from_slice(&field.to_le_bytes());
+        }
+        let mut orders = self.orders.clone();
+        orders.resize(256, 0);
+        out.extend_from_slice(&orders);
+        for (rows, cells) in &self.patterns {
+            let mut data = vec![];
+            for row in 0..*rows as usize {
+                for channel in 0..self.channels as usize {
+                    let cell = cells
+                        .iter()
+                        .find(|c| (c.0, c.1) == (row, channel))
+                        .map_or([0; 5], |c| c.2);
+                    if cell.iter().all(|&b| b != 0) {
+                        data.extend_from_slice(&cell);
+                    } else {
+                        let mask = (0..5)
+                            .filter(|&i| cell[i] != 0)
+                            .map(|i| 1u8 << i)
+                            .sum::<u8>();
+                        data.push(0x80 | mask);
+                        data.extend(cell.iter().filter(|&&b| b != 0));
+                    }
+                }
+            }
+            out.extend_from_slice(&9u32.to_le_bytes());
+            out.push(0);
+            out.extend_from_slice(&rows.to_le_bytes());
+            out.extend_from_slice(&(data.len() as u16).to_le_bytes());
+            out.extend_from_slice(&data);
+        }
+        for instrument in &self.instruments {
+            let mut hdr = vec![0; 29];
+            hdr[4..8].copy_from_slice(b"inst");
+            hdr[27..29].copy_from_slice(&(instrument.samples.len() as u16).to_le_bytes());
+            if !instrument.samples.is_empty() {
+                hdr.resize(243, 0);
+                hdr[29..33].copy_from_slice(&40u32.to_le_bytes());
+                for &(note, sample) in &instrument.keymap {
+                    hdr[33 + note] = sample;
+                }
+                hdr[233] = instrument.envelope as u8;
+            }
+            let len = hdr.len() as u32;
+            hdr[..4].copy_from_slice(&len.to_le_bytes());
+            out.extend_from_slice(&hdr);
+            let mut data = vec![];
+            for sample in &instrument.samples {
+                let (width, flags) = match sample.data {
+                    SampleData::Bits8(_) => (1, 0),
+                    _ => (2, 0x10),
+                };
+                let mut hdr = vec![0; 40];
+                hdr[..4].copy_from_slice(&(sample.data.len() as u32 * width).to_le_bytes());
+                hdr[4..8].copy_from_slice(&(sample.repeat.0 * width).to_le_bytes());
+                hdr[8..12].copy_from_slice(&(sample.repeat.1 * width).to_le_bytes());
+                hdr[12] = sample.volume;
+                hdr[13] = sample.finetune as u8;
+                hdr[14] = sample.loop_type | flags;
+                hdr[15] = sample.panning;
+                hdr[16] = sample.relative_note as u8;
+                hdr[18..22].copy_from_slice(b"smp!");
+                out.extend_from_slice(&hdr);
+                match &sample.data {
+                    SampleData::Bits8(frames) => {
+                        let mut last = 0u8;
+                        for &b in frames {
+                            data.push(b.wrapping_sub(last));
+                            last = b;
+                        }
+                    }
+                    SampleData::Bits16(frames) => {
+                        let mut last = 0i16;
+                        for &v in frames {
+                            data.extend_from_slice(&v.wrapping_sub(last).to_le_bytes());
+                            last = v;
+                        }
+                    }
+                    _ => unreachable!("XM samples are mono"),
+                }
+            }
+            out.extend_from_slice(&data);
+        }
+        out
+    }
+
+    fn load(&self) -> (Mod, Vec<Warning>) {
+        xm::load(&mut Cursor::new(self.encode())).unwrap()
+    }
+}
+
+#[test]
+fn converts_patterns_instruments_and_effects() {
+    let mut song = TestXm::new(
+        3,
+        vec![
+            (
+                0x40,
+                vec![
+                    // all five fields, stored unpacked
+                    (0, 0, [49, 1, 0x30, 0xa, 0x20]),
+                    (0, 1, [61, 2, 0, 8, 0xff]),
+                    (0, 2, [73, 2, 0x65, 0xa, 1]),
+                    (1, 0, fx(0xa, 0)),
+                    (1, 1, [97, 0, 0xc0, 0, 0]),
+                    (2, 0, [0, 0, 0x92, 9, 0x10]),
+                    (2, 1, fx(0xf, 0x80)),
+                    (3, 0, fx(0xb, 7)),
+                    (3, 1, fx(0x1b, 0x13)),
+                ],
+            ),
+            (0x10, vec![(0, 0, fx(0xf, 3))]),
+        ],
+    );
+    song.speed = 4;
+    song.tempo = 150;
+    let mut data = vec![0i16, 0x7fff, -0x8000, 0x1234];
+    data.resize(8, 0);
+    song.instruments.push(TestInstrument {
+        samples: vec![
+            TestSample {
+                data: SampleData::Bits16(data),
+                repeat: (2, 4),
+                loop_type: 2,
+                volume: 0x50,
+                finetune: -16,
+                panning: 0,
+                relative_note: 12,
+            },
+            TestSample::saw(),
+        ],
+        keymap: vec![(72, 1)],
+        envelope: true,
+    });
+    let (module, warnings) = song.load();
+
+    assert_eq!(module.name, "test");
+    assert_eq!(module.channels(), 3);
+    assert_eq!(module.panning, [0x40; 3]);
+    assert!(module.linear_periods);
+    assert_eq!(module.speed, 4);
+    assert_eq!(module.positions, [0, 1]);
+    assert_eq!(module.samples.len(), 4);
+    let saw = &module.samples[1];
+    assert_eq!(saw.name, "smp!");
+    assert_eq!(saw.repeat, Some((0, 32)));
+    assert_eq!(saw.data.frame(0), i16::MIN);
+    assert_eq!(
+        (saw.tuning, saw.panning, saw.c2spd),
+        (0, Some(0x40), Some(8363))
+    );
+    let wide = &module.samples[2];
+    assert_eq!(wide.data.frame(1), 0x7fff);
+    assert_eq!(wide.data.frame(2), -0x8000);
+    assert_eq!(wide.data.frame(3), 0x1234);
+    assert_eq!(wide.repeat, Some((2, 4)));
+    assert_eq!(wide.volume, 0x40);
+    assert_eq!((wide.tuning, wide.panning), (12 * 128 - 16, Some(0)));
+
+    let cells = &module.patterns[0];
+    assert_eq!(cells.len(), 0x40);
+    assert_eq!(module.patterns[1].len(), 0x10);
+    assert_eq!(cells[0][0].period, Some(48));
+    assert_eq!(cells[0][0].sample, Some(1));
+    assert_eq!(cells[0][0].volume, Some(0x20));
+    assert!(matches!(
+        cells[0][0].volume_effect,
+        VolumeEffect::VolumeSlide(2)
+    ));
+    // the instrument maps C-5 to its first sample and C-6 to its second
+    assert_eq!(cells[0][1].sample, Some(2));
+    assert_eq!(cells[0][2].sample, Some(3));
+    assert!(matches!(
+        cells[0][1].misc_effect,
+        MiscEffect::SetPanning(0x80)
+    ));
+    // the effect column slides, so the volume column one is left out
+    assert!(matches!(
+        cells[0][2].volume_effect,
+        VolumeEffect::VolumeSlide(-1)
+    ));
+    // A00 slides as the last A of the channel did
+    assert!(matches!(
+        cells[1][0].volume_effect,
+        VolumeEffect::VolumeSlide(2)
+    ));
+    let off = cells[1][1];
+    assert_eq!((off.period, off.volume), (None, Some(0)));
+    assert!(matches!(off.misc_effect, MiscEffect::SetPanning(0)));
+    assert!(matches!(
+        cells[2][0].volume_effect,
+        VolumeEffect::FineVolumeSlide(2)
+    ));
+    // out of the song
+    assert!(matches!(
+        cells[3][0].misc_effect,
+        MiscEffect::PositionJump(0)
+    ));
+    assert!(matches!(cells[3][1].misc_effect, MiscEffect::RetrigNote(3)));
+    assert!(matches!(
+        module.patterns[1][0][0].misc_effect,
+        MiscEffect::SetSpeed(3)
+    ));
+    assert!(matches!(cells[0][0].tone_effect, ToneEffect::None));
+
+    assert_eq!(
+        warnings,
+        [
+            Warning::Tempo(150),
+            Warning::Envelope(2),
+            Warning::MultiSample(2),
+            Warning::PingPongLoop(2),
+            Warning::UnsupportedEffect {
+                effect: "F (tempo)".into(),
+                count: 1
+            },
+            Warning::UnsupportedEffect {
+                effect: "volume -".into(),
+                count: 1
+            },
+            Warning::ApproximatedEffect {
+                effect: "R".into(),
+                count: 1
+            },
+        ]
+    );
+}
+
+#[test]
+fn plays_16_bit_samples() {
+    let song = |sample: TestSample| {
+        let mut song = TestXm::new(1, vec![(0x40, vec![(0, 0, note(49, 1))])]);
+        song.instruments[0].samples = vec![sample];
+        let (_player, mut state) =
+            player::play_offline(song.load().0, SfxBank::new(), PlayerConfig::default());
+        render_ticks(&mut state, 5)
+    };
+    let coarse = song(TestSample {
+        data: SampleData::Bits8(vec![0x12; 2000]),
+        ..TestSample::flat16(0)
+    });
+    assert!(coarse.iter().any(|&v| v != 0));
+    assert_eq!(song(TestSample::flat16(0x1200)), coarse);
+    let fine = song(TestSample::flat16(0x1234));
+    assert_ne!(fine, coarse);
+}
+
+#[test]
+fn plays_linear_periods() {
+    let song = |linear: bool, relative_note: i8| {
+        let mut song = TestXm::new(1, vec![(0x40, vec![(0, 0, note(49, 1))])]);
+        song.linear = linear;
+        song.instruments[0].samples = vec![TestSample {
+            relative_note,
+            ..TestSample::flat16(0x100)
+        }];
+        let (_player, mut state) =
+            player::play_offline(song.load().0, SfxBank::new(), PlayerConfig::default());
+        render_ticks(&mut state, 1);
+        state.channels()[0]
+    };
+    // C-4 plays the sample at 8363Hz, 167.26 frames a tick
+    let c4 = song(true, 0);
+    assert_eq!((c4.period, c4.position), (4608, 167));
+    let c5 = song(true, 12);
+    assert_eq!((c5.period, c5.position), (3840, 334));
+    // and about as fast with Amiga periods
+    let amiga = song(false, 0);
+    assert_eq!(amiga.period, 424);
+    assert!(amiga.position.abs_diff(167) <= 2);
+}
+
+#[test]
+fn plays_patterns_of_any_length() {
+    let mut song = TestXm::new(
+        1,
+        vec![
+            (0x20, vec![(0, 0, note(49, 1))]),
+            (0x50, vec![(0x4f, 0, fx(0xd, 0x60))]),
+        ],
+    );
+    song.speed = 1;
+    let module = song.load().0;
+    let timing = module.timing();
+    assert_eq!(timing.row_starts[0].len(), 0x20);
+    assert_eq!(timing.row_starts[1].len(), 0x50);
+    assert_eq!(timing.row_starts[1][0x48], Some(0x68));
+    let (player, mut state) = player::play_offline(module, SfxBank::new(), PlayerConfig::default());
+    render_ticks(&mut state, 0x21);
+    assert_eq!(state.status(), (1, 0));
+    player.seek(1, 0x48).unwrap();
+    assert_eq!(player.seek(0, 0x20), Err(CommandError::RowOutOfRange(0x20)));
+    render_ticks(&mut state, 1);
+    assert_eq!(state.status(), (1, 0x48));
+    // the break past the end of the first pattern lands on its last row
+    render_ticks(&mut state, 8);
+    assert_eq!(state.status(), (0, 0x1f));
+}
+
+#[test]
+fn rejects_what_does_not_convert() {
+    let err = |data: Vec<u8>| match xm::load(&mut Cursor::new(data)) {
+        Ok(_) => panic!("loaded a broken XM"),
+        Err(e) => e,
+    };
+    let song = TestXm::new(1, vec![(0x40, vec![])]);
+    let mut data = song.encode();
+    data[0] = b'X';
+    assert_eq!(err(data).kind(), io::ErrorKind::InvalidData);
+    let mut missing = TestXm::new(1, vec![(0x40, vec![])]);
+    missing.orders = vec![0, 3];
+    assert!(err(missing.encode()).to_string().contains("pattern 3"));
+    let too_wide = TestXm::new(33, vec![(0x40, vec![])]);
+    assert!(err(too_wide.encode()).to_string().contains("33 channels"));
+    let mut adpcm = song.encode();
+    let at = adpcm.len() - 32 - 40 + 17;
+    adpcm[at] = 0xad;
+    assert!(err(adpcm).to_string().contains("ADPCM"));
+    let mut truncated = song.encode();
+    truncated.truncate(0x60);
+    assert_eq!(err(truncated).kind(), io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn truncated_samples_are_cut_short() {
+    let mut data = TestXm::new(1, vec![(0x40, vec![])]).encode();
+    let at = data.len() - 32 - 40;
+    data[at..at + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+    let (module, warnings) = xm::load(&mut Cursor::new(data)).unwrap();
+    assert_eq!(module.samples[1].data.len(), 32);
+    assert!(warnings.contains(&Warning::TruncatedSample(1)));
+}